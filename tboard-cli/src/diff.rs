@@ -0,0 +1,40 @@
+use anyhow::Result;
+use tboard::diff::DiffOptions;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// An event file or a run directory.
+    a: std::path::PathBuf,
+
+    /// An event file or a run directory.
+    b: std::path::PathBuf,
+
+    /// The absolute tolerance used to detect divergences.
+    #[arg(long, default_value_t = 1e-8)]
+    atol: f64,
+
+    /// The relative tolerance used to detect divergences.
+    #[arg(long, default_value_t = 1e-5)]
+    rtol: f64,
+
+    /// Only compare the tags matching this regex.
+    #[arg(long)]
+    tags: Option<regex::Regex>,
+
+    /// Print the comparison as JSON rather than as a table.
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let a = tboard::diff::load_run(&args.a)?;
+    let b = tboard::diff::load_run(&args.b)?;
+    let opts = DiffOptions { atol: args.atol, rtol: args.rtol, tag_filter: args.tags };
+    let diff = tboard::diff::diff(&a, &b, &opts);
+    if args.json {
+        println!("{}", diff.to_json())
+    } else {
+        diff.write_table(std::io::stdout().lock())?
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use tboard::export::{ExportOptions, Format, Layout, NonFinite};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum FormatArg {
+    Csv,
+    Jsonl,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum NonFiniteArg {
+    Keep,
+    Null,
+    Skip,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// An event file or a log directory.
+    path: std::path::PathBuf,
+
+    #[arg(long, value_enum, default_value_t = FormatArg::Csv)]
+    format: FormatArg,
+
+    /// Use one column per tag rather than one row per value.
+    #[arg(long)]
+    wide: bool,
+
+    /// Only export the tags matching this regex.
+    #[arg(long)]
+    tags: Option<regex::Regex>,
+
+    /// How to write the NaN and infinite values.
+    #[arg(long, value_enum, default_value_t = NonFiniteArg::Keep)]
+    non_finite: NonFiniteArg,
+
+    /// Only export the scalars.
+    #[arg(long)]
+    no_tensors: bool,
+
+    /// The output file, defaults to stdout.
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let opts = ExportOptions {
+        format: match args.format {
+            FormatArg::Csv => Format::Csv,
+            FormatArg::Jsonl => Format::JsonLines,
+        },
+        layout: if args.wide { Layout::Wide } else { Layout::Long },
+        tag_filter: args.tags,
+        non_finite: match args.non_finite {
+            NonFiniteArg::Keep => NonFinite::Keep,
+            NonFiniteArg::Null => NonFinite::Null,
+            NonFiniteArg::Skip => NonFinite::Skip,
+        },
+        tensors: !args.no_tensors,
+    };
+    let w: Box<dyn std::io::Write> = match &args.output {
+        None => Box::new(std::io::stdout().lock()),
+        Some(output) => Box::new(std::fs::File::create(output)?),
+    };
+    if args.path.is_dir() {
+        tboard::export::export_logdir(&args.path, w, &opts)?
+    } else {
+        let reader = tboard::SummaryReader::open(&args.path)?;
+        tboard::export::export_reader(".", reader, w, &opts)?
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use tboard::export::Layout;
+use tboard::import::{Format, ImportOptions, TimeUnit};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum FormatArg {
+    Csv,
+    Json,
+    Jsonl,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum TimeUnitArg {
+    S,
+    Ms,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// A CSV, JSON, or JSON Lines file.
+    input: std::path::PathBuf,
+
+    /// The log directory where to write the event files.
+    logdir: std::path::PathBuf,
+
+    /// The input format, guessed from the file extension by default.
+    #[arg(long, value_enum)]
+    format: Option<FormatArg>,
+
+    /// The input has one column per tag rather than one row per value.
+    #[arg(long)]
+    wide: bool,
+
+    #[arg(long, default_value = "step")]
+    step_column: String,
+
+    #[arg(long, default_value = "wall_time")]
+    wall_time_column: String,
+
+    #[arg(long, value_enum, default_value_t = TimeUnitArg::S)]
+    wall_time_unit: TimeUnitArg,
+
+    #[arg(long, default_value = "tag")]
+    tag_column: String,
+
+    #[arg(long, default_value = "value")]
+    value_column: String,
+
+    #[arg(long, default_value = "run")]
+    run_column: String,
+
+    /// The run for the records without a run column, relative to the log directory.
+    #[arg(long, default_value = ".")]
+    run: String,
+
+    /// Only import the tags matching this regex.
+    #[arg(long)]
+    tags: Option<regex::Regex>,
+
+    /// Also import the columns starting with an underscore in the wide layout, e.g. the
+    /// W&B _runtime column.
+    #[arg(long)]
+    underscore_columns: bool,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let format = match args.format {
+        Some(FormatArg::Csv) => Format::Csv,
+        Some(FormatArg::Json) => Format::Json,
+        Some(FormatArg::Jsonl) => Format::JsonLines,
+        None => match Format::from_path(&args.input) {
+            Some(format) => format,
+            None => anyhow::bail!("cannot guess the format of {:?}, use --format", args.input),
+        },
+    };
+    let opts = ImportOptions {
+        format,
+        layout: if args.wide { Layout::Wide } else { Layout::Long },
+        step_column: args.step_column,
+        wall_time_column: args.wall_time_column,
+        wall_time_unit: match args.wall_time_unit {
+            TimeUnitArg::S => TimeUnit::Seconds,
+            TimeUnitArg::Ms => TimeUnit::Milliseconds,
+        },
+        tag_column: args.tag_column,
+        value_column: args.value_column,
+        run_column: args.run_column,
+        run: args.run,
+        tag_filter: args.tags,
+        underscore_columns: args.underscore_columns,
+    };
+    for filename in tboard::import::import_file(&args.input, &args.logdir, &opts)? {
+        println!("{}", filename.display())
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+mod diff;
+mod export;
+mod import;
+mod plot;
+mod repair;
+#[cfg(feature = "serve")]
+mod serve;
+mod surgery;
+mod validate;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare the scalar series of two runs.
+    Diff(diff::Args),
+    /// Export the scalar and tensor series as CSV or JSON Lines.
+    Export(export::Args),
+    /// Import some scalars from CSV or JSON files as event files.
+    Import(import::Args),
+    /// Filter, rename, crop, or merge event files into a new event file.
+    Rewrite(surgery::RewriteArgs),
+    /// Plot some scalar tags in the terminal.
+    Plot(plot::PlotArgs),
+    /// Copy the valid records of a damaged event file to a new file.
+    Repair(repair::Args),
+    /// Serve a log directory over http with a JSON api and a page plotting the scalars.
+    #[cfg(feature = "serve")]
+    Serve(serve::Args),
+    /// Split an event file in one run per tag prefix.
+    Split(surgery::SplitArgs),
+    /// List the tags of each run.
+    Tags(plot::TagsArgs),
+    /// Check the event files and print a JSON report, exits with 1 on errors.
+    Validate(validate::Args),
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    match args.command {
+        Command::Diff(args) => diff::run(args),
+        Command::Export(args) => export::run(args),
+        Command::Import(args) => import::run(args),
+        Command::Rewrite(args) => surgery::rewrite(args),
+        Command::Plot(args) => plot::plot(args),
+        Command::Repair(args) => repair::run(args),
+        #[cfg(feature = "serve")]
+        Command::Serve(args) => serve::run(args),
+        Command::Split(args) => surgery::split(args),
+        Command::Tags(args) => plot::tags(args),
+        Command::Validate(args) => {
+            let code = validate::run(args)?;
+            std::process::exit(code)
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use tboard::plot::{ChartOptions, Series};
+use tboard::EventAccumulator;
+
+// Loads an event file as the "." run, or all the runs from a log directory. The errors are
+// reported on stderr and the events read before them are kept.
+fn load(path: &std::path::Path) -> Result<Vec<(String, EventAccumulator)>> {
+    if path.is_dir() {
+        let mut runs = vec![];
+        for run in tboard::logdir::load(path)? {
+            for (path, err) in run.errors.iter() {
+                eprintln!("{}: {err}", path.display())
+            }
+            runs.push((run.name, run.accumulator))
+        }
+        Ok(runs)
+    } else {
+        let mut accumulator = EventAccumulator::default();
+        if let Err(err) = accumulator.load(tboard::SummaryReader::open(path)?) {
+            eprintln!("{err}")
+        }
+        Ok(vec![(".".to_string(), accumulator)])
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PlotArgs {
+    /// An event file or a log directory.
+    path: std::path::PathBuf,
+
+    /// The scalar tags to plot, as a regex.
+    #[arg(long)]
+    tags: Option<regex::Regex>,
+
+    /// The weight of the exponential moving average, between 0 and 1.
+    #[arg(long, default_value_t = 0.)]
+    smoothing: f64,
+
+    /// Use a log scale for the values.
+    #[arg(long)]
+    log: bool,
+
+    #[arg(long, default_value_t = 70)]
+    width: usize,
+
+    #[arg(long, default_value_t = 16)]
+    height: usize,
+
+    /// Draw the series with different colors.
+    #[arg(long)]
+    color: bool,
+
+    /// Reload the data and redraw the charts every few seconds.
+    #[arg(long)]
+    follow: bool,
+
+    /// The delay between two redraws in follow mode, in seconds.
+    #[arg(long, default_value_t = 2.)]
+    interval: f64,
+}
+
+// One chart per tag, with a series per run.
+fn render(args: &PlotArgs) -> Result<String> {
+    let runs = load(&args.path)?;
+    let mut tags: Vec<&str> = runs.iter().flat_map(|(_, acc)| acc.scalar_tags()).collect();
+    tags.sort();
+    tags.dedup();
+    let opts = ChartOptions {
+        width: args.width,
+        height: args.height,
+        log_scale: args.log,
+        color: args.color,
+    };
+    let mut out = String::new();
+    for tag in tags.into_iter().filter(|tag| args.tags.as_ref().is_none_or(|re| re.is_match(tag))) {
+        let mut series = vec![];
+        for (run, accumulator) in runs.iter() {
+            let events = match accumulator.scalars(tag) {
+                None => continue,
+                Some(events) => events,
+            };
+            let values: Vec<f64> = events.iter().map(|e| e.value).collect();
+            let values = tboard::plot::smooth(&values, args.smoothing);
+            let points = events.iter().zip(values).map(|(e, v)| (e.step as f64, v)).collect();
+            series.push(Series { label: run.to_string(), points })
+        }
+        out.push_str(&format!("{tag}\n"));
+        out.push_str(&tboard::plot::braille_chart(&series, &opts));
+        out.push('\n')
+    }
+    Ok(out)
+}
+
+pub fn plot(args: PlotArgs) -> Result<()> {
+    if !args.follow {
+        print!("{}", render(&args)?);
+        return Ok(());
+    }
+    loop {
+        let out = render(&args)?;
+        // Clear the screen and move the cursor to the top left corner.
+        print!("\x1b[2J\x1b[H{out}");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        std::thread::sleep(std::time::Duration::from_secs_f64(args.interval))
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TagsArgs {
+    /// An event file or a log directory.
+    path: std::path::PathBuf,
+
+    /// Add a sparkline of the values of each scalar tag.
+    #[arg(long)]
+    sparkline: bool,
+
+    /// The width of the sparklines.
+    #[arg(long, default_value_t = 30)]
+    width: usize,
+}
+
+pub fn tags(args: TagsArgs) -> Result<()> {
+    for (run, accumulator) in load(&args.path)? {
+        println!("{run}");
+        for tag in accumulator.scalar_tags() {
+            let events = accumulator.scalars(tag).unwrap_or_default();
+            let last =
+                events.last().map_or_else(String::new, |e| format!("{:.6} @ {}", e.value, e.step));
+            if args.sparkline {
+                let values: Vec<f64> = events.iter().map(|e| e.value).collect();
+                let sparkline = tboard::plot::sparkline(&values, args.width);
+                println!("  {tag}  {sparkline}  {last}")
+            } else {
+                println!("  {tag}  scalars: {}  last: {last}", events.len())
+            }
+        }
+        let kinds = [
+            ("histograms", accumulator.histogram_tags().collect::<Vec<_>>()),
+            ("images", accumulator.image_tags().collect()),
+            ("audio", accumulator.audio_tags().collect()),
+            ("tensors", accumulator.tensor_tags().collect()),
+        ];
+        for (kind, tags) in kinds {
+            for tag in tags {
+                println!("  {tag}  {kind}")
+            }
+        }
+    }
+    Ok(())
+}
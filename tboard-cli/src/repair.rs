@@ -0,0 +1,53 @@
+use anyhow::Result;
+use tboard::repair::RepairOptions;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The damaged event file.
+    path: std::path::PathBuf,
+
+    /// Where to write the repaired event file.
+    #[arg(short, long, required_unless_present = "in_place", conflicts_with = "in_place")]
+    output: Option<std::path::PathBuf>,
+
+    /// Replace the original file, a backup is kept unless --no-backup is used.
+    #[arg(long)]
+    in_place: bool,
+
+    /// The path of the backup, defaults to the original path with "tfevents" replaced by
+    /// "tfbackup" and a ".bak" extension.
+    #[arg(long, requires = "in_place")]
+    backup: Option<std::path::PathBuf>,
+
+    #[arg(long, requires = "in_place", conflicts_with = "backup")]
+    no_backup: bool,
+
+    /// Skip the corrupted regions rather than dropping everything after the first one.
+    #[arg(long)]
+    resync: bool,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let opts = RepairOptions { resync: args.resync };
+    let report = match &args.output {
+        Some(output) => tboard::repair::repair_file(&args.path, output, &opts)?,
+        None => {
+            let backup = match args.backup {
+                _ if args.no_backup => None,
+                Some(backup) => Some(backup),
+                None => Some(tboard::repair::backup_path(&args.path)),
+            };
+            let report = tboard::repair::repair_in_place(&args.path, backup.as_deref(), &opts)?;
+            if let Some(backup) = backup.filter(|_| !report.is_clean()) {
+                println!("backup: {}", backup.display())
+            }
+            report
+        }
+    };
+    println!("records: {}", report.records);
+    println!("dropped records: {}", report.dropped_records);
+    for (offset, len) in report.skipped.iter() {
+        println!("skipped {len} bytes at offset {offset}")
+    }
+    Ok(())
+}
@@ -0,0 +1,474 @@
+// A small http server for a log directory, with a JSON api and a static page plotting the
+// scalars. The data is read through a `Multiplexer` that is reloaded periodically in a
+// background thread so that the new events are picked up.
+//
+// The api routes all take the run and tag as query parameters:
+//   /api/runs                       the run names
+//   /api/tags                       the tags for each run and kind of data
+//   /api/scalars?run=&tag=          [wall_time, step, value] for each event
+//   /api/histograms?run=&tag=       the histograms with their buckets
+//   /api/images?run=&tag=           the image metadata, the index refers to /api/image
+//   /api/image?run=&tag=&index=     the encoded image
+//   /api/audio?run=&tag=            the audio metadata, the index refers to /api/audio_data
+//   /api/audio_data?run=&tag=&index= the encoded audio
+//
+// The `/data/` routes implement the tensorboard data api, see the `data` module, so that the
+// tensorboard frontend can be served from the directory given via `--assets`.
+use anyhow::Result;
+use std::sync::{Arc, RwLock};
+use tboard::multiplexer::Multiplexer;
+use tboard::EventAccumulator;
+
+mod data;
+
+const INDEX_HTML: &str = include_str!("static/index.html");
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    logdir: std::path::PathBuf,
+
+    /// The address to bind to, only the local interface by default.
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(long, default_value_t = 6006)]
+    port: u16,
+
+    /// The delay between two reloads of the log directory, in seconds.
+    #[arg(long, default_value_t = 5.)]
+    reload_interval: f64,
+
+    /// Serve the static files from this directory rather than the builtin page, e.g. the
+    /// tensorboard frontend.
+    #[arg(long)]
+    assets: Option<std::path::PathBuf>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(value: serde_json::Value) -> Self {
+        let body = value.to_string().into_bytes();
+        Self { status: 200, content_type: "application/json".to_string(), body }
+    }
+
+    pub fn bytes(content_type: &str, body: Vec<u8>) -> Self {
+        Self { status: 200, content_type: content_type.to_string(), body }
+    }
+
+    pub fn error(status: u16, msg: &str) -> Self {
+        let body = msg.as_bytes().to_vec();
+        Self { status, content_type: "text/plain".to_string(), body }
+    }
+}
+
+// Non-finite values are not valid JSON numbers, they are sent as strings, e.g. "NaN".
+pub fn num(v: f64) -> serde_json::Value {
+    serde_json::Number::from_f64(v).map_or_else(|| v.to_string().into(), |v| v.into())
+}
+
+fn percent_decode(s: &str) -> String {
+    let s = s.as_bytes();
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < s.len() => {
+                let hex = std::str::from_utf8(&s[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+pub struct Query(Vec<(String, String)>);
+
+impl Query {
+    // Parses an url query string or an url-encoded form, the keys can be repeated.
+    fn parse(query: &str) -> Self {
+        let query = query
+            .split('&')
+            .filter(|kv| !kv.is_empty())
+            .map(|kv| match kv.split_once('=') {
+                None => (percent_decode(kv), String::new()),
+                Some((k, v)) => (percent_decode(k), percent_decode(v)),
+            })
+            .collect();
+        Self(query)
+    }
+
+    pub fn get(&self, key: &str) -> std::result::Result<&str, Response> {
+        match self.0.iter().find(|(k, _)| k == key) {
+            None => Err(Response::error(400, &format!("missing {key} parameter"))),
+            Some((_, v)) => Ok(v.as_str()),
+        }
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn index(&self) -> std::result::Result<usize, Response> {
+        self.get("index")?.parse().map_err(|_| Response::error(400, "invalid index"))
+    }
+
+    /// The accumulator for the `run` parameter and the `tag` parameter.
+    pub fn run_tag<'a>(
+        &'a self,
+        mux: &'a Multiplexer,
+    ) -> std::result::Result<(&'a EventAccumulator, &'a str), Response> {
+        let run = self.get("run")?;
+        match mux.accumulator(run) {
+            None => Err(Response::error(404, &format!("unknown run {run}"))),
+            Some(accumulator) => Ok((accumulator, self.get("tag")?)),
+        }
+    }
+}
+
+pub fn not_found() -> Response {
+    Response::error(404, "not found")
+}
+
+fn api(mux: &Multiplexer, path: &str, query: &Query) -> std::result::Result<Response, Response> {
+    let response = match path {
+        "runs" => Response::json(mux.runs().collect::<Vec<_>>().into()),
+        "tags" => {
+            let mut tags = serde_json::Map::new();
+            for run in mux.runs() {
+                let acc = mux.accumulator(run).ok_or_else(not_found)?;
+                let run_tags = serde_json::json!({
+                    "scalars": acc.scalar_tags().collect::<Vec<_>>(),
+                    "histograms": acc.histogram_tags().collect::<Vec<_>>(),
+                    "images": acc.image_tags().collect::<Vec<_>>(),
+                    "audio": acc.audio_tags().collect::<Vec<_>>(),
+                    "tensors": acc.tensor_tags().collect::<Vec<_>>(),
+                });
+                tags.insert(run.to_string(), run_tags);
+            }
+            Response::json(tags.into())
+        }
+        "scalars" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.scalars(tag).ok_or_else(not_found)?;
+            let events =
+                events.iter().map(|e| serde_json::json!([e.wall_time, e.step, num(e.value)]));
+            Response::json(events.collect::<Vec<_>>().into())
+        }
+        "histograms" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.histograms(tag).ok_or_else(not_found)?;
+            let events = events.iter().map(|e| {
+                let h = &e.value;
+                serde_json::json!({
+                    "wall_time": e.wall_time,
+                    "step": e.step,
+                    "min": num(h.min),
+                    "max": num(h.max),
+                    "num": num(h.num),
+                    "sum": num(h.sum),
+                    "sum_squares": num(h.sum_squares),
+                    "bucket_limit": h.bucket_limit.iter().map(|&v| num(v)).collect::<Vec<_>>(),
+                    "bucket": h.bucket.iter().map(|&v| num(v)).collect::<Vec<_>>(),
+                })
+            });
+            Response::json(events.collect::<Vec<_>>().into())
+        }
+        "images" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.images(tag).ok_or_else(not_found)?;
+            let events = events.iter().enumerate().map(|(index, e)| {
+                serde_json::json!({
+                    "wall_time": e.wall_time,
+                    "step": e.step,
+                    "width": e.value.width,
+                    "height": e.value.height,
+                    "index": index,
+                })
+            });
+            Response::json(events.collect::<Vec<_>>().into())
+        }
+        "image" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.images(tag).ok_or_else(not_found)?;
+            let image = &events.get(query.index()?).ok_or_else(not_found)?.value;
+            let data = image.encoded_image_string.clone();
+            Response::bytes(tboard::image_content_type(&data), data)
+        }
+        "audio" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.audio(tag).ok_or_else(not_found)?;
+            let events = events.iter().enumerate().map(|(index, e)| {
+                serde_json::json!({
+                    "wall_time": e.wall_time,
+                    "step": e.step,
+                    "content_type": e.value.content_type,
+                    "sample_rate": e.value.sample_rate,
+                    "num_channels": e.value.num_channels,
+                    "length_frames": e.value.length_frames,
+                    "index": index,
+                })
+            });
+            Response::json(events.collect::<Vec<_>>().into())
+        }
+        "audio_data" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.audio(tag).ok_or_else(not_found)?;
+            let audio = &events.get(query.index()?).ok_or_else(not_found)?.value;
+            Response::bytes(&audio.content_type, audio.encoded_audio_string.clone())
+        }
+        _ => not_found(),
+    };
+    Ok(response)
+}
+
+fn static_file(assets: &std::path::Path, path: &str) -> Response {
+    let path = if path.ends_with('/') { format!("{path}index.html") } else { path.to_string() };
+    // Only plain relative paths are served to avoid leaving the assets directory.
+    let path = std::path::Path::new(path.trim_start_matches('/'));
+    if path.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+        return not_found();
+    }
+    let content_type = match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    };
+    match std::fs::read(assets.join(path)) {
+        Ok(data) => Response::bytes(content_type, data),
+        Err(_) => not_found(),
+    }
+}
+
+// The form fields of POST requests are merged with the query parameters.
+fn handle(mux: &Multiplexer, url: &str, form: &str, assets: Option<&std::path::Path>) -> Response {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let query = Query::parse(&format!("{query}&{form}"));
+    // The tensorboard frontend can prefix the data routes with an experiment id.
+    let path = match path.strip_prefix("/experiment/").and_then(|p| p.split_once('/')) {
+        Some((_, path)) => format!("/{path}"),
+        None => path.to_string(),
+    };
+    if let Some(path) = path.strip_prefix("/api/") {
+        return api(mux, path, &query).unwrap_or_else(|err| err);
+    }
+    if let Some(path) = path.strip_prefix("/data/") {
+        return data::handle(mux, path, &query).unwrap_or_else(|err| err);
+    }
+    match (assets, path.as_str()) {
+        (Some(assets), path) => static_file(assets, path),
+        (None, "/" | "/index.html") => {
+            Response::bytes("text/html; charset=utf-8", INDEX_HTML.into())
+        }
+        (None, _) => not_found(),
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut mux = Multiplexer::new(&args.logdir);
+    mux.reload()?;
+    let mux = Arc::new(RwLock::new(mux));
+    {
+        let mux = mux.clone();
+        let interval = std::time::Duration::from_secs_f64(args.reload_interval);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            // The files are read without holding the lock so that the requests are only
+            // blocked while the new events are added.
+            let positions = mux.read().unwrap().positions();
+            match positions.read_updates() {
+                Ok(updates) => mux.write().unwrap().apply(updates),
+                Err(err) => eprintln!("error reloading {:?}: {err}", args.logdir),
+            }
+        });
+    }
+    let server = tiny_http::Server::http((args.host.as_str(), args.port))
+        .map_err(|err| anyhow::anyhow!("cannot bind {}:{}: {err}", args.host, args.port))?;
+    println!("serving on http://{}:{}", args.host, args.port);
+    let octet_stream = tiny_http::Header::from_bytes("Content-Type", "application/octet-stream")
+        .map_err(|()| anyhow::anyhow!("invalid content type"))?;
+    for mut request in server.incoming_requests() {
+        let response = match request.method() {
+            tiny_http::Method::Get => {
+                handle(&mux.read().unwrap(), request.url(), "", args.assets.as_deref())
+            }
+            tiny_http::Method::Post => {
+                let mut form = String::new();
+                match request.as_reader().read_to_string(&mut form) {
+                    Err(err) => Response::error(400, &err.to_string()),
+                    Ok(_) => {
+                        let mux = mux.read().unwrap();
+                        handle(&mux, request.url(), &form, args.assets.as_deref())
+                    }
+                }
+            }
+            _ => Response::error(405, "method not allowed"),
+        };
+        // The audio content types come from the event files and may not be valid headers.
+        let header =
+            tiny_http::Header::from_bytes("Content-Type", response.content_type.as_bytes())
+                .unwrap_or_else(|()| octet_stream.clone());
+        let http_response = tiny_http::Response::from_data(response.body)
+            .with_status_code(response.status)
+            .with_header(header);
+        if let Err(err) = request.respond(http_response) {
+            eprintln!("error sending response: {err}")
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) fn temp_dir(name: &str) -> std::path::PathBuf {
+        let name = format!("tboard-cli-test-{}-{name}", std::process::id());
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // A log directory with a train and an eval run, returns the directory and its
+    // multiplexer.
+    pub(super) fn logdir(name: &str) -> (std::path::PathBuf, Multiplexer) {
+        let dir = temp_dir(name);
+        let mut writer = tboard::EventWriter::create(dir.join("train")).unwrap();
+        writer.write_scalar(1, "loss", 0.5).unwrap();
+        writer.write_scalar(2, "loss", f32::NAN).unwrap();
+        let (bucket, limit) = (vec![1., 2., 3.], vec![0.5, 1., f64::MAX]);
+        writer.write_histo(3, "weights", -1., 2., 6., 3., 9., bucket, limit).unwrap();
+        writer.write_image_pixels(4, "image", 2, 1, 1, &[0, 255]).unwrap();
+        writer.write_audio(5, "audio", "audio/wav", b"RIFF".to_vec(), 1, 1, 8000.).unwrap();
+        writer.flush().unwrap();
+        let mut writer = tboard::EventWriter::create(dir.join("eval")).unwrap();
+        writer.write_scalar(1, "loss", 0.75).unwrap();
+        writer.flush().unwrap();
+        let mut mux = Multiplexer::new(&dir);
+        mux.reload().unwrap();
+        (dir, mux)
+    }
+
+    pub(super) fn json(response: Response) -> serde_json::Value {
+        assert_eq!((response.status, response.content_type.as_str()), (200, "application/json"));
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(percent_decode("a+b%20c"), "a b c");
+        assert_eq!(percent_decode("%41%4a%2B%2f"), "AJ+/");
+        assert_eq!(percent_decode("100%25"), "100%");
+        assert_eq!(percent_decode("%C3%A9"), "é");
+        assert_eq!(percent_decode("%ff"), "\u{fffd}");
+        // Invalid or incomplete escapes are kept as is.
+        assert_eq!(percent_decode("%"), "%");
+        assert_eq!(percent_decode("a%4"), "a%4");
+        assert_eq!(percent_decode("%zz%"), "%zz%");
+    }
+
+    #[test]
+    fn query() {
+        let query = Query::parse("run=a%2Fb&tag=x+y&tag=z&flag&&index=3");
+        assert_eq!(query.get("run").ok(), Some("a/b"));
+        assert_eq!(query.get("tag").ok(), Some("x y"));
+        assert_eq!(query.get_all("tag").collect::<Vec<_>>(), ["x y", "z"]);
+        assert_eq!(query.get("flag").ok(), Some(""));
+        assert_eq!(query.index().ok(), Some(3));
+        let err = query.get("missing").unwrap_err();
+        assert_eq!(
+            (err.status, err.body.as_slice()),
+            (400, b"missing missing parameter".as_slice())
+        );
+        let err = Query::parse("index=-1").index().unwrap_err();
+        assert_eq!((err.status, err.body.as_slice()), (400, b"invalid index".as_slice()));
+    }
+
+    #[test]
+    fn static_files() {
+        let dir = temp_dir("serve-static");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(assets.join("js")).unwrap();
+        std::fs::write(assets.join("index.html"), "<html>").unwrap();
+        std::fs::write(assets.join("js/app.js"), "app").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        let response = static_file(&assets, "/");
+        assert_eq!((response.status, response.body.as_slice()), (200, b"<html>".as_slice()));
+        assert_eq!(response.content_type, "text/html; charset=utf-8");
+        let response = static_file(&assets, "/js/app.js");
+        assert_eq!((response.status, response.content_type.as_str()), (200, "text/javascript"));
+        assert_eq!(static_file(&assets, "/missing.css").status, 404);
+        // The paths leaving the assets directory are rejected.
+        for path in ["/../secret.txt", "/js/../../secret.txt", "/./index.html", "//secret.txt"] {
+            assert_eq!(static_file(&assets, path).status, 404, "{path}");
+        }
+        let secret = dir.join("secret.txt");
+        assert_eq!(static_file(&assets, secret.to_str().unwrap()).status, 404);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn routes() {
+        let (dir, mux) = logdir("serve-routes");
+        assert_eq!(json(handle(&mux, "/api/runs", "", None)), serde_json::json!(["eval", "train"]));
+        let tags = json(handle(&mux, "/api/tags", "", None));
+        assert_eq!(tags["train"]["scalars"], serde_json::json!(["loss"]));
+        assert_eq!(tags["train"]["histograms"], serde_json::json!(["weights"]));
+        assert_eq!(tags["eval"]["images"], serde_json::json!([]));
+        let scalars = json(handle(&mux, "/api/scalars?run=train&tag=loss", "", None));
+        assert_eq!((scalars[0][1].as_i64(), scalars[0][2].as_f64()), (Some(1), Some(0.5)));
+        assert_eq!(scalars[1][2], "NaN");
+        // The POST form fields are merged with the query, and the experiment prefix is
+        // stripped.
+        let response = handle(&mux, "/experiment/abc/api/scalars?run=eval", "tag=loss", None);
+        assert_eq!(json(response)[0][2], 0.75);
+        let histograms = json(handle(&mux, "/api/histograms?run=train&tag=weights", "", None));
+        assert_eq!(histograms[0]["bucket"], serde_json::json!([1., 2., 3.]));
+        let images = json(handle(&mux, "/api/images?run=train&tag=image", "", None));
+        assert_eq!((images[0]["width"].as_i64(), images[0]["index"].as_i64()), (Some(2), Some(0)));
+        let response = handle(&mux, "/api/image?run=train&tag=image&index=0", "", None);
+        assert_eq!((response.status, response.content_type.as_str()), (200, "image/png"));
+        let response = handle(&mux, "/api/audio_data?run=train&tag=audio&index=0", "", None);
+        assert_eq!(
+            (response.content_type.as_str(), response.body.as_slice()),
+            ("audio/wav", b"RIFF".as_slice())
+        );
+
+        let error = |url: &str| {
+            let response = handle(&mux, url, "", None);
+            (response.status, String::from_utf8(response.body).unwrap())
+        };
+        assert_eq!(error("/api/scalars?tag=loss"), (400, "missing run parameter".to_string()));
+        assert_eq!(error("/api/scalars?run=test&tag=loss"), (404, "unknown run test".to_string()));
+        assert_eq!(error("/api/scalars?run=train&tag=acc").0, 404);
+        assert_eq!(error("/api/image?run=train&tag=image&index=1").0, 404);
+        assert_eq!(error("/api/unknown").0, 404);
+
+        let response = handle(&mux, "/", "", None);
+        assert_eq!((response.status, response.body.as_slice()), (200, INDEX_HTML.as_bytes()));
+        assert_eq!(handle(&mux, "/app.js", "", None).status, 404);
+        let response = handle(&mux, "/", "", Some(&dir));
+        assert_eq!(response.status, 404);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
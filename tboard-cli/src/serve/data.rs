@@ -0,0 +1,227 @@
+// The routes of the tensorboard data api used by the stock frontend for the scalars,
+// histograms, images, and audio dashboards. The routes can be prefixed with
+// `/experiment/<id>` as done by the frontend when embedded. The time series dashboard uses
+// a different api that is not supported.
+use super::{not_found, num, Query, Response};
+use tboard::multiplexer::Multiplexer;
+use tboard::EventAccumulator;
+
+fn plugin_entry(element_name: &str, tab_name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "disable_reload": false,
+        "enabled": true,
+        "remove_dom": false,
+        "tab_name": tab_name,
+        "loading_mechanism": {"type": "CUSTOM_ELEMENT", "element_name": element_name},
+    })
+}
+
+// The tags for each run as returned by the `tags` route of the plugins.
+fn tags<'a, F, I>(mux: &'a Multiplexer, tags: F, samples: bool) -> serde_json::Value
+where
+    F: Fn(&'a EventAccumulator) -> I,
+    I: Iterator<Item = &'a str>,
+{
+    let mut runs = serde_json::Map::new();
+    for run in mux.runs() {
+        let acc = match mux.accumulator(run) {
+            None => continue,
+            Some(acc) => acc,
+        };
+        let mut run_tags = serde_json::Map::new();
+        for tag in tags(acc) {
+            let mut info = serde_json::json!({"displayName": "", "description": ""});
+            if samples {
+                info["samples"] = 1.into()
+            }
+            run_tags.insert(tag.to_string(), info);
+        }
+        runs.insert(run.to_string(), run_tags.into());
+    }
+    runs.into()
+}
+
+fn scalars(acc: &EventAccumulator, tag: &str) -> Option<serde_json::Value> {
+    let events = acc.scalars(tag)?;
+    let events = events.iter().map(|e| serde_json::json!([e.wall_time, e.step, num(e.value)]));
+    Some(events.collect::<Vec<_>>().into())
+}
+
+// The histograms are converted to [left, right, count] buckets, as done by tensorboard
+// when reading the legacy histogram summaries.
+fn histogram_buckets(h: &tboard::tensorboard::HistogramProto) -> serde_json::Value {
+    let mut left = h.min;
+    let buckets =
+        h.bucket_limit.iter().zip(h.bucket.iter()).enumerate().map(|(i, (&limit, &count))| {
+            let right = if i + 1 == h.bucket.len() { h.max } else { limit };
+            let bucket = serde_json::json!([num(left), num(right), num(count)]);
+            left = right;
+            bucket
+        });
+    buckets.collect::<Vec<_>>().into()
+}
+
+// The query string identifying a blob, used by the individualImage and individualAudio
+// routes.
+fn blob_query(run: &str, tag: &str, index: usize) -> String {
+    let encode = |s: &str| {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{b:02X}"),
+            })
+            .collect::<String>()
+    };
+    format!("run={}&tag={}&index={index}", encode(run), encode(tag))
+}
+
+pub fn handle(
+    mux: &Multiplexer,
+    path: &str,
+    query: &Query,
+) -> std::result::Result<Response, Response> {
+    let response = match path {
+        "environment" => Response::json(serde_json::json!({
+            "data_location": mux.logdir().to_string_lossy(),
+            "window_title": "",
+            "experiment_name": "",
+            "experiment_description": "",
+            "creation_time": 0,
+        })),
+        "runs" => Response::json(mux.runs().collect::<Vec<_>>().into()),
+        "plugins_listing" => Response::json(serde_json::json!({
+            "scalars": plugin_entry("tf-scalar-dashboard", "scalars"),
+            "images": plugin_entry("tf-image-dashboard", "images"),
+            "audio": plugin_entry("tf-audio-dashboard", "audio"),
+            "histograms": plugin_entry("tf-histogram-dashboard", "histograms"),
+        })),
+        "plugin/scalars/tags" => Response::json(tags(mux, |acc| acc.scalar_tags(), false)),
+        "plugin/scalars/scalars" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            Response::json(scalars(acc, tag).ok_or_else(not_found)?)
+        }
+        "plugin/scalars/scalars_multirun" => {
+            let tag = query.get("tag")?;
+            let mut runs = serde_json::Map::new();
+            for run in query.get_all("runs") {
+                if let Some(events) = mux.accumulator(run).and_then(|acc| scalars(acc, tag)) {
+                    runs.insert(run.to_string(), events);
+                }
+            }
+            Response::json(runs.into())
+        }
+        "plugin/histograms/tags" => Response::json(tags(mux, |acc| acc.histogram_tags(), false)),
+        "plugin/histograms/histograms" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.histograms(tag).ok_or_else(not_found)?;
+            let events = events
+                .iter()
+                .map(|e| serde_json::json!([e.wall_time, e.step, histogram_buckets(&e.value)]));
+            Response::json(events.collect::<Vec<_>>().into())
+        }
+        "plugin/images/tags" => Response::json(tags(mux, |acc| acc.image_tags(), true)),
+        "plugin/images/images" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let run = query.get("run")?;
+            let events = acc.images(tag).ok_or_else(not_found)?;
+            let events = events.iter().enumerate().map(|(index, e)| {
+                serde_json::json!({
+                    "wall_time": e.wall_time,
+                    "step": e.step,
+                    "width": e.value.width,
+                    "height": e.value.height,
+                    "query": blob_query(run, tag, index),
+                })
+            });
+            Response::json(events.collect::<Vec<_>>().into())
+        }
+        "plugin/images/individualImage" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.images(tag).ok_or_else(not_found)?;
+            let image = &events.get(query.index()?).ok_or_else(not_found)?.value;
+            let data = image.encoded_image_string.clone();
+            Response::bytes(tboard::image_content_type(&data), data)
+        }
+        "plugin/audio/tags" => Response::json(tags(mux, |acc| acc.audio_tags(), true)),
+        "plugin/audio/audio" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let run = query.get("run")?;
+            let events = acc.audio(tag).ok_or_else(not_found)?;
+            let events = events.iter().enumerate().map(|(index, e)| {
+                serde_json::json!({
+                    "wall_time": e.wall_time,
+                    "step": e.step,
+                    "label": "",
+                    "contentType": e.value.content_type,
+                    "query": blob_query(run, tag, index),
+                })
+            });
+            Response::json(events.collect::<Vec<_>>().into())
+        }
+        "plugin/audio/individualAudio" => {
+            let (acc, tag) = query.run_tag(mux)?;
+            let events = acc.audio(tag).ok_or_else(not_found)?;
+            let audio = &events.get(query.index()?).ok_or_else(not_found)?.value;
+            Response::bytes(&audio.content_type, audio.encoded_audio_string.clone())
+        }
+        _ => not_found(),
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{json, logdir};
+    use super::super::{handle as serve, Response};
+
+    fn get(mux: &super::Multiplexer, url: &str, form: &str) -> Response {
+        serve(mux, url, form, None)
+    }
+
+    #[test]
+    fn runs_and_tags() {
+        let (dir, mux) = logdir("data-tags");
+        assert_eq!(json(get(&mux, "/data/runs", "")), serde_json::json!(["eval", "train"]));
+        let info = serde_json::json!({"displayName": "", "description": ""});
+        let tags = json(get(&mux, "/data/plugin/scalars/tags", ""));
+        assert_eq!(tags, serde_json::json!({"eval": {"loss": info}, "train": {"loss": info}}));
+        let tags = json(get(&mux, "/experiment/1/data/plugin/images/tags", ""));
+        assert_eq!(tags["train"]["image"]["samples"], 1);
+        assert_eq!(tags["eval"], serde_json::json!({}));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn scalars_multirun() {
+        let (dir, mux) = logdir("data-multirun");
+        // The frontend sends the tag and the runs as POST form fields, the unknown runs are
+        // skipped.
+        let url = "/data/plugin/scalars/scalars_multirun";
+        let scalars = json(get(&mux, url, "tag=loss&runs=train&runs=eval&runs=test"));
+        let runs = scalars.as_object().unwrap();
+        let mut names = runs.keys().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["eval", "train"]);
+        assert_eq!(
+            (runs["eval"][0][1].as_i64(), runs["eval"][0][2].as_f64()),
+            (Some(1), Some(0.75))
+        );
+        assert_eq!(runs["train"][1][2], "NaN");
+        assert_eq!(get(&mux, url, "runs=train").status, 400);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn histograms() {
+        let (dir, mux) = logdir("data-histograms");
+        let url = "/data/plugin/histograms/histograms?run=train&tag=weights";
+        let histograms = json(get(&mux, url, ""));
+        assert_eq!(histograms[0][1], 3);
+        // The last bucket ends at the max value rather than at its limit.
+        let buckets = serde_json::json!([[-1., 0.5, 1.], [0.5, 1., 2.], [1., 2., 3.]]);
+        assert_eq!(histograms[0][2], buckets);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use tboard::surgery::RewriteOptions;
+
+fn parse_rename(s: &str) -> Result<(regex::Regex, String)> {
+    match s.split_once('=') {
+        None => anyhow::bail!("expected FROM=TO, got {s:?}"),
+        Some((from, to)) => Ok((regex::Regex::new(from)?, to.to_string())),
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FilterArgs {
+    /// Only keep the tags matching this regex.
+    #[arg(long)]
+    include: Option<regex::Regex>,
+
+    /// Remove the tags matching this regex.
+    #[arg(long)]
+    exclude: Option<regex::Regex>,
+
+    #[arg(long)]
+    min_step: Option<i64>,
+
+    #[arg(long)]
+    max_step: Option<i64>,
+
+    /// Added to the steps of the events that are kept.
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    step_offset: i64,
+
+    /// Rename the tags matching a regex, e.g. `--rename 'train_(.*)=train/$1'`.
+    #[arg(long, value_parser = parse_rename)]
+    rename: Vec<(regex::Regex, String)>,
+}
+
+impl FilterArgs {
+    fn options(self) -> RewriteOptions {
+        RewriteOptions {
+            include: self.include,
+            exclude: self.exclude,
+            min_step: self.min_step,
+            max_step: self.max_step,
+            step_offset: self.step_offset,
+            renames: self.rename,
+        }
+    }
+}
+
+// The directories are replaced by the event files that they directly contain.
+fn event_files(paths: &[std::path::PathBuf]) -> Result<Vec<std::path::PathBuf>> {
+    let mut files = vec![];
+    for path in paths.iter() {
+        if path.is_dir() {
+            let mut dir_files = vec![];
+            for entry in std::fs::read_dir(path)? {
+                let path = entry?.path();
+                if path.is_file() && tboard::logdir::is_event_file(&path) {
+                    dir_files.push(path)
+                }
+            }
+            dir_files.sort();
+            files.extend(dir_files)
+        } else {
+            files.push(path.clone())
+        }
+    }
+    Ok(files)
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RewriteArgs {
+    /// The event files, or the directories containing them. Multiple files are merged by
+    /// wall time.
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// The log directory where to write the new event file.
+    #[arg(short, long)]
+    output: std::path::PathBuf,
+
+    #[command(flatten)]
+    filters: FilterArgs,
+}
+
+pub fn rewrite(args: RewriteArgs) -> Result<()> {
+    let inputs = event_files(&args.inputs)?;
+    let (filename, stats) =
+        tboard::surgery::rewrite(&inputs, &args.output, &args.filters.options())?;
+    match filename {
+        None => eprintln!("no events to write"),
+        Some(filename) => {
+            eprintln!("wrote {}/{} events", stats.events_written, stats.events_read);
+            println!("{}", filename.display())
+        }
+    }
+    Ok(())
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SplitArgs {
+    /// The event file to split.
+    input: std::path::PathBuf,
+
+    /// The log directory where to write the runs.
+    #[arg(short, long)]
+    output: std::path::PathBuf,
+
+    #[command(flatten)]
+    filters: FilterArgs,
+}
+
+pub fn split(args: SplitArgs) -> Result<()> {
+    for (run, filename) in
+        tboard::surgery::split(&args.input, &args.output, &args.filters.options())?
+    {
+        println!("{run}\t{}", filename.display())
+    }
+    Ok(())
+}
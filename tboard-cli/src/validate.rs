@@ -0,0 +1,31 @@
+use anyhow::Result;
+use tboard::validate::Severity;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// An event file or a log directory.
+    path: std::path::PathBuf,
+
+    /// Also exit with a non-zero code when there are only warnings.
+    #[arg(long)]
+    strict: bool,
+
+    /// Pretty-print the JSON report.
+    #[arg(long)]
+    pretty: bool,
+}
+
+/// Prints the report as JSON, returns the exit code: 1 when some errors have been found,
+/// 0 otherwise.
+pub fn run(args: Args) -> Result<i32> {
+    let report = tboard::validate::validate(&args.path)?;
+    let json = report.to_json();
+    if args.pretty {
+        println!("{}", serde_json::to_string_pretty(&json)?)
+    } else {
+        println!("{json}")
+    }
+    let failed = report.num_issues(Severity::Error) > 0
+        || (args.strict && report.num_issues(Severity::Warning) > 0);
+    Ok(if failed { 1 } else { 0 })
+}
@@ -1,3 +1,4 @@
+use numpy::PyReadonlyArray4;
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyDict, PyList};
 
@@ -132,12 +133,19 @@ impl EventReader {
     }
 
     fn __iter__(slf: PyRef<'_, Self>) -> PyResult<EventIter> {
-        let reader = std::fs::File::open(&slf.filename)?;
-        let reader = tb::SummaryReader::new(reader);
+        let reader = tb::SummaryReader::open(&slf.filename).map_err(w)?;
         Ok(EventIter { reader })
     }
 }
 
+// Extracts the values and shape of a 4d numpy array with elements of type `T`.
+fn array4<T: numpy::Element + Copy>(array: &PyAny) -> Option<(Vec<T>, [usize; 4])> {
+    let array = array.extract::<PyReadonlyArray4<T>>().ok()?;
+    let array = array.as_array();
+    let (d0, d1, d2, d3) = array.dim();
+    Some((array.iter().copied().collect(), [d0, d1, d2, d3]))
+}
+
 #[pyclass]
 struct EventWriter {
     inner: tb::EventWriter<std::fs::File>,
@@ -157,19 +165,39 @@ impl EventWriter {
             }
         }
     }
+
+    fn write_batch<T: tb::grid::Pixel>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        (data, shape): (Vec<T>, [usize; 4]),
+        layout: tb::grid::ImageLayout,
+        opts: &tb::grid::GridOptions,
+        grid: bool,
+    ) -> tb::Result<()> {
+        let batch = tb::grid::Batch { data: &data, shape, layout };
+        if grid {
+            self.inner.write_image_grid(step, tag, &batch, opts)
+        } else {
+            self.inner.write_images(step, tag, &batch, opts.normalize.as_ref())
+        }
+    }
 }
 
 #[pymethods]
 impl EventWriter {
     #[new]
-    #[pyo3(signature = (logdir, on_error="raise"))]
-    fn new(logdir: String, on_error: &str) -> PyResult<Self> {
-        let inner = tb::EventWriter::create(&logdir).map_err(w)?;
+    #[pyo3(signature = (logdir, on_error="raise", purge_step=None))]
+    fn new(logdir: String, on_error: &str, purge_step: Option<i64>) -> PyResult<Self> {
+        let mut inner = tb::EventWriter::create(&logdir).map_err(w)?;
         let on_error = match on_error {
             "raise" => OnError::Raise,
             "log" => OnError::Log,
             on_error => py_bail!("on_error can only be 'raise' or 'log', got '{on_error}'"),
         };
+        if let Some(purge_step) = purge_step {
+            inner.resume(purge_step).map_err(w)?
+        }
         Ok(Self { inner, logdir, on_error })
     }
 
@@ -193,6 +221,49 @@ impl EventWriter {
         self.flush()
     }
 
+    /// Write a batch of images, either tiled in a single image when `grid` is true, or as
+    /// one image per value. The array can contain uint8 values, or floats between 0 and 1
+    /// unless `normalize` is set.
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (
+        tag, img_tensor, global_step=0, dataformats="NCHW", grid=true, ncols=8, padding=2,
+        normalize=false, value_range=None, scale_each=false,
+    ))]
+    fn add_images(
+        &mut self,
+        tag: &str,
+        img_tensor: &PyAny,
+        global_step: i64,
+        dataformats: &str,
+        grid: bool,
+        ncols: usize,
+        padding: usize,
+        normalize: bool,
+        value_range: Option<(f32, f32)>,
+        scale_each: bool,
+    ) -> PyResult<()> {
+        use tb::grid::{GridOptions, ImageLayout, Normalize};
+
+        let layout = match dataformats {
+            "NCHW" => ImageLayout::Chw,
+            "NHWC" => ImageLayout::Hwc,
+            _ => py_bail!("dataformats can only be 'NCHW' or 'NHWC', got '{dataformats}'"),
+        };
+        let normalize = normalize.then_some(Normalize { value_range, scale_each });
+        let opts = GridOptions { ncols, padding, pad_value: 0, normalize };
+        let res = if let Some(array) = array4::<u8>(img_tensor) {
+            self.write_batch(global_step, tag, array, layout, &opts, grid)
+        } else if let Some(array) = array4::<f32>(img_tensor) {
+            self.write_batch(global_step, tag, array, layout, &opts, grid)
+        } else if let Some(array) = array4::<f64>(img_tensor) {
+            self.write_batch(global_step, tag, array, layout, &opts, grid)
+        } else {
+            py_bail!("img_tensor should be a 4d numpy array of uint8, float32, or float64")
+        };
+        self.handle_err(res)?;
+        self.flush()
+    }
+
     fn flush(&mut self) -> PyResult<()> {
         let res = self.inner.flush();
         self.handle_err(res)
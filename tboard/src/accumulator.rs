@@ -0,0 +1,549 @@
+// Aggregates the events from one or multiple event files per tag, similar to the
+// tensorboard EventAccumulator.
+// https://github.com/tensorflow/tensorboard/blob/d1ab6e7a39e4dc4d556a8a73c0ae5c1b116801ba/tensorboard/backend/event_processing/event_accumulator.py
+use crate::{tensorboard, Result};
+use std::collections::BTreeMap;
+
+/// A value logged for a tag together with its step and wall time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaggedEvent<T> {
+    pub wall_time: f64,
+    pub step: i64,
+    pub value: T,
+}
+
+pub type ScalarEvent = TaggedEvent<f64>;
+pub type HistogramEvent = TaggedEvent<tensorboard::HistogramProto>;
+pub type ImageEvent = TaggedEvent<tensorboard::summary::Image>;
+pub type AudioEvent = TaggedEvent<tensorboard::summary::Audio>;
+pub type TensorEvent = TaggedEvent<tensorboard::TensorProto>;
+
+/// Returns the elements of a numeric tensor converted to f64, either from the typed
+/// value fields or from the little-endian `tensor_content` bytes. Returns `None` for the
+/// non-numeric tensors, and when the content is not a whole number of values.
+pub fn tensor_to_f64(tensor: &tensorboard::TensorProto) -> Option<Vec<f64>> {
+    use crate::writer::from_content;
+    use tensorboard::DataType;
+
+    let content = tensor.tensor_content.as_slice();
+    let values = match tensor.dtype() {
+        DataType::DtFloat if content.is_empty() => {
+            tensor.float_val.iter().map(|&v| v as f64).collect()
+        }
+        DataType::DtFloat => from_content(content, |v| f32::from_le_bytes(v) as f64)?,
+        DataType::DtDouble if content.is_empty() => tensor.double_val.clone(),
+        DataType::DtDouble => from_content(content, f64::from_le_bytes)?,
+        DataType::DtHalf | DataType::DtBfloat16 if content.is_empty() => {
+            let bf16 = tensor.dtype() == DataType::DtBfloat16;
+            tensor.half_val.iter().map(|&v| half_to_f64(v as u16, bf16)).collect()
+        }
+        DataType::DtHalf => from_content(content, |v| half_to_f64(u16::from_le_bytes(v), false))?,
+        DataType::DtBfloat16 => {
+            from_content(content, |v| half_to_f64(u16::from_le_bytes(v), true))?
+        }
+        DataType::DtInt32 | DataType::DtInt16 | DataType::DtInt8 if content.is_empty() => {
+            tensor.int_val.iter().map(|&v| v as f64).collect()
+        }
+        DataType::DtUint8 | DataType::DtUint16 if content.is_empty() => {
+            tensor.int_val.iter().map(|&v| v as f64).collect()
+        }
+        DataType::DtInt32 => from_content(content, |v| i32::from_le_bytes(v) as f64)?,
+        DataType::DtInt16 => from_content(content, |v| i16::from_le_bytes(v) as f64)?,
+        DataType::DtInt8 => from_content(content, |v: [u8; 1]| v[0] as i8 as f64)?,
+        DataType::DtUint8 => from_content(content, |v: [u8; 1]| v[0] as f64)?,
+        DataType::DtUint16 => from_content(content, |v| u16::from_le_bytes(v) as f64)?,
+        DataType::DtInt64 if content.is_empty() => {
+            tensor.int64_val.iter().map(|&v| v as f64).collect()
+        }
+        DataType::DtInt64 => from_content(content, |v| i64::from_le_bytes(v) as f64)?,
+        DataType::DtUint32 if content.is_empty() => {
+            tensor.uint32_val.iter().map(|&v| v as f64).collect()
+        }
+        DataType::DtUint32 => from_content(content, |v| u32::from_le_bytes(v) as f64)?,
+        DataType::DtUint64 if content.is_empty() => {
+            tensor.uint64_val.iter().map(|&v| v as f64).collect()
+        }
+        DataType::DtUint64 => from_content(content, |v| u64::from_le_bytes(v) as f64)?,
+        DataType::DtBool if content.is_empty() => {
+            tensor.bool_val.iter().map(|&v| v as u8 as f64).collect()
+        }
+        DataType::DtBool => from_content(content, |v: [u8; 1]| v[0] as f64)?,
+        _ => return None,
+    };
+    Some(values)
+}
+
+// Converts the bits of an IEEE half or of a bfloat16 to f64.
+fn half_to_f64(bits: u16, bf16: bool) -> f64 {
+    if bf16 {
+        return f32::from_bits((bits as u32) << 16) as f64;
+    }
+    let sign = if bits & 0x8000 == 0 { 1. } else { -1. };
+    let exp = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    match exp {
+        0 => sign * mantissa * 2f64.powi(-24),
+        0x1f if mantissa == 0. => sign * f64::INFINITY,
+        0x1f => f64::NAN,
+        exp => sign * (1. + mantissa / 1024.) * 2f64.powi(exp - 15),
+    }
+}
+
+// Returns the value of a single element tensor written by the tensorboard scalars plugin.
+pub(crate) fn scalar_of_tensor(tensor: &tensorboard::TensorProto) -> Option<f64> {
+    match tensor_to_f64(tensor)?.as_slice() {
+        [v] => Some(*v),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_scalar_plugin(metadata: &Option<tensorboard::SummaryMetadata>) -> bool {
+    match metadata.as_ref().and_then(|m| m.plugin_data.as_ref()) {
+        None => false,
+        Some(plugin_data) => plugin_data.plugin_name == "scalars",
+    }
+}
+
+/// The maximum number of values kept per tag for each kind of values, 0 meaning no limit.
+/// When a tag has more values, a uniform sample of its values is kept using reservoir
+/// sampling, the most recent value always being part of the sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeGuidance {
+    pub scalars: usize,
+    pub histograms: usize,
+    pub images: usize,
+    pub audio: usize,
+    pub tensors: usize,
+}
+
+impl SizeGuidance {
+    pub const UNLIMITED: Self = Self { scalars: 0, histograms: 0, images: 0, audio: 0, tensors: 0 };
+
+    /// The per-plugin defaults of the tensorboard application.
+    pub const TENSORBOARD: Self =
+        Self { scalars: 1000, histograms: 500, images: 10, audio: 10, tensors: 10 };
+}
+
+// The values for a tag, similar to the tensorboard `_ReservoirBucket`.
+// https://github.com/tensorflow/tensorboard/blob/d1ab6e7a39e4dc4d556a8a73c0ae5c1b116801ba/tensorboard/backend/event_processing/reservoir.py#L178
+#[derive(Debug, Clone)]
+struct Reservoir<T> {
+    items: Vec<TaggedEvent<T>>,
+    num_items_seen: u64,
+    // The state of a splitmix64 generator, the sampling is deterministic so that the same
+    // events always result in the same values.
+    rng: u64,
+}
+
+impl<T> Default for Reservoir<T> {
+    fn default() -> Self {
+        Self { items: vec![], num_items_seen: 0, rng: 0 }
+    }
+}
+
+impl<T> Reservoir<T> {
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e3779b97f4a7c15);
+        let z = self.rng;
+        let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn push(&mut self, item: TaggedEvent<T>, max_size: usize) {
+        if max_size == 0 || self.items.len() < max_size {
+            self.items.push(item)
+        } else {
+            // The new item replaces a random one with probability max_size / (seen + 1),
+            // otherwise it replaces the previous most recent item.
+            let r = (self.next_random() % (self.num_items_seen + 1)) as usize;
+            if r < max_size {
+                self.items.remove(r);
+                self.items.push(item)
+            } else if let Some(last) = self.items.last_mut() {
+                *last = item
+            }
+        }
+        self.num_items_seen += 1
+    }
+
+    // The number of items seen is scaled down as if the removed items had never been seen.
+    fn retain(&mut self, f: impl Fn(&TaggedEvent<T>) -> bool) {
+        let len = self.items.len();
+        self.items.retain(f);
+        if len > 0 {
+            let ratio = self.items.len() as f64 / len as f64;
+            self.num_items_seen = (self.num_items_seen as f64 * ratio).round() as u64
+        }
+    }
+}
+
+/// Accumulates the values for each tag, the `purge_orphaned_data` flag controls whether
+/// the events that have been orphaned by a restart should be discarded. A restart is
+/// detected either via a `SessionLog::START` event, or for files using a version older
+/// than 2 when a step goes backward. By default all the values are kept, see
+/// `with_size_guidance` to only keep a sample of the values.
+#[derive(Debug, Clone)]
+pub struct EventAccumulator {
+    purge_orphaned_data: bool,
+    size_guidance: SizeGuidance,
+    file_version: Option<f64>,
+    most_recent_step: i64,
+    most_recent_wall_time: f64,
+    first_event_timestamp: Option<f64>,
+    scalars: BTreeMap<String, Reservoir<f64>>,
+    histograms: BTreeMap<String, Reservoir<tensorboard::HistogramProto>>,
+    images: BTreeMap<String, Reservoir<tensorboard::summary::Image>>,
+    audio: BTreeMap<String, Reservoir<tensorboard::summary::Audio>>,
+    tensors: BTreeMap<String, Reservoir<tensorboard::TensorProto>>,
+}
+
+impl Default for EventAccumulator {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl EventAccumulator {
+    pub fn new(purge_orphaned_data: bool) -> Self {
+        Self {
+            purge_orphaned_data,
+            size_guidance: SizeGuidance::UNLIMITED,
+            file_version: None,
+            most_recent_step: -1,
+            most_recent_wall_time: -1.,
+            first_event_timestamp: None,
+            scalars: BTreeMap::new(),
+            histograms: BTreeMap::new(),
+            images: BTreeMap::new(),
+            audio: BTreeMap::new(),
+            tensors: BTreeMap::new(),
+        }
+    }
+
+    /// Only keep a sample of the values for the tags with more values than the limits.
+    pub fn with_size_guidance(mut self, size_guidance: SizeGuidance) -> Self {
+        self.size_guidance = size_guidance;
+        self
+    }
+
+    /// Accumulate all the events from an event file.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let mut slf = Self::default();
+        slf.load(crate::SummaryReader::open(path)?)?;
+        Ok(slf)
+    }
+
+    /// Accumulate the events from an iterator, typically a `SummaryReader`. This stops on
+    /// the first error.
+    pub fn load<I: IntoIterator<Item = Result<tensorboard::Event>>>(
+        &mut self,
+        events: I,
+    ) -> Result<()> {
+        for event in events {
+            self.add_event(event?)
+        }
+        Ok(())
+    }
+
+    /// Same as `load`, except that a truncated record at the end of the events is ignored.
+    /// This is typically a record that is still being written.
+    pub fn load_complete_records<I: IntoIterator<Item = Result<tensorboard::Event>>>(
+        &mut self,
+        events: I,
+    ) -> Result<()> {
+        match self.load(events) {
+            Ok(()) | Err(crate::Error::TruncatedRecord { .. }) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn add_event(&mut self, event: tensorboard::Event) {
+        use tensorboard::event::What;
+        use tensorboard::summary::value::Value;
+
+        if self.first_event_timestamp.is_none() {
+            self.first_event_timestamp = Some(event.wall_time)
+        }
+        if let Some(What::FileVersion(version)) = &event.what {
+            self.file_version = version.strip_prefix("brain.Event:").and_then(|v| v.parse().ok())
+        }
+        self.maybe_purge_orphaned_data(&event);
+        let summary = match event.what {
+            Some(What::Summary(summary)) => summary,
+            _ => return,
+        };
+        self.most_recent_step = event.step;
+        self.most_recent_wall_time = event.wall_time;
+        let (wall_time, step) = (event.wall_time, event.step);
+        let limits = self.size_guidance;
+        for value in summary.value {
+            let tag = value.tag;
+            match value.value {
+                None | Some(Value::ObsoleteOldStyleHistogram(_)) => {}
+                Some(Value::SimpleValue(v)) => {
+                    let v = TaggedEvent { wall_time, step, value: v as f64 };
+                    self.scalars.entry(tag).or_default().push(v, limits.scalars)
+                }
+                Some(Value::Histo(v)) => {
+                    let v = TaggedEvent { wall_time, step, value: v };
+                    self.histograms.entry(tag).or_default().push(v, limits.histograms)
+                }
+                Some(Value::Image(v)) => {
+                    let v = TaggedEvent { wall_time, step, value: v };
+                    self.images.entry(tag).or_default().push(v, limits.images)
+                }
+                Some(Value::Audio(v)) => {
+                    let v = TaggedEvent { wall_time, step, value: v };
+                    self.audio.entry(tag).or_default().push(v, limits.audio)
+                }
+                Some(Value::Tensor(v)) => match scalar_of_tensor(&v) {
+                    Some(s) if is_scalar_plugin(&value.metadata) => {
+                        let v = TaggedEvent { wall_time, step, value: s };
+                        self.scalars.entry(tag).or_default().push(v, limits.scalars)
+                    }
+                    _ => {
+                        let v = TaggedEvent { wall_time, step, value: v };
+                        self.tensors.entry(tag).or_default().push(v, limits.tensors)
+                    }
+                },
+            }
+        }
+    }
+
+    // https://github.com/tensorflow/tensorboard/blob/d1ab6e7a39e4dc4d556a8a73c0ae5c1b116801ba/tensorboard/backend/event_processing/event_accumulator.py#L648
+    fn maybe_purge_orphaned_data(&mut self, event: &tensorboard::Event) {
+        use tensorboard::event::What;
+        use tensorboard::session_log::SessionStatus;
+
+        if !self.purge_orphaned_data {
+            return;
+        }
+        let file_version = self.file_version.unwrap_or(0.);
+        match &event.what {
+            Some(What::SessionLog(session_log))
+                if file_version >= 2. && session_log.status() == SessionStatus::Start =>
+            {
+                self.purge(event.step, None)
+            }
+            Some(What::Summary(summary))
+                if file_version < 2. && event.step < self.most_recent_step =>
+            {
+                let tags = summary.value.iter().map(|v| v.tag.as_str()).collect::<Vec<_>>();
+                self.purge(event.step, Some(&tags))
+            }
+            _ => {}
+        }
+    }
+
+    // Remove the events with a step greater or equal to `step`, either for all tags or
+    // only for the specified ones.
+    fn purge(&mut self, step: i64, tags: Option<&[&str]>) {
+        fn purge<T>(m: &mut BTreeMap<String, Reservoir<T>>, step: i64, tags: Option<&[&str]>) {
+            for (tag, events) in m.iter_mut() {
+                if tags.is_none_or(|tags| tags.contains(&tag.as_str())) {
+                    events.retain(|e| e.step < step)
+                }
+            }
+        }
+        purge(&mut self.scalars, step, tags);
+        purge(&mut self.histograms, step, tags);
+        purge(&mut self.images, step, tags);
+        purge(&mut self.audio, step, tags);
+        purge(&mut self.tensors, step, tags);
+    }
+
+    pub fn first_event_timestamp(&self) -> Option<f64> {
+        self.first_event_timestamp
+    }
+
+    pub fn most_recent_step(&self) -> i64 {
+        self.most_recent_step
+    }
+
+    pub fn most_recent_wall_time(&self) -> f64 {
+        self.most_recent_wall_time
+    }
+
+    pub fn scalar_tags(&self) -> impl Iterator<Item = &str> {
+        self.scalars.keys().map(|v| v.as_str())
+    }
+
+    pub fn histogram_tags(&self) -> impl Iterator<Item = &str> {
+        self.histograms.keys().map(|v| v.as_str())
+    }
+
+    pub fn image_tags(&self) -> impl Iterator<Item = &str> {
+        self.images.keys().map(|v| v.as_str())
+    }
+
+    pub fn audio_tags(&self) -> impl Iterator<Item = &str> {
+        self.audio.keys().map(|v| v.as_str())
+    }
+
+    pub fn tensor_tags(&self) -> impl Iterator<Item = &str> {
+        self.tensors.keys().map(|v| v.as_str())
+    }
+
+    pub fn scalars(&self, tag: &str) -> Option<&[ScalarEvent]> {
+        self.scalars.get(tag).map(|v| v.items.as_slice())
+    }
+
+    pub fn histograms(&self, tag: &str) -> Option<&[HistogramEvent]> {
+        self.histograms.get(tag).map(|v| v.items.as_slice())
+    }
+
+    pub fn images(&self, tag: &str) -> Option<&[ImageEvent]> {
+        self.images.get(tag).map(|v| v.items.as_slice())
+    }
+
+    pub fn audio(&self, tag: &str) -> Option<&[AudioEvent]> {
+        self.audio.get(tag).map(|v| v.items.as_slice())
+    }
+
+    pub fn tensors(&self, tag: &str) -> Option<&[TensorEvent]> {
+        self.tensors.get(tag).map(|v| v.items.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::what;
+
+    fn add_scalars(acc: &mut EventAccumulator, steps: std::ops::Range<i64>) {
+        for step in steps {
+            let what = Some(what::scalar("loss", step as f32));
+            acc.add_event(tensorboard::Event { wall_time: 1., step, source_metadata: None, what })
+        }
+    }
+
+    fn steps(acc: &EventAccumulator) -> Vec<i64> {
+        acc.scalars("loss").unwrap().iter().map(|e| e.step).collect()
+    }
+
+    #[test]
+    fn tensor_content() {
+        let mut tensor = tensorboard::TensorProto {
+            dtype: tensorboard::DataType::DtHalf.into(),
+            tensor_content: vec![0x00, 0x3c, 0x00, 0xc0, 0x00, 0x7c],
+            ..Default::default()
+        };
+        assert_eq!(tensor_to_f64(&tensor), Some(vec![1., -2., f64::INFINITY]));
+        tensor.tensor_content.push(0);
+        assert_eq!(tensor_to_f64(&tensor), None);
+        tensor.dtype = tensorboard::DataType::DtUint8.into();
+        assert_eq!(tensor_to_f64(&tensor).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn reservoir_sampling() {
+        let mut acc = EventAccumulator::default();
+        add_scalars(&mut acc, 0..100);
+        assert_eq!(steps(&acc), (0..100).collect::<Vec<_>>());
+
+        let limits = SizeGuidance { scalars: 10, ..SizeGuidance::UNLIMITED };
+        let mut acc = EventAccumulator::default().with_size_guidance(limits);
+        add_scalars(&mut acc, 0..10);
+        assert_eq!(steps(&acc), (0..10).collect::<Vec<_>>());
+        add_scalars(&mut acc, 10..1000);
+        let sample = steps(&acc);
+        assert_eq!(sample.len(), 10);
+        // The sample stays sorted, contains the most recent step, and is spread over the
+        // whole range rather than only containing the first or last steps.
+        assert!(sample.windows(2).all(|w| w[0] < w[1]), "{sample:?}");
+        assert_eq!(sample.last(), Some(&999));
+        assert!(sample.iter().filter(|&&s| s < 500).count() >= 2, "{sample:?}");
+        assert!(sample.iter().filter(|&&s| s >= 500).count() >= 2, "{sample:?}");
+        // The sampling is deterministic.
+        let mut other = EventAccumulator::default().with_size_guidance(limits);
+        add_scalars(&mut other, 0..1000);
+        assert_eq!(steps(&other), sample);
+    }
+
+    #[test]
+    fn purge_with_reservoir() {
+        let limits = SizeGuidance { scalars: 4, ..SizeGuidance::UNLIMITED };
+        let mut acc = EventAccumulator::default().with_size_guidance(limits);
+        add_scalars(&mut acc, 0..4);
+        let restart = tensorboard::SessionLog {
+            status: tensorboard::session_log::SessionStatus::Start as i32,
+            ..Default::default()
+        };
+        let file_version = Some(tensorboard::event::What::FileVersion("brain.Event:2".into()));
+        acc.add_event(tensorboard::Event { what: file_version, ..Default::default() });
+        let what = Some(tensorboard::event::What::SessionLog(restart));
+        acc.add_event(tensorboard::Event { step: 2, what, ..Default::default() });
+        assert_eq!(steps(&acc), [0, 1]);
+        add_scalars(&mut acc, 2..4);
+        assert_eq!(steps(&acc), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn purge_out_of_order_steps() {
+        let acc_steps = |acc: &EventAccumulator| -> Vec<i64> {
+            acc.scalars("acc").unwrap().iter().map(|e| e.step).collect()
+        };
+        // Without a file version or with a version below 2, a step lower than the most
+        // recent one purges the following steps for the tags of the event.
+        for file_version in [None, Some("brain.Event:1")] {
+            let mut acc = EventAccumulator::default();
+            if let Some(version) = file_version {
+                let what = Some(tensorboard::event::What::FileVersion(version.into()));
+                acc.add_event(tensorboard::Event { what, ..Default::default() });
+            }
+            for step in 0..5 {
+                let what = Some(what::scalar("acc", step as f32));
+                acc.add_event(tensorboard::Event { step, what, ..Default::default() })
+            }
+            add_scalars(&mut acc, 0..5);
+            add_scalars(&mut acc, 2..4);
+            assert_eq!(steps(&acc), [0, 1, 2, 3]);
+            assert_eq!(acc_steps(&acc), [0, 1, 2, 3, 4]);
+        }
+        // With version 2, only the session logs trigger a purge.
+        let mut acc = EventAccumulator::default();
+        let what = Some(tensorboard::event::What::FileVersion("brain.Event:2".into()));
+        acc.add_event(tensorboard::Event { what, ..Default::default() });
+        add_scalars(&mut acc, 0..3);
+        add_scalars(&mut acc, 1..2);
+        assert_eq!(steps(&acc), [0, 1, 2, 1]);
+    }
+
+    #[test]
+    fn no_purge() {
+        let mut acc = EventAccumulator::new(false);
+        add_scalars(&mut acc, 0..3);
+        add_scalars(&mut acc, 1..2);
+        let what = Some(tensorboard::event::What::FileVersion("brain.Event:2".into()));
+        acc.add_event(tensorboard::Event { what, ..Default::default() });
+        let status = tensorboard::session_log::SessionStatus::Start;
+        let what = Some(what::session_log(status, "", ""));
+        acc.add_event(tensorboard::Event { step: 1, what, ..Default::default() });
+        assert_eq!(steps(&acc), [0, 1, 2, 1]);
+    }
+
+    #[test]
+    fn purge_on_resume() {
+        let mut data = vec![];
+        let mut writer = crate::EventWriter::from_writer(&mut data, None).unwrap();
+        for step in 1..5 {
+            writer.write_scalar(step, "loss", step as f32).unwrap()
+        }
+        writer.resume(3).unwrap();
+        for step in 3..6 {
+            writer.write_scalar(step, "loss", 10. * step as f32).unwrap()
+        }
+        drop(writer);
+
+        let mut acc = EventAccumulator::default();
+        acc.load(crate::SummaryReader::new(data.as_slice())).unwrap();
+        assert_eq!(steps(&acc), [1, 2, 3, 4, 5]);
+        let values: Vec<f64> = acc.scalars("loss").unwrap().iter().map(|e| e.value).collect();
+        assert_eq!(values, [1., 2., 30., 40., 50.]);
+
+        let mut acc = EventAccumulator::new(false);
+        acc.load(crate::SummaryReader::new(data.as_slice())).unwrap();
+        assert_eq!(steps(&acc), [1, 2, 3, 4, 3, 4, 5]);
+    }
+}
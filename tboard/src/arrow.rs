@@ -0,0 +1,393 @@
+// Conversion of the accumulated events to arrow record batches, with one table per kind of
+// values: scalars, histograms, tensors, and blobs (images and audio). The `parquet`
+// feature adds the possibility to write these tables as parquet files.
+use crate::accumulator::tensor_to_f64;
+use crate::{Error, EventAccumulator, Result};
+use ::arrow::array::{
+    ArrayRef, Float32Builder, Float64Builder, Int32Builder, Int64Builder, LargeBinaryBuilder,
+    ListBuilder, StringBuilder,
+};
+use ::arrow::datatypes::{DataType, Field, Schema};
+use ::arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+
+// The run, tag, step, and wall_time columns that are common to all the tables.
+#[derive(Default)]
+struct KeyColumns {
+    run: StringBuilder,
+    tag: StringBuilder,
+    step: Int64Builder,
+    wall_time: Float64Builder,
+}
+
+impl KeyColumns {
+    fn append(&mut self, run: &str, tag: &str, step: i64, wall_time: f64) {
+        self.run.append_value(run);
+        self.tag.append_value(tag);
+        self.step.append_value(step);
+        self.wall_time.append_value(wall_time);
+    }
+
+    fn fields() -> Vec<Field> {
+        vec![
+            Field::new("run", DataType::Utf8, false),
+            Field::new("tag", DataType::Utf8, false),
+            Field::new("step", DataType::Int64, false),
+            Field::new("wall_time", DataType::Float64, false),
+        ]
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.run.finish()),
+            Arc::new(self.tag.finish()),
+            Arc::new(self.step.finish()),
+            Arc::new(self.wall_time.finish()),
+        ]
+    }
+}
+
+fn list_field(name: &str, data_type: DataType) -> Field {
+    Field::new(name, DataType::List(Arc::new(Field::new("item", data_type, true))), false)
+}
+
+fn batch(
+    mut fields: Vec<Field>,
+    extra_fields: Vec<Field>,
+    columns: Vec<ArrayRef>,
+) -> Result<RecordBatch> {
+    fields.extend(extra_fields);
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(Error::wrap)
+}
+
+/// The arrow tables for some runs.
+#[derive(Debug, Clone)]
+pub struct Tables {
+    /// Columns: run, tag, step, wall_time, value.
+    pub scalars: RecordBatch,
+    /// Columns: run, tag, step, wall_time, min, max, num, sum, sum_squares, bucket_limit,
+    /// bucket, the last two being lists of f64.
+    pub histograms: RecordBatch,
+    /// Columns: run, tag, step, wall_time, dtype, shape, values. The values are converted
+    /// to f64, the shape is empty when unknown.
+    pub tensors: RecordBatch,
+    /// Columns: run, tag, step, wall_time, kind ("image" or "audio"), content_type,
+    /// width, height, sample_rate, num_channels, length_frames, data. The columns that do
+    /// not apply to a kind are null.
+    pub blobs: RecordBatch,
+}
+
+/// Accumulates the values from multiple runs in arrow builders.
+#[derive(Default)]
+pub struct TablesBuilder {
+    scalars: (KeyColumns, Float64Builder),
+    histograms: (KeyColumns, [Float64Builder; 5], [ListBuilder<Float64Builder>; 2]),
+    tensors: (KeyColumns, StringBuilder, ListBuilder<Int64Builder>, ListBuilder<Float64Builder>),
+    blobs: BlobColumns,
+}
+
+#[derive(Default)]
+struct BlobColumns {
+    keys: KeyColumns,
+    kind: StringBuilder,
+    content_type: StringBuilder,
+    width: Int32Builder,
+    height: Int32Builder,
+    sample_rate: Float32Builder,
+    num_channels: Int64Builder,
+    length_frames: Int64Builder,
+    // The blobs can exceed the 2GB limit of the i32 offsets of binary arrays once combined.
+    data: LargeBinaryBuilder,
+}
+
+impl TablesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_run(&mut self, run: &str, accumulator: &EventAccumulator) {
+        for tag in accumulator.scalar_tags() {
+            for e in accumulator.scalars(tag).unwrap_or_default() {
+                let (keys, value) = &mut self.scalars;
+                keys.append(run, tag, e.step, e.wall_time);
+                value.append_value(e.value);
+            }
+        }
+        for tag in accumulator.histogram_tags() {
+            for e in accumulator.histograms(tag).unwrap_or_default() {
+                let (keys, stats, buckets) = &mut self.histograms;
+                keys.append(run, tag, e.step, e.wall_time);
+                let h = &e.value;
+                for (builder, v) in
+                    stats.iter_mut().zip([h.min, h.max, h.num, h.sum, h.sum_squares])
+                {
+                    builder.append_value(v)
+                }
+                for (builder, v) in buckets.iter_mut().zip([&h.bucket_limit, &h.bucket]) {
+                    builder.append_value(v.iter().map(|&v| Some(v)))
+                }
+            }
+        }
+        for tag in accumulator.tensor_tags() {
+            for e in accumulator.tensors(tag).unwrap_or_default() {
+                let (keys, dtype, shape, values) = &mut self.tensors;
+                keys.append(run, tag, e.step, e.wall_time);
+                dtype.append_value(e.value.dtype().as_str_name());
+                let dims = e.value.tensor_shape.as_ref().map_or(&[][..], |v| v.dim.as_slice());
+                shape.append_value(dims.iter().map(|d| Some(d.size)));
+                match tensor_to_f64(&e.value) {
+                    None => values.append_null(),
+                    Some(v) => values.append_value(v.into_iter().map(Some)),
+                }
+            }
+        }
+        let b = &mut self.blobs;
+        for tag in accumulator.image_tags() {
+            for e in accumulator.images(tag).unwrap_or_default() {
+                b.keys.append(run, tag, e.step, e.wall_time);
+                let image = &e.value;
+                b.kind.append_value("image");
+                b.content_type.append_value(crate::image_content_type(&image.encoded_image_string));
+                b.width.append_value(image.width);
+                b.height.append_value(image.height);
+                b.sample_rate.append_null();
+                b.num_channels.append_null();
+                b.length_frames.append_null();
+                b.data.append_value(&image.encoded_image_string);
+            }
+        }
+        for tag in accumulator.audio_tags() {
+            for e in accumulator.audio(tag).unwrap_or_default() {
+                b.keys.append(run, tag, e.step, e.wall_time);
+                let audio = &e.value;
+                b.kind.append_value("audio");
+                b.content_type.append_value(&audio.content_type);
+                b.width.append_null();
+                b.height.append_null();
+                b.sample_rate.append_value(audio.sample_rate);
+                b.num_channels.append_value(audio.num_channels);
+                b.length_frames.append_value(audio.length_frames);
+                b.data.append_value(&audio.encoded_audio_string);
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Result<Tables> {
+        let scalars = {
+            let (keys, value) = &mut self.scalars;
+            let fields = vec![Field::new("value", DataType::Float64, false)];
+            batch(
+                KeyColumns::fields(),
+                fields,
+                [keys.finish(), vec![Arc::new(value.finish())]].concat(),
+            )?
+        };
+        let histograms = {
+            let (keys, stats, buckets) = &mut self.histograms;
+            let mut fields = ["min", "max", "num", "sum", "sum_squares"]
+                .map(|name| Field::new(name, DataType::Float64, false))
+                .to_vec();
+            fields.push(list_field("bucket_limit", DataType::Float64));
+            fields.push(list_field("bucket", DataType::Float64));
+            let mut columns = keys.finish();
+            columns.extend(stats.iter_mut().map(|b| Arc::new(b.finish()) as ArrayRef));
+            columns.extend(buckets.iter_mut().map(|b| Arc::new(b.finish()) as ArrayRef));
+            batch(KeyColumns::fields(), fields, columns)?
+        };
+        let tensors = {
+            let (keys, dtype, shape, values) = &mut self.tensors;
+            let fields = vec![
+                Field::new("dtype", DataType::Utf8, false),
+                list_field("shape", DataType::Int64),
+                Field::new(
+                    "values",
+                    DataType::List(Arc::new(Field::new("item", DataType::Float64, true))),
+                    true,
+                ),
+            ];
+            let mut columns = keys.finish();
+            columns.push(Arc::new(dtype.finish()));
+            columns.push(Arc::new(shape.finish()));
+            columns.push(Arc::new(values.finish()));
+            batch(KeyColumns::fields(), fields, columns)?
+        };
+        let blobs = {
+            let b = &mut self.blobs;
+            let fields = vec![
+                Field::new("kind", DataType::Utf8, false),
+                Field::new("content_type", DataType::Utf8, false),
+                Field::new("width", DataType::Int32, true),
+                Field::new("height", DataType::Int32, true),
+                Field::new("sample_rate", DataType::Float32, true),
+                Field::new("num_channels", DataType::Int64, true),
+                Field::new("length_frames", DataType::Int64, true),
+                Field::new("data", DataType::LargeBinary, false),
+            ];
+            let mut columns = b.keys.finish();
+            columns.push(Arc::new(b.kind.finish()));
+            columns.push(Arc::new(b.content_type.finish()));
+            columns.push(Arc::new(b.width.finish()));
+            columns.push(Arc::new(b.height.finish()));
+            columns.push(Arc::new(b.sample_rate.finish()));
+            columns.push(Arc::new(b.num_channels.finish()));
+            columns.push(Arc::new(b.length_frames.finish()));
+            columns.push(Arc::new(b.data.finish()));
+            batch(KeyColumns::fields(), fields, columns)?
+        };
+        Ok(Tables { scalars, histograms, tensors, blobs })
+    }
+}
+
+impl Tables {
+    /// Build the tables for the events from a reader, using `run` as the run name. A
+    /// truncated record at the end of the file is ignored.
+    pub fn from_reader<R: std::io::Read>(
+        run: &str,
+        reader: crate::SummaryReader<R>,
+    ) -> Result<Self> {
+        let mut accumulator = EventAccumulator::default();
+        accumulator.load_complete_records(reader)?;
+        let mut builder = TablesBuilder::new();
+        builder.add_run(run, &accumulator);
+        builder.finish()
+    }
+
+    /// Build the tables for all the runs in a log directory. The runs that cannot be fully
+    /// read result in an error, except for a truncated record at the end of a file, see
+    /// `LoadedRun::check_errors`.
+    pub fn from_logdir<P: AsRef<std::path::Path>>(logdir: P) -> Result<Self> {
+        let mut builder = TablesBuilder::new();
+        for run in crate::logdir::load(logdir)? {
+            let run = run.check_errors()?;
+            builder.add_run(&run.name, &run.accumulator)
+        }
+        builder.finish()
+    }
+
+    /// Write each table as a parquet file in `dir`: scalars.parquet, histograms.parquet,
+    /// tensors.parquet, and blobs.parquet.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet<P: AsRef<std::path::Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        for (name, batch) in [
+            ("scalars", &self.scalars),
+            ("histograms", &self.histograms),
+            ("tensors", &self.tensors),
+            ("blobs", &self.blobs),
+        ] {
+            let file = std::fs::File::create(dir.join(format!("{name}.parquet")))?;
+            write_parquet(batch, file)?
+        }
+        Ok(())
+    }
+}
+
+/// Write a record batch as a parquet file, compressed with snappy.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W: std::io::Write + Send>(batch: &RecordBatch, w: W) -> Result<()> {
+    let props = parquet::file::properties::WriterProperties::builder()
+        .set_compression(parquet::basic::Compression::SNAPPY)
+        .build();
+    let mut writer = parquet::arrow::ArrowWriter::try_new(w, batch.schema(), Some(props))
+        .map_err(Error::wrap)?;
+    writer.write(batch).map_err(Error::wrap)?;
+    writer.close().map_err(Error::wrap)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tensorboard::Event;
+    use crate::writer::what;
+    use ::arrow::array::{Array, AsArray};
+    use ::arrow::datatypes::{Float64Type, Int64Type};
+
+    fn accumulator() -> EventAccumulator {
+        let mut accumulator = EventAccumulator::default();
+        let events = [
+            (0, what::scalar("loss", 1.5)),
+            (1, what::scalar("loss", 0.5)),
+            (1, what::image("img", 1, 1, 1, b"GIF89a...".to_vec())),
+            (2, what::tensor("t", vec![1f32, 2., 3.]).unwrap()),
+        ];
+        for (step, what) in events {
+            let what = Some(what);
+            accumulator.add_event(Event { wall_time: 1., step, source_metadata: None, what });
+        }
+        accumulator
+    }
+
+    #[test]
+    fn tables() {
+        let mut builder = TablesBuilder::new();
+        builder.add_run("a", &accumulator());
+        builder.add_run("b", &accumulator());
+        let tables = builder.finish().unwrap();
+        assert_eq!(tables.scalars.num_rows(), 4);
+        let run = tables.scalars.column(0).as_string::<i32>();
+        let steps = tables.scalars.column(2).as_primitive::<Int64Type>();
+        let values = tables.scalars.column(4).as_primitive::<Float64Type>();
+        assert_eq!(run.iter().flatten().collect::<Vec<_>>(), ["a", "a", "b", "b"]);
+        assert_eq!(steps.values().to_vec(), [0, 1, 0, 1]);
+        assert_eq!(values.values().to_vec(), [1.5, 0.5, 1.5, 0.5]);
+        assert_eq!(tables.histograms.num_rows(), 0);
+
+        let values = tables.tensors.column(6).as_list::<i32>();
+        let values = values.value(0);
+        assert_eq!(values.as_primitive::<Float64Type>().values().to_vec(), [1., 2., 3.]);
+
+        assert_eq!(tables.blobs.num_rows(), 2);
+        assert_eq!(tables.blobs.column(5).as_string::<i32>().value(0), "image/gif");
+        assert!(tables.blobs.column(8).is_null(0));
+        let data = tables.blobs.column(11).as_binary::<i64>();
+        assert_eq!(data.value(1), b"GIF89a...");
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let dir = crate::test_utils::temp_dir("arrow-truncated");
+        let data = crate::test_utils::scalar_events(&[("loss", 1, 1.), ("loss", 2, 0.5)]);
+        let data = &data[..data.len() - 3];
+        std::fs::write(dir.join("events.out.tfevents.1.test"), data).unwrap();
+        let tables = Tables::from_logdir(&dir).unwrap();
+        assert_eq!(tables.scalars.num_rows(), 1);
+        let tables = Tables::from_reader("run", crate::SummaryReader::new(data)).unwrap();
+        assert_eq!(tables.scalars.num_rows(), 1);
+        let steps = tables.scalars.column(2).as_primitive::<Int64Type>();
+        assert_eq!(steps.values().to_vec(), [1]);
+
+        // Other errors are still reported.
+        let mut data = data.to_vec();
+        let len = data.len();
+        data[len - 40] ^= 1;
+        std::fs::write(dir.join("events.out.tfevents.1.test"), &data).unwrap();
+        assert!(Tables::from_logdir(&dir).is_err());
+        assert!(Tables::from_reader("run", crate::SummaryReader::new(data.as_slice())).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let dir = crate::test_utils::temp_dir("arrow-parquet");
+        let mut builder = TablesBuilder::new();
+        builder.add_run("a", &accumulator());
+        let tables = builder.finish().unwrap();
+        tables.write_parquet(&dir).unwrap();
+        for (name, batch) in [("scalars", &tables.scalars), ("blobs", &tables.blobs)] {
+            let file = std::fs::File::open(dir.join(format!("{name}.parquet"))).unwrap();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+            let column = reader.metadata().row_group(0).column(0);
+            assert_eq!(column.compression(), parquet::basic::Compression::SNAPPY);
+            let batches =
+                reader.build().unwrap().collect::<std::result::Result<Vec<_>, _>>().unwrap();
+            assert_eq!(batches.len(), 1);
+            assert_eq!(&batches[0], batch);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
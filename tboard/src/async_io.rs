@@ -0,0 +1,440 @@
+// Async versions of `SummaryReader` and `EventWriter` based on tokio, the record framing
+// and crc checks are shared with the sync versions.
+use crate::reader::{check_payload, parse_header, FOOTER_LEN, HEADER_LEN};
+use crate::writer::{encode_record, event_filename, wall_time_now, what, IntoTensor};
+use crate::{tensorboard, Error, RecordPos, Result};
+use prost::Message;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+const MIN_READ_LEN: usize = 4096;
+
+/// A stream of the events from an event file, similar to `SummaryReader` and with the same
+/// handling of the errors.
+pub struct EventStream<R: AsyncRead + Unpin> {
+    reader: R,
+    buf: Vec<u8>,
+    filled: usize,
+    // The payload length once the record header has been read.
+    event_len: Option<u64>,
+    path: Option<std::path::PathBuf>,
+    offset: u64,
+    index: u64,
+    done: bool,
+}
+
+impl EventStream<tokio::fs::File> {
+    /// Open an event file, the path is reported in the errors.
+    pub async fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = tokio::fs::File::open(path).await?;
+        Ok(Self::new(reader).with_path(path))
+    }
+}
+
+impl<R: AsyncRead + Unpin> EventStream<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![0u8; HEADER_LEN],
+            filled: 0,
+            event_len: None,
+            path: None,
+            offset: 0,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Set the path reported in errors.
+    pub fn with_path<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// The byte offset of the next record to be read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The ordinal of the next record to be read.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    fn pos(&self) -> RecordPos {
+        RecordPos { path: self.path.clone(), offset: self.offset, index: self.index }
+    }
+
+    fn record_len(&self) -> usize {
+        match self.event_len {
+            None => HEADER_LEN,
+            Some(event_len) => HEADER_LEN + event_len as usize + FOOTER_LEN,
+        }
+    }
+
+    // Reads the current record, returns `Poll::Ready(None)` on a clean end of stream.
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<tensorboard::Event>>> {
+        loop {
+            let record_len = self.record_len();
+            while self.filled < record_len {
+                // Grow the buffer progressively, so that a corrupted length results in a
+                // truncated record rather than in a large allocation.
+                if self.buf.len() == self.filled {
+                    let len = record_len.min(2 * self.filled.max(MIN_READ_LEN));
+                    self.buf.resize(len, 0u8)
+                }
+                let end = self.buf.len().min(record_len);
+                let mut read_buf = ReadBuf::new(&mut self.buf[self.filled..end]);
+                match Pin::new(&mut self.reader).poll_read(cx, &mut read_buf) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(source)) => {
+                        let pos = self.pos();
+                        return Poll::Ready(Some(Err(Error::RecordIo { source, pos })));
+                    }
+                    Poll::Ready(Ok(())) => {}
+                }
+                let read = read_buf.filled().len();
+                if read == 0 {
+                    if self.filled == 0 {
+                        return Poll::Ready(None);
+                    }
+                    let expected_len = self.event_len;
+                    let actual_len = self.filled.saturating_sub(HEADER_LEN) as u64;
+                    let actual_len = actual_len.min(expected_len.unwrap_or(0));
+                    let pos = self.pos();
+                    let err = Error::TruncatedRecord { expected_len, actual_len, pos };
+                    return Poll::Ready(Some(Err(err)));
+                }
+                self.filled += read
+            }
+            let pos = self.pos();
+            match self.event_len {
+                None => match parse_header(&self.buf[..HEADER_LEN], &pos) {
+                    Ok(event_len) => self.event_len = Some(event_len),
+                    Err(err) => {
+                        // Skip the header and carry on with the following bytes, as done
+                        // by `SummaryReader`.
+                        self.offset += HEADER_LEN as u64;
+                        self.index += 1;
+                        self.filled = 0;
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                },
+                Some(event_len) => {
+                    let payload = &self.buf[HEADER_LEN..HEADER_LEN + event_len as usize];
+                    let footer = &self.buf[HEADER_LEN + event_len as usize..record_len];
+                    self.offset += record_len as u64;
+                    self.index += 1;
+                    self.filled = 0;
+                    self.event_len = None;
+                    if let Err(err) = check_payload(payload, footer, &pos) {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                    let event = tensorboard::Event::decode(payload)
+                        .map_err(|source| Error::RecordDecode { source, pos });
+                    return Poll::Ready(Some(event));
+                }
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> futures_core::Stream for EventStream<R> {
+    type Item = Result<tensorboard::Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let slf = self.get_mut();
+        if slf.done {
+            return Poll::Ready(None);
+        }
+        let record = slf.poll_record(cx);
+        if let Poll::Ready(
+            None | Some(Err(Error::TruncatedRecord { .. } | Error::RecordIo { .. })),
+        ) = record
+        {
+            slf.done = true
+        }
+        record
+    }
+}
+
+/// Async version of `EventWriter`.
+pub struct AsyncEventWriter<W: AsyncWrite + Unpin> {
+    writer: W,
+    buf: Vec<u8>,
+    filename: Option<std::path::PathBuf>,
+}
+
+impl AsyncEventWriter<tokio::fs::File> {
+    /// Create an `EventFileWriter` like structure in the specified log directory.
+    pub async fn create<P: AsRef<std::path::Path>>(logdir: P) -> Result<Self> {
+        // Creating the directory and getting the hostname are blocking operations.
+        let logdir = logdir.as_ref().to_path_buf();
+        let filename = tokio::task::spawn_blocking(move || event_filename(&logdir))
+            .await
+            .map_err(Error::wrap)??;
+        let file = tokio::fs::File::create(&filename).await?;
+        Self::from_writer(file, Some(filename)).await
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEventWriter<W> {
+    pub async fn from_writer(writer: W, filename: Option<std::path::PathBuf>) -> Result<Self> {
+        let mut slf = Self { writer, buf: vec![], filename };
+        slf.write(0, tensorboard::event::What::FileVersion("brain.Event:2".to_string())).await?;
+        Ok(slf)
+    }
+
+    pub async fn write_event(&mut self, event: tensorboard::Event) -> Result<()> {
+        encode_record(&event, &mut self.buf)?;
+        self.writer.write_all(self.buf.as_slice()).await?;
+        Ok(())
+    }
+
+    pub async fn write(&mut self, step: i64, what: tensorboard::event::What) -> Result<()> {
+        self.write_event(tensorboard::Event {
+            wall_time: wall_time_now()?,
+            step,
+            source_metadata: None,
+            what: Some(what),
+        })
+        .await
+    }
+
+    pub async fn write_scalar(&mut self, step: i64, tag: &str, value: f32) -> Result<()> {
+        self.write(step, what::scalar(tag, value)).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn write_audio(
+        &mut self,
+        step: i64,
+        tag: &str,
+        content_type: &str,
+        encoded_audio_string: Vec<u8>,
+        length_frames: i64,
+        num_channels: i64,
+        sample_rate: f32,
+    ) -> Result<()> {
+        let what = what::audio(
+            tag,
+            content_type,
+            encoded_audio_string,
+            length_frames,
+            num_channels,
+            sample_rate,
+        );
+        self.write(step, what).await
+    }
+
+    pub async fn write_pcm_as_wav<S: crate::wave::Sample>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        pcm_data: &[S],
+        sample_rate: u32,
+    ) -> Result<()> {
+        self.write(step, what::pcm_as_wav(tag, pcm_data, sample_rate)?).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn write_histo(
+        &mut self,
+        step: i64,
+        tag: &str,
+        min: f64,
+        max: f64,
+        num: f64,
+        sum: f64,
+        sum_squares: f64,
+        bucket: Vec<f64>,
+        bucket_limit: Vec<f64>,
+    ) -> Result<()> {
+        let histo =
+            tensorboard::HistogramProto { bucket, bucket_limit, max, min, num, sum, sum_squares };
+        self.write(step, what::histo(tag, histo)).await
+    }
+
+    /// Write a histogram of some values using the tensorflow default buckets.
+    pub async fn write_histogram_values<T: Into<f64> + Copy>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        values: &[T],
+    ) -> Result<()> {
+        let histogram = crate::histogram::Histogram::from_values(values);
+        self.write(step, what::histo(tag, histogram.to_proto())).await
+    }
+
+    pub async fn write_image(
+        &mut self,
+        step: i64,
+        tag: &str,
+        width: i32,
+        height: i32,
+        colorspace: i32,
+        encoded_image_string: Vec<u8>,
+    ) -> Result<()> {
+        self.write(step, what::image(tag, width, height, colorspace, encoded_image_string)).await
+    }
+
+    /// Write some raw pixels as a PNG image, see `EventWriter::write_image_pixels`.
+    pub async fn write_image_pixels(
+        &mut self,
+        step: i64,
+        tag: &str,
+        width: usize,
+        height: usize,
+        channels: usize,
+        pixels: &[u8],
+    ) -> Result<()> {
+        self.write(step, what::image_pixels(tag, width, height, channels, pixels)?).await
+    }
+
+    /// Write some frames as an animated GIF, see `EventWriter::write_video`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn write_video<T: crate::grid::Pixel>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        frames: &[T],
+        n_frames: usize,
+        height: usize,
+        width: usize,
+        channels: usize,
+        fps: f32,
+    ) -> Result<()> {
+        let what = what::video(tag, frames, n_frames, height, width, channels, fps)?;
+        self.write(step, what).await
+    }
+
+    pub async fn write_tensor<T: IntoTensor>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        val: T,
+    ) -> Result<()> {
+        self.write(step, what::tensor(tag, val)?).await
+    }
+
+    pub async fn write_session_log(
+        &mut self,
+        step: i64,
+        status: tensorboard::session_log::SessionStatus,
+        checkpoint_path: &str,
+        msg: &str,
+    ) -> Result<()> {
+        self.write(step, what::session_log(status, checkpoint_path, msg)).await
+    }
+
+    /// Signal that training restarted from `step`, see `EventWriter::resume`.
+    pub async fn resume(&mut self, step: i64) -> Result<()> {
+        let status = tensorboard::session_log::SessionStatus::Start;
+        self.write_session_log(step, status, "", "").await
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    pub fn filename(&self) -> Option<&std::path::PathBuf> {
+        self.filename.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{scalar_events, temp_dir};
+    use futures_core::Stream;
+
+    async fn collect<R: AsyncRead + Unpin>(
+        mut stream: EventStream<R>,
+    ) -> Vec<Result<tensorboard::Event>> {
+        let mut events = vec![];
+        while let Some(event) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+        {
+            events.push(event)
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn write_and_stream() {
+        let dir = temp_dir("async-io");
+        let mut writer = AsyncEventWriter::create(dir.join("run")).await.unwrap();
+        for step in 0..3 {
+            writer.write_scalar(step, "loss", step as f32 / 2.).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        let path = writer.filename().unwrap().clone();
+        let events: Vec<_> = collect(EventStream::open(&path).await.unwrap())
+            .await
+            .into_iter()
+            .map(|e| e.unwrap())
+            .collect();
+        let expected: Vec<_> =
+            crate::SummaryReader::open(&path).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events, expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_stream() {
+        let data = scalar_events(&[("loss", 0, 1.), ("loss", 1, 0.5)]);
+        let events = collect(EventStream::new(&data[..data.len() - 6])).await;
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[1].as_ref().unwrap().step, 0);
+        match &events[2] {
+            Err(Error::TruncatedRecord { expected_len: Some(len), actual_len, pos }) => {
+                assert_eq!(len - actual_len, 2);
+                assert_eq!(pos.index, 2);
+            }
+            err => panic!("unexpected result {err:?}"),
+        }
+
+        // A corrupted length with a valid crc does not result in a large allocation.
+        let mut data = (1u64 << 30).to_le_bytes().to_vec();
+        data.extend_from_slice(&crate::masked_crc(&data).to_le_bytes());
+        data.extend_from_slice(&[0u8; 100]);
+        let mut stream = EventStream::new(data.as_slice());
+        let err = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await;
+        assert!(matches!(
+            err,
+            Some(Err(Error::TruncatedRecord {
+                expected_len: Some(0x4000_0000),
+                actual_len: 100,
+                ..
+            }))
+        ));
+        assert!(stream.buf.len() < 1 << 20);
+    }
+
+    #[tokio::test]
+    async fn same_as_sync_reader() {
+        let describe = |event: Result<tensorboard::Event>| match event {
+            Ok(event) => format!("step {}", event.step),
+            Err(err) => err.to_string(),
+        };
+        let mut data = scalar_events(&[("loss", 1, 1.), ("loss", 2, 0.5), ("loss", 3, 0.2)]);
+        let mut reader = crate::SummaryReader::new(data.as_slice());
+        reader.nth(1).unwrap().unwrap();
+        let offset = reader.offset() as usize;
+        // Corrupt the length crc of the second scalar, then truncate the last header.
+        data[offset + 9] ^= 1;
+        data.extend_from_slice(&[1, 2, 3]);
+        let sync_events: Vec<_> =
+            crate::SummaryReader::new(data.as_slice()).map(describe).collect();
+        let events: Vec<_> =
+            collect(EventStream::new(data.as_slice())).await.into_iter().map(describe).collect();
+        assert_eq!(events, sync_events);
+        assert!(events.len() > 4, "{events:?}");
+        assert_eq!(events[..2], ["step 0", "step 1"]);
+        assert!(events[2].contains("crc"), "{events:?}");
+        assert!(events.last().unwrap().contains("truncated"), "{events:?}");
+    }
+}
@@ -0,0 +1,228 @@
+// Writing candle tensors as tensor, histogram, and image summaries. The tensors are copied
+// to the cpu when needed, the floating point tensors are written as f32 except for f64 ones,
+// and the integer tensors as i64.
+//
+// `EventWriter::write_var_map` logs the histograms and norms of the variables of a model
+// and of their gradients, similar to `wandb.watch` for PyTorch models.
+use crate::histogram::Histogram;
+use crate::writer::{tensor_shape, IntoTensor, TensorType};
+use crate::{tensorboard, Error, EventWriter, Result};
+use candle_core::backprop::GradStore;
+use candle_core::{DType, Device, Tensor, WithDType};
+use candle_nn::VarMap;
+
+fn to_vec<T: WithDType>(tensor: &Tensor) -> Result<Vec<T>> {
+    let tensor = tensor.to_device(&Device::Cpu).map_err(Error::wrap)?;
+    let tensor = tensor.to_dtype(T::DTYPE).map_err(Error::wrap)?;
+    tensor.flatten_all().and_then(|t| t.to_vec1::<T>()).map_err(Error::wrap)
+}
+
+// The L2 norm of a tensor, computed on its device.
+fn norm(tensor: &Tensor) -> Result<f32> {
+    let norm = tensor.to_dtype(DType::F32).and_then(|t| t.sqr()?.sum_all()?.sqrt());
+    norm.and_then(|t| t.to_scalar::<f32>()).map_err(Error::wrap)
+}
+
+fn histogram(tensor: &Tensor) -> Result<Histogram> {
+    let mut histogram = Histogram::default();
+    for v in to_vec::<f64>(tensor)? {
+        histogram.add(v)
+    }
+    Ok(histogram)
+}
+
+impl IntoTensor for &Tensor {
+    fn into_tensor_proto(self) -> Result<tensorboard::TensorProto> {
+        let mut tensor = match self.dtype() {
+            DType::F64 => f64::into_proto(to_vec(self)?),
+            DType::U8 | DType::U32 | DType::I16 | DType::I32 | DType::I64 => {
+                i64::into_proto(to_vec(self)?)
+            }
+            _ => f32::into_proto(to_vec(self)?),
+        };
+        tensor.tensor_shape = Some(tensor_shape(self.dims()));
+        Ok(tensor)
+    }
+}
+
+impl IntoTensor for Tensor {
+    fn into_tensor_proto(self) -> Result<tensorboard::TensorProto> {
+        (&self).into_tensor_proto()
+    }
+}
+
+impl<W: std::io::Write> EventWriter<W> {
+    /// Write a histogram of the values of a tensor using the tensorflow default buckets.
+    pub fn write_histogram_tensor(&mut self, step: i64, tag: &str, tensor: &Tensor) -> Result<()> {
+        self.write_histogram(step, tag, &histogram(tensor)?)
+    }
+
+    /// Write an image from a tensor of shape (channels, height, width) with 1 to 4 channels,
+    /// or (height, width) for grayscale images. The u8 tensors are written as is, and the
+    /// values of the other tensors are expected to be between 0 and 1 and are clamped.
+    pub fn write_image_tensor(&mut self, step: i64, tag: &str, tensor: &Tensor) -> Result<()> {
+        let tensor = match tensor.dims() {
+            [_, _] => tensor.unsqueeze(2),
+            [_, _, _] => tensor.permute((1, 2, 0)),
+            dims => crate::bail!("unexpected image shape {dims:?}, expected (c, h, w) or (h, w)"),
+        };
+        let tensor = tensor.map_err(Error::wrap)?;
+        let (height, width, channels) = tensor.dims3().map_err(Error::wrap)?;
+        let pixels = match tensor.dtype() {
+            DType::U8 => to_vec::<u8>(&tensor)?,
+            _ => {
+                let pixels = to_vec::<f32>(&tensor)?;
+                pixels.iter().map(|v| (v.clamp(0., 1.) * 255.).round() as u8).collect()
+            }
+        };
+        self.write_image_pixels(step, tag, width, height, channels, &pixels)
+    }
+
+    /// Write the histogram and L2 norm of each variable of a var-map, using the `weights/`
+    /// and `weight_norms/` tag prefixes followed by the variable name. When `grads` is set,
+    /// the same is done for the gradients with the `grads/` and `grad_norms/` prefixes, the
+    /// variables without a gradient being skipped. This is meant to be called every few
+    /// training steps as it copies all the weights to the cpu.
+    pub fn write_var_map(
+        &mut self,
+        step: i64,
+        var_map: &VarMap,
+        grads: Option<&GradStore>,
+    ) -> Result<()> {
+        let mut vars: Vec<_> = {
+            let data = var_map.data().lock().unwrap_or_else(|e| e.into_inner());
+            data.iter().map(|(name, var)| (name.clone(), var.as_tensor().clone())).collect()
+        };
+        vars.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, var) in vars.iter() {
+            self.write_histogram(step, &format!("weights/{name}"), &histogram(var)?)?;
+            self.write_scalar(step, &format!("weight_norms/{name}"), norm(var)?)?;
+            if let Some(grad) = grads.and_then(|grads| grads.get(var)) {
+                self.write_histogram(step, &format!("grads/{name}"), &histogram(grad)?)?;
+                self.write_scalar(step, &format!("grad_norms/{name}"), norm(grad)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tensorboard::summary::value::Value;
+
+    // The tag and value of the summaries written by `f`.
+    fn values(f: impl FnOnce(&mut EventWriter<&mut Vec<u8>>)) -> Vec<(String, Value)> {
+        let mut data = vec![];
+        f(&mut EventWriter::from_writer(&mut data, None).unwrap());
+        let mut values = vec![];
+        for event in crate::SummaryReader::new(data.as_slice()) {
+            if let Some(tensorboard::event::What::Summary(summary)) = event.unwrap().what {
+                values.extend(summary.value.into_iter().map(|v| (v.tag, v.value.unwrap())))
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn tensors() {
+        let device = Device::Cpu;
+        let t = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.]], &device).unwrap();
+        let values = values(|w| {
+            for dtype in [DType::F16, DType::BF16, DType::U8] {
+                let t = t.to_dtype(dtype).unwrap();
+                w.write_tensor(0, &format!("{dtype:?}"), &t).unwrap()
+            }
+        });
+        let dtypes = [
+            ("F16", tensorboard::DataType::DtFloat),
+            ("BF16", tensorboard::DataType::DtFloat),
+            ("U8", tensorboard::DataType::DtInt64),
+        ];
+        assert_eq!(values.len(), 3);
+        for ((tag, value), (expected_tag, dtype)) in values.iter().zip(dtypes) {
+            let tensor = match value {
+                Value::Tensor(tensor) => tensor,
+                v => panic!("unexpected value {v:?}"),
+            };
+            assert_eq!((tag.as_str(), tensor.dtype()), (expected_tag, dtype));
+            let dims: Vec<_> =
+                tensor.tensor_shape.as_ref().unwrap().dim.iter().map(|d| d.size).collect();
+            assert_eq!(dims, [2, 3]);
+        }
+        match &values[1].1 {
+            Value::Tensor(tensor) => assert_eq!(tensor.float_val, [1., 2., 3., 4., 5., 6.]),
+            v => panic!("unexpected value {v:?}"),
+        }
+        match &values[2].1 {
+            Value::Tensor(tensor) => assert_eq!(tensor.int64_val, [1, 2, 3, 4, 5, 6]),
+            v => panic!("unexpected value {v:?}"),
+        }
+    }
+
+    #[test]
+    fn images() {
+        let device = Device::Cpu;
+        let gray = Tensor::new(&[[0f32, 0.5, 2.]], &device).unwrap();
+        let rgb = Tensor::new(&[[[1u8, 2]], [[3, 4]], [[5, 6]]], &device).unwrap();
+        let values = values(|w| {
+            w.write_image_tensor(0, "gray", &gray).unwrap();
+            w.write_image_tensor(0, "rgb", &rgb).unwrap();
+            let err = w.write_image_tensor(0, "4d", &rgb.unsqueeze(0).unwrap()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "unexpected image shape [1, 3, 1, 2], expected (c, h, w) or (h, w)"
+            );
+        });
+        let images: Vec<_> = values
+            .into_iter()
+            .map(|(tag, value)| match value {
+                Value::Image(image) => (tag, image),
+                v => panic!("unexpected value {v:?}"),
+            })
+            .collect();
+        assert_eq!(images.len(), 2);
+        let (tag, image) = &images[0];
+        assert_eq!((tag.as_str(), image.width, image.height, image.colorspace), ("gray", 3, 1, 1));
+        // The float values are clamped and scaled to 0..255.
+        assert_eq!(
+            image.encoded_image_string,
+            crate::png::encode(3, 1, 1, &[0, 128, 255]).unwrap()
+        );
+        let (tag, image) = &images[1];
+        assert_eq!((tag.as_str(), image.width, image.height, image.colorspace), ("rgb", 2, 1, 3));
+        let pixels = [1, 3, 5, 2, 4, 6];
+        assert_eq!(image.encoded_image_string, crate::png::encode(2, 1, 3, &pixels).unwrap());
+    }
+
+    #[test]
+    fn var_map() {
+        let device = Device::Cpu;
+        let var_map = VarMap::new();
+        let init = candle_nn::Init::Const(3.);
+        let a = var_map.get(2, "a", init, DType::F32, &device).unwrap();
+        var_map.get(2, "z", init, DType::F32, &device).unwrap();
+        // Only `a` gets a gradient, 2 * a.
+        let grads = a.sqr().unwrap().sum_all().unwrap().backward().unwrap();
+        for grads in [None, Some(&grads)] {
+            let values = values(|w| w.write_var_map(1, &var_map, grads).unwrap());
+            let tags: Vec<&str> = values.iter().map(|(tag, _)| tag.as_str()).collect();
+            let mut expected = vec!["weights/a", "weight_norms/a"];
+            if grads.is_some() {
+                expected.extend(["grads/a", "grad_norms/a"])
+            }
+            expected.extend(["weights/z", "weight_norms/z"]);
+            assert_eq!(tags, expected);
+            for (tag, value) in values.iter() {
+                match value {
+                    Value::Histo(histo) => assert_eq!(histo.num, 2., "{tag}"),
+                    Value::SimpleValue(v) => {
+                        let expected = if tag.starts_with("grad") { 72f32 } else { 18. };
+                        assert!((v - expected.sqrt()).abs() < 1e-5, "{tag} {v}")
+                    }
+                    v => panic!("unexpected value {v:?}"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// Comparison of the scalar series from two runs. The series are aligned by step, when a
+// step appears multiple times in a series the last value is used, and the values are
+// compared using a tolerance similar to `numpy.isclose`.
+use crate::{EventAccumulator, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Two values a and b are considered equal if |a - b| <= atol + rtol * |b|, as in
+    /// `numpy.isclose` the values from the second run b are the reference.
+    pub atol: f64,
+    pub rtol: f64,
+    /// Only compare the tags matching this regex.
+    pub tag_filter: Option<regex::Regex>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self { atol: 1e-8, rtol: 1e-5, tag_filter: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagDiff {
+    pub tag: String,
+    /// The number of steps present in both series.
+    pub common_steps: usize,
+    /// The last step and value of each series.
+    pub last_a: (i64, f64),
+    pub last_b: (i64, f64),
+    /// The largest absolute difference on the common steps and the step where it happens,
+    /// `None` if there is no common step.
+    pub max_abs_diff: Option<(i64, f64)>,
+    /// The first common step where the values are not within the tolerance.
+    pub first_divergence: Option<i64>,
+}
+
+impl TagDiff {
+    /// The difference between the last values, b - a.
+    pub fn last_delta(&self) -> f64 {
+        self.last_b.1 - self.last_a.1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diff {
+    /// The tags present in both runs, sorted by name.
+    pub tags: Vec<TagDiff>,
+    pub only_a: Vec<String>,
+    pub only_b: Vec<String>,
+}
+
+fn series(accumulator: &EventAccumulator, tag: &str) -> BTreeMap<i64, f64> {
+    let events = accumulator.scalars(tag).unwrap_or_default();
+    events.iter().map(|e| (e.step, e.value)).collect()
+}
+
+// NaN values are only equal to other NaN values, and infinite values to themselves.
+fn abs_diff(a: f64, b: f64) -> f64 {
+    if a == b || (a.is_nan() && b.is_nan()) {
+        0.
+    } else {
+        let diff = (a - b).abs();
+        if diff.is_nan() {
+            f64::INFINITY
+        } else {
+            diff
+        }
+    }
+}
+
+fn tag_diff(
+    tag: &str,
+    a: &BTreeMap<i64, f64>,
+    b: &BTreeMap<i64, f64>,
+    opts: &DiffOptions,
+) -> TagDiff {
+    let last = |s: &BTreeMap<i64, f64>| s.last_key_value().map(|(&k, &v)| (k, v));
+    let mut diff = TagDiff {
+        tag: tag.to_string(),
+        common_steps: 0,
+        last_a: last(a).unwrap_or((0, f64::NAN)),
+        last_b: last(b).unwrap_or((0, f64::NAN)),
+        max_abs_diff: None,
+        first_divergence: None,
+    };
+    for (&step, &va) in a.iter() {
+        let vb = match b.get(&step) {
+            None => continue,
+            Some(&vb) => vb,
+        };
+        diff.common_steps += 1;
+        let d = abs_diff(va, vb);
+        if diff.max_abs_diff.is_none_or(|(_, max)| d > max) {
+            diff.max_abs_diff = Some((step, d))
+        }
+        // The tolerance is infinite or NaN for non-finite values, these only match when
+        // they are the same. The negated comparison also flags a NaN tolerance.
+        #[allow(clippy::neg_cmp_op_on_partial_ord)]
+        let diverges = if va.is_finite() && vb.is_finite() {
+            !(d <= opts.atol + opts.rtol * vb.abs())
+        } else {
+            d != 0.
+        };
+        if diff.first_divergence.is_none() && diverges {
+            diff.first_divergence = Some(step)
+        }
+    }
+    diff
+}
+
+/// Compare the scalar series of two runs.
+pub fn diff(a: &EventAccumulator, b: &EventAccumulator, opts: &DiffOptions) -> Diff {
+    let keep_tag = |tag: &&str| opts.tag_filter.as_ref().is_none_or(|re| re.is_match(tag));
+    let tags_a: std::collections::BTreeSet<&str> = a.scalar_tags().filter(keep_tag).collect();
+    let tags_b: std::collections::BTreeSet<&str> = b.scalar_tags().filter(keep_tag).collect();
+    let tags = tags_a
+        .intersection(&tags_b)
+        .map(|tag| tag_diff(tag, &series(a, tag), &series(b, tag), opts))
+        .collect();
+    let only_a = tags_a.difference(&tags_b).map(|v| v.to_string()).collect();
+    let only_b = tags_b.difference(&tags_a).map(|v| v.to_string()).collect();
+    Diff { tags, only_a, only_b }
+}
+
+/// Load the events from an event file, or from all the event files of a run directory. A
+/// truncated record at the end of a file is ignored as it is usually being written.
+pub fn load_run<P: AsRef<Path>>(path: P) -> Result<EventAccumulator> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        let mut accumulator = EventAccumulator::default();
+        accumulator.load_complete_records(crate::SummaryReader::open(path)?)?;
+        return Ok(accumulator);
+    }
+    let run = crate::logdir::runs(path)?.into_iter().find(|run| run.name == ".");
+    let run = match run {
+        None => crate::bail!("no event files in {path:?}"),
+        Some(run) => run.load(),
+    };
+    Ok(run.check_errors()?.accumulator)
+}
+
+fn json_f64(v: f64) -> serde_json::Value {
+    serde_json::Number::from_f64(v).map_or_else(|| v.to_string().into(), |v| v.into())
+}
+
+impl Diff {
+    /// Returns true if the runs have the same tags and all the values are within the
+    /// tolerance.
+    pub fn is_same(&self) -> bool {
+        self.only_a.is_empty()
+            && self.only_b.is_empty()
+            && self.tags.iter().all(|t| t.first_divergence.is_none())
+    }
+
+    /// The non-finite numbers are written as strings, e.g. "NaN".
+    pub fn to_json(&self) -> serde_json::Value {
+        let tags = self
+            .tags
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "tag": t.tag,
+                    "common_steps": t.common_steps,
+                    "last_a": {"step": t.last_a.0, "value": json_f64(t.last_a.1)},
+                    "last_b": {"step": t.last_b.0, "value": json_f64(t.last_b.1)},
+                    "last_delta": json_f64(t.last_delta()),
+                    "max_abs_diff": t.max_abs_diff.map(|(step, v)| {
+                        serde_json::json!({"step": step, "value": json_f64(v)})
+                    }),
+                    "first_divergence": t.first_divergence,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({ "tags": tags, "only_a": self.only_a, "only_b": self.only_b })
+    }
+
+    /// Write the comparison as a text table with one line per tag.
+    pub fn write_table<W: std::io::Write>(&self, mut w: W) -> Result<()> {
+        let header = ["tag", "steps", "last a", "last b", "delta", "max diff", "at", "diverges"];
+        let mut rows = vec![header.map(|v| v.to_string()).to_vec()];
+        let opt = |v: Option<i64>| v.map_or_else(|| "-".to_string(), |v| v.to_string());
+        for t in self.tags.iter() {
+            rows.push(vec![
+                t.tag.to_string(),
+                t.common_steps.to_string(),
+                format!("{:.6}", t.last_a.1),
+                format!("{:.6}", t.last_b.1),
+                format!("{:+.6}", t.last_delta()),
+                t.max_abs_diff.map_or_else(|| "-".to_string(), |(_, v)| format!("{v:.6}")),
+                opt(t.max_abs_diff.map(|(step, _)| step)),
+                opt(t.first_divergence),
+            ])
+        }
+        let widths: Vec<usize> = (0..header.len())
+            .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+            .collect();
+        for row in rows.iter() {
+            let mut cells = vec![format!("{:<w$}", row[0], w = widths[0])];
+            for (cell, w) in row.iter().zip(widths.iter()).skip(1) {
+                cells.push(format!("{cell:>w$}"))
+            }
+            writeln!(w, "{}", cells.join("  "))?
+        }
+        for (name, tags) in [("a", &self.only_a), ("b", &self.only_b)] {
+            if !tags.is_empty() {
+                writeln!(w, "only in {name}: {}", tags.join(", "))?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tensorboard::Event;
+    use crate::writer::what;
+
+    fn accumulator(scalars: &[(&str, i64, f32)]) -> EventAccumulator {
+        let mut accumulator = EventAccumulator::default();
+        for &(tag, step, value) in scalars.iter() {
+            let what = Some(what::scalar(tag, value));
+            accumulator.add_event(Event { wall_time: 1., step, source_metadata: None, what })
+        }
+        accumulator
+    }
+
+    #[test]
+    fn tolerance() {
+        let a = accumulator(&[("loss", 0, 1.), ("loss", 1, 0.5), ("loss", 2, 0.25), ("a", 0, 0.)]);
+        let b =
+            accumulator(&[("loss", 1, 0.500001), ("loss", 2, 0.3), ("loss", 3, 0.), ("b", 0, 0.)]);
+        let d = diff(&a, &b, &DiffOptions::default());
+        assert_eq!((d.only_a, d.only_b), (vec!["a".to_string()], vec!["b".to_string()]));
+        let t = &d.tags[0];
+        assert_eq!((t.common_steps, t.last_a, t.last_b), (2, (2, 0.25), (3, 0.)));
+        assert_eq!(t.first_divergence, Some(2));
+        let (step, max) = t.max_abs_diff.unwrap();
+        assert_eq!(step, 2);
+        assert!((max - 0.05).abs() < 1e-6);
+
+        let opts = DiffOptions { atol: 0.1, ..Default::default() };
+        let opts = DiffOptions { tag_filter: Some(regex::Regex::new("loss").unwrap()), ..opts };
+        let d = diff(&a, &b, &opts);
+        assert!(d.only_a.is_empty() && d.tags[0].first_divergence.is_none());
+        assert!(d.is_same());
+
+        // The relative tolerance uses the values from b.
+        let (a, b) = (accumulator(&[("t", 0, 0.)]), accumulator(&[("t", 0, 1.)]));
+        let opts = DiffOptions { atol: 0., rtol: 1., ..Default::default() };
+        assert!(diff(&a, &b, &opts).is_same());
+        assert!(!diff(&b, &a, &opts).is_same());
+    }
+
+    #[test]
+    fn load_truncated() {
+        let dir = crate::test_utils::temp_dir("diff-load-truncated");
+        let path = dir.join("events.out.tfevents.1.test");
+        let data = crate::test_utils::scalar_events(&[("a", 1, 1.), ("a", 2, 2.)]);
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        for path in [path.as_path(), dir.as_path()] {
+            let run = load_run(path).unwrap();
+            assert_eq!(series(&run, "a").into_iter().collect::<Vec<_>>(), [(1, 1.)]);
+        }
+        // The other errors are still reported.
+        let mut corrupted = data.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(load_run(&path).is_err());
+        assert!(load_run(&dir).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn non_finite() {
+        let (nan, inf) = (f32::NAN, f32::INFINITY);
+        let a = accumulator(&[
+            ("t", 0, nan),
+            ("t", 1, inf),
+            ("t", 2, -inf),
+            ("t", 3, nan),
+            ("t", 4, inf),
+            ("t", 5, 1.),
+        ]);
+        let b = accumulator(&[
+            ("t", 0, nan),
+            ("t", 1, inf),
+            ("t", 2, inf),
+            ("t", 3, 1.),
+            ("t", 4, 1.),
+            ("t", 5, nan),
+        ]);
+        let steps = |range: std::ops::Range<usize>| {
+            let a = series(&a, "t").into_iter().skip(range.start).take(range.len()).collect();
+            let b = series(&b, "t").into_iter().skip(range.start).take(range.len()).collect();
+            tag_diff("t", &a, &b, &DiffOptions::default())
+        };
+        let t = steps(0..2);
+        assert_eq!((t.first_divergence, t.max_abs_diff), (None, Some((0, 0.))));
+        for step in 2..6 {
+            let t = steps(step..step + 1);
+            assert_eq!(t.first_divergence, Some(step as i64));
+            assert_eq!(t.max_abs_diff, Some((step as i64, f64::INFINITY)));
+        }
+        let t = steps(0..6);
+        assert_eq!(t.first_divergence, Some(2));
+        let json = diff(&a, &b, &DiffOptions::default()).to_json();
+        assert_eq!(json["tags"][0]["last_a"]["value"], 1.);
+        assert_eq!(json["tags"][0]["last_b"]["value"], "NaN");
+    }
+}
@@ -1,3 +1,23 @@
+/// Location of a record in an event file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordPos {
+    /// Path of the event file when known.
+    pub path: Option<std::path::PathBuf>,
+    /// Byte offset of the start of the record.
+    pub offset: u64,
+    /// Ordinal of the record in the file, starting from 0.
+    pub index: u64,
+}
+
+impl std::fmt::Display for RecordPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path.display())?
+        }
+        write!(f, "record {} at offset {}", self.index, self.offset)
+    }
+}
+
 /// Main library error type.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -17,11 +37,34 @@ pub enum Error {
     #[error(transparent)]
     ProstEncode(#[from] prost::EncodeError),
 
-    #[error("crc mismatch, file: {file_crc} computed: {computed_crc}")]
-    LenCrcMismatch { file_crc: u32, computed_crc: u32 },
+    /// The crc of the record length does not match, the rest of the file cannot be
+    /// decoded reliably.
+    #[error("{pos}: length crc mismatch, file: {file_crc} computed: {computed_crc}")]
+    LenCrcMismatch { file_crc: u32, computed_crc: u32, pos: RecordPos },
+
+    /// The crc of the record payload does not match.
+    #[error("{pos}: crc mismatch, file: {file_crc} computed: {computed_crc}, payload len: {len}")]
+    CrcMismatch { file_crc: u32, computed_crc: u32, len: u64, pos: RecordPos },
+
+    /// The file ends in the middle of a record. `expected_len` is the payload length from
+    /// the record header, `None` when the header itself is truncated, and `actual_len` is
+    /// the number of payload bytes available. When both are equal, only the payload crc is
+    /// missing.
+    #[error("{pos}: {}", truncated_msg(.expected_len, .actual_len))]
+    TruncatedRecord { expected_len: Option<u64>, actual_len: u64, pos: RecordPos },
 
-    #[error("crc mismatch, file: {file_crc} computed: {computed_crc}")]
-    CrcMismatch { file_crc: u32, computed_crc: u32 },
+    /// The record is malformed, e.g. its length exceeds the protobuf size limit or its
+    /// payload is not valid protobuf.
+    #[error("{pos}: invalid record, {msg}")]
+    InvalidRecord { msg: String, pos: RecordPos },
+
+    /// I/O error while reading a record.
+    #[error("{pos}: {source}")]
+    RecordIo { source: std::io::Error, pos: RecordPos },
+
+    /// Protobuf decode error for a record with a valid crc.
+    #[error("{pos}: {source}")]
+    RecordDecode { source: prost::DecodeError, pos: RecordPos },
 
     /// Arbitrary errors wrapping.
     #[error(transparent)]
@@ -32,6 +75,14 @@ pub enum Error {
     Msg(String),
 }
 
+fn truncated_msg(expected_len: &Option<u64>, actual_len: &u64) -> String {
+    match expected_len {
+        None => "truncated record header".to_string(),
+        Some(len) if len == actual_len => format!("truncated crc after a {len} bytes payload"),
+        Some(len) => format!("truncated record, expected {len} payload bytes, got {actual_len}"),
+    }
+}
+
 #[macro_export]
 macro_rules! bail {
     ($msg:literal $(,)?) => {
@@ -53,6 +104,19 @@ impl Error {
     pub fn msg(err: impl std::error::Error + Send + Sync + 'static) -> Self {
         Self::Msg(err.to_string())
     }
+
+    /// The position of the record for the errors that occurred while reading a record.
+    pub fn record_pos(&self) -> Option<&RecordPos> {
+        match self {
+            Self::LenCrcMismatch { pos, .. }
+            | Self::CrcMismatch { pos, .. }
+            | Self::TruncatedRecord { pos, .. }
+            | Self::RecordIo { pos, .. }
+            | Self::RecordDecode { pos, .. }
+            | Self::InvalidRecord { pos, .. } => Some(pos),
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,348 @@
+// Export of the scalar and tensor series as tables, either in long format with one row
+// per (run, tag, step, wall_time, value) or pivoted in wide format with one column per
+// tag, and written as CSV or JSON Lines.
+use crate::accumulator::tensor_to_f64;
+use crate::{EventAccumulator, Result, SummaryReader};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// One row per value with the run, tag, step, wall_time, and value columns.
+    Long,
+    /// One row per run and step with a column per tag. The tags named `run`, `step`, or
+    /// `wall_time` cannot be exported in this layout.
+    Wide,
+}
+
+/// How to handle the NaN and infinite values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonFinite {
+    /// Write them as `NaN`, `inf`, and `-inf` in CSV and as the `"NaN"`, `"Infinity"`, and
+    /// `"-Infinity"` strings in JSON.
+    Keep,
+    /// Write them as an empty field in CSV and as `null` in JSON.
+    Null,
+    /// Do not output them, in the wide layout this results in an empty cell.
+    Skip,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: Format,
+    pub layout: Layout,
+    /// Only export the tags matching this regex.
+    pub tag_filter: Option<regex::Regex>,
+    pub non_finite: NonFinite,
+    /// Export the numeric tensors, the tensors with multiple elements result in one series
+    /// per element with the `{tag}/{index}` tag.
+    pub tensors: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::Csv,
+            layout: Layout::Long,
+            tag_filter: None,
+            non_finite: NonFinite::Keep,
+            tensors: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub run: String,
+    pub tag: String,
+    pub step: i64,
+    pub wall_time: f64,
+    pub value: f64,
+}
+
+impl ExportOptions {
+    fn keep_tag(&self, tag: &str) -> bool {
+        self.tag_filter.as_ref().is_none_or(|re| re.is_match(tag))
+    }
+}
+
+/// Extract the rows for a run, in long format.
+pub fn rows(run: &str, accumulator: &EventAccumulator, opts: &ExportOptions) -> Vec<Row> {
+    let mut rows = vec![];
+    for tag in accumulator.scalar_tags().filter(|tag| opts.keep_tag(tag)) {
+        for e in accumulator.scalars(tag).unwrap_or_default() {
+            let (step, wall_time, value) = (e.step, e.wall_time, e.value);
+            rows.push(Row { run: run.to_string(), tag: tag.to_string(), step, wall_time, value })
+        }
+    }
+    if opts.tensors {
+        for tag in accumulator.tensor_tags() {
+            for e in accumulator.tensors(tag).unwrap_or_default() {
+                let values = match tensor_to_f64(&e.value) {
+                    None => continue,
+                    Some(values) => values,
+                };
+                let single = values.len() == 1;
+                for (index, value) in values.into_iter().enumerate() {
+                    let tag = if single { tag.to_string() } else { format!("{tag}/{index}") };
+                    if opts.keep_tag(&tag) {
+                        let (step, wall_time) = (e.step, e.wall_time);
+                        rows.push(Row { run: run.to_string(), tag, step, wall_time, value })
+                    }
+                }
+            }
+        }
+    }
+    if opts.non_finite == NonFinite::Skip {
+        rows.retain(|r| r.value.is_finite())
+    }
+    rows
+}
+
+// Values that have been converted from f32 are formatted as f32 so that they use the
+// shortest representation, e.g. 0.1 rather than 0.10000000149011612.
+fn format_value(v: f64) -> String {
+    if v as f32 as f64 == v {
+        (v as f32).to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+fn csv_value(v: Option<f64>, opts: &ExportOptions) -> String {
+    match v {
+        None => String::new(),
+        Some(v) if v.is_finite() || opts.non_finite == NonFinite::Keep => format_value(v),
+        Some(_) => String::new(),
+    }
+}
+
+fn json_value(v: Option<f64>, opts: &ExportOptions) -> serde_json::Value {
+    match v {
+        None => serde_json::Value::Null,
+        Some(v) if v.is_finite() => {
+            let v = format_value(v).parse().unwrap_or(v);
+            serde_json::Number::from_f64(v).map_or(serde_json::Value::Null, |v| v.into())
+        }
+        Some(v) => match opts.non_finite {
+            NonFinite::Null | NonFinite::Skip => serde_json::Value::Null,
+            NonFinite::Keep if v.is_nan() => "NaN".into(),
+            NonFinite::Keep if v > 0. => "Infinity".into(),
+            NonFinite::Keep => "-Infinity".into(),
+        },
+    }
+}
+
+// A table with some header and rows of values, the first columns being strings.
+struct Table {
+    header: Vec<String>,
+    rows: Vec<(Vec<serde_json::Value>, Vec<Option<f64>>)>,
+}
+
+impl Table {
+    fn long(rows: &[Row]) -> Self {
+        let header = ["run", "tag", "step", "wall_time", "value"].map(|v| v.to_string()).to_vec();
+        let rows = rows
+            .iter()
+            .map(|r| {
+                let keys = vec![r.run.as_str().into(), r.tag.as_str().into(), r.step.into()];
+                (keys, vec![Some(r.wall_time), Some(r.value)])
+            })
+            .collect();
+        Self { header, rows }
+    }
+
+    fn wide(rows: &[Row]) -> Result<Self> {
+        let mut header = ["run", "step", "wall_time"].map(|v| v.to_string()).to_vec();
+        let tags = rows.iter().map(|r| r.tag.as_str()).collect::<BTreeSet<_>>();
+        if let Some(tag) = tags.iter().find(|&&tag| header.iter().any(|h| h == tag)) {
+            crate::bail!("tag {tag} conflicts with the {tag} column of the wide layout")
+        }
+        let tag_index = tags.iter().enumerate().map(|(i, &t)| (t, i)).collect::<BTreeMap<_, _>>();
+        // The minimum wall time and the value for each tag, indexed by run and step.
+        type WideRow = (f64, Vec<Option<f64>>);
+        let mut table: BTreeMap<(&str, i64), WideRow> = BTreeMap::new();
+        for r in rows.iter() {
+            let (wall_time, values) = table
+                .entry((r.run.as_str(), r.step))
+                .or_insert_with(|| (r.wall_time, vec![None; tags.len()]));
+            *wall_time = wall_time.min(r.wall_time);
+            values[tag_index[r.tag.as_str()]] = Some(r.value);
+        }
+        header.extend(tags.iter().map(|v| v.to_string()));
+        let rows = table
+            .into_iter()
+            .map(|((run, step), (wall_time, values))| {
+                let mut row = vec![Some(wall_time)];
+                row.extend(values);
+                (vec![run.into(), step.into()], row)
+            })
+            .collect();
+        Ok(Self { header, rows })
+    }
+}
+
+/// Write some rows using the format and layout from the options.
+pub fn write_rows<W: Write>(rows: &[Row], w: W, opts: &ExportOptions) -> Result<()> {
+    let table = match opts.layout {
+        Layout::Long => Table::long(rows),
+        Layout::Wide => Table::wide(rows)?,
+    };
+    match opts.format {
+        Format::Csv => {
+            let mut w = csv::Writer::from_writer(w);
+            w.write_record(&table.header).map_err(crate::Error::wrap)?;
+            for (keys, values) in table.rows.iter() {
+                let keys = keys.iter().map(|k| match k {
+                    serde_json::Value::String(s) => s.to_string(),
+                    k => k.to_string(),
+                });
+                let values = values.iter().map(|&v| csv_value(v, opts));
+                w.write_record(keys.chain(values)).map_err(crate::Error::wrap)?;
+            }
+            w.flush()?;
+        }
+        Format::JsonLines => {
+            let mut w = std::io::BufWriter::new(w);
+            for (keys, values) in table.rows.into_iter() {
+                let values = values.iter().map(|&v| json_value(v, opts));
+                let row = table.header.iter().cloned().zip(keys.into_iter().chain(values));
+                let row = serde_json::Value::Object(row.collect());
+                serde_json::to_writer(&mut w, &row).map_err(crate::Error::wrap)?;
+                w.write_all(b"\n")?;
+            }
+            w.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Export the events from a reader, using `run` as the run name. A truncated record at the
+/// end of the file is ignored.
+pub fn export_reader<R: std::io::Read, W: Write>(
+    run: &str,
+    reader: SummaryReader<R>,
+    w: W,
+    opts: &ExportOptions,
+) -> Result<()> {
+    let mut accumulator = EventAccumulator::default();
+    accumulator.load_complete_records(reader)?;
+    write_rows(&rows(run, &accumulator, opts), w, opts)
+}
+
+/// Export all the runs from a log directory. The runs that cannot be fully read result in
+/// an error, except for a truncated record at the end of a file which is usually being
+/// written: as when following a live run, the events before this record are exported.
+pub fn export_logdir<P: AsRef<std::path::Path>, W: Write>(
+    logdir: P,
+    w: W,
+    opts: &ExportOptions,
+) -> Result<()> {
+    let mut all_rows = vec![];
+    for run in crate::logdir::load(logdir)? {
+        let run = run.check_errors()?;
+        all_rows.extend(rows(&run.name, &run.accumulator, opts))
+    }
+    write_rows(&all_rows, w, opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tensorboard::Event;
+    use crate::writer::what;
+
+    fn scalar(wall_time: f64, step: i64, tag: &str, value: f32) -> Event {
+        Event { wall_time, step, source_metadata: None, what: Some(what::scalar(tag, value)) }
+    }
+
+    fn accumulator() -> EventAccumulator {
+        let mut accumulator = EventAccumulator::default();
+        accumulator.add_event(scalar(10., 0, "loss", 1.5));
+        accumulator.add_event(scalar(10.5, 0, "acc", 0.1));
+        accumulator.add_event(scalar(11., 1, "loss", f32::NAN));
+        accumulator.add_event(scalar(12., 2, "loss", f32::INFINITY));
+        accumulator
+    }
+
+    fn export(opts: &ExportOptions) -> String {
+        let mut out = vec![];
+        write_rows(&rows("run", &accumulator(), opts), &mut out, opts).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn long_csv() {
+        let opts = ExportOptions::default();
+        let expected = "run,tag,step,wall_time,value\n\
+                        run,acc,0,10.5,0.1\n\
+                        run,loss,0,10,1.5\n\
+                        run,loss,1,11,NaN\n\
+                        run,loss,2,12,inf\n";
+        assert_eq!(export(&opts), expected);
+        let opts = ExportOptions { non_finite: NonFinite::Skip, ..Default::default() };
+        assert_eq!(export(&opts).lines().count(), 3);
+        let opts = ExportOptions { tag_filter: Some(regex::Regex::new("^l").unwrap()), ..opts };
+        assert_eq!(export(&opts), "run,tag,step,wall_time,value\nrun,loss,0,10,1.5\n");
+    }
+
+    #[test]
+    fn wide_json_lines() {
+        let opts =
+            ExportOptions { format: Format::JsonLines, layout: Layout::Wide, ..Default::default() };
+        let expected = r#"{"run":"run","step":0,"wall_time":10.0,"acc":0.1,"loss":1.5}
+{"run":"run","step":1,"wall_time":11.0,"acc":null,"loss":"NaN"}
+{"run":"run","step":2,"wall_time":12.0,"acc":null,"loss":"Infinity"}
+"#;
+        assert_eq!(export(&opts), expected);
+        let opts = ExportOptions { non_finite: NonFinite::Null, ..opts };
+        assert!(export(&opts).contains(r#""step":2,"wall_time":12.0,"acc":null,"loss":null"#));
+    }
+
+    #[test]
+    fn wide_column_conflict() {
+        let mut accumulator = accumulator();
+        accumulator.add_event(scalar(13., 3, "step", 3.));
+        let rows = rows("run", &accumulator, &ExportOptions::default());
+        for format in [Format::Csv, Format::JsonLines] {
+            let opts = ExportOptions { format, layout: Layout::Wide, ..Default::default() };
+            let err = write_rows(&rows, &mut vec![], &opts).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "tag step conflicts with the step column of the wide layout"
+            );
+        }
+        // The long layout has a column for the tags.
+        let mut out = vec![];
+        write_rows(&rows, &mut out, &ExportOptions::default()).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with("run,step,3,13,3\n"));
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let dir = crate::test_utils::temp_dir("export-truncated");
+        let data = crate::test_utils::scalar_events(&[("loss", 0, 1.), ("loss", 1, 0.5)]);
+        let path = dir.join("events.out.tfevents.1.test");
+        std::fs::write(&path, &data[..data.len() - 3]).unwrap();
+        let mut out = vec![];
+        export_logdir(&dir, &mut out, &ExportOptions::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 2, "{out}");
+        assert!(out.lines().nth(1).unwrap().starts_with(".,loss,0,"));
+
+        // Other errors are still reported.
+        let mut data = data;
+        let len = data.len();
+        data[len - 10] ^= 1;
+        std::fs::write(&path, &data).unwrap();
+        let err = export_logdir(&dir, &mut vec![], &ExportOptions::default()).unwrap_err();
+        assert!(matches!(err, crate::Error::CrcMismatch { .. }));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
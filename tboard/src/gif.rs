@@ -0,0 +1,498 @@
+// A minimal animated GIF encoder, used to write frame sequences as image summaries that
+// the Images dashboard plays as videos, similar to the `add_video` method of the PyTorch
+// `SummaryWriter`. All the frames share a single palette of at most 256 colors: the
+// frames with few colors are encoded exactly, otherwise the palette is computed from the
+// colors of all the frames by splitting the color boxes with the largest error, followed by
+// a k-means refinement.
+use crate::grid::Pixel;
+use crate::Result;
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+const MAX_CODES: u16 = 4096;
+
+// Packs the variable width LZW codes, least significant bits first.
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    n_bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, code_size: u32) {
+        self.acc |= (code as u32) << self.n_bits;
+        self.n_bits += code_size;
+        while self.n_bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n_bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n_bits > 0 {
+            self.out.push(self.acc as u8)
+        }
+        self.out
+    }
+}
+
+// The GIF flavor of LZW, the indexes must be below `1 << min_code_size`.
+fn lzw_encode(indexes: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear_code = 1u16 << min_code_size;
+    let end_code = clear_code + 1;
+    let mut bits = BitWriter { out: vec![], acc: 0, n_bits: 0 };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end_code + 1;
+    bits.write(clear_code, code_size);
+    let mut prefix = match indexes.first() {
+        None => {
+            bits.write(end_code, code_size);
+            return bits.finish();
+        }
+        Some(&index) => index as u16,
+    };
+    for &index in indexes[1..].iter() {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        bits.write(prefix, code_size);
+        // The decoder adds its codes one step behind the encoder, hence the width only
+        // increases once the code that does not fit has been used.
+        if next_code == 1 << code_size && code_size < 12 {
+            code_size += 1
+        }
+        if next_code < MAX_CODES {
+            codes.insert((prefix, index), next_code);
+            next_code += 1;
+        } else {
+            bits.write(clear_code, code_size);
+            codes.clear();
+            code_size = min_code_size + 1;
+            next_code = end_code + 1;
+        }
+        prefix = index as u16;
+    }
+    bits.write(prefix, code_size);
+    if next_code == 1 << code_size && code_size < 12 {
+        code_size += 1
+    }
+    bits.write(end_code, code_size);
+    bits.finish()
+}
+
+// The colors with the same 5 most significant bits per channel share a bin for the
+// palette computation.
+fn bin(color: [u8; 3]) -> usize {
+    ((color[0] as usize >> 3) << 10) | ((color[1] as usize >> 3) << 5) | (color[2] as usize >> 3)
+}
+
+// The number of pixels in each bin with the sums and sums of squares of their channels.
+struct Bins {
+    counts: Vec<u64>,
+    sums: Vec<[f64; 3]>,
+    sums_sq: Vec<[f64; 3]>,
+}
+
+impl Bins {
+    fn mean(&self, bin: usize) -> [f64; 3] {
+        self.sums[bin].map(|s| s / self.counts[bin] as f64)
+    }
+}
+
+// A set of bins with their number of pixels, the sums of their channels, and the squared
+// error of the pixels to their mean color.
+struct ColorBox {
+    bins: Vec<usize>,
+    count: u64,
+    sum: [f64; 3],
+    error: f64,
+}
+
+impl ColorBox {
+    fn new(bins: Vec<usize>, stats: &Bins) -> Self {
+        let (mut count, mut sum, mut sum_sq) = (0, [0.; 3], [0.; 3]);
+        for &bin in bins.iter() {
+            count += stats.counts[bin];
+            for c in 0..3 {
+                sum[c] += stats.sums[bin][c];
+                sum_sq[c] += stats.sums_sq[bin][c];
+            }
+        }
+        let error = (0..3).map(|c| sum_sq[c] - sum[c] * sum[c] / count.max(1) as f64).sum();
+        Self { bins, count, sum, error }
+    }
+
+    // Splits the box along the channel with the largest variance, at the position that
+    // minimizes the squared error of the two halves.
+    fn split(mut self, stats: &Bins) -> (Self, Self) {
+        let n = self.count as f64;
+        let variance = |c: usize| {
+            let sum_sq: f64 = self.bins.iter().map(|&bin| stats.sums_sq[bin][c]).sum();
+            sum_sq / n - (self.sum[c] / n).powi(2)
+        };
+        let c = (0..3).max_by(|&c1, &c2| variance(c1).total_cmp(&variance(c2))).unwrap_or(0);
+        self.bins.sort_by(|&b1, &b2| stats.mean(b1)[c].total_cmp(&stats.mean(b2)[c]));
+        // The squared error of a box is the sum of the squares minus |sum|^2 / count, so the
+        // best split maximizes |sum|^2 / count over both halves.
+        let score =
+            |sum: [f64; 3], count: u64| sum.iter().map(|s| s * s).sum::<f64>() / count as f64;
+        let (mut count, mut sum) = (0, [0.; 3]);
+        let mut best = (f64::NEG_INFINITY, 1);
+        for (index, &bin) in self.bins[..self.bins.len() - 1].iter().enumerate() {
+            count += stats.counts[bin];
+            for (s, v) in sum.iter_mut().zip(stats.sums[bin].iter()) {
+                *s += v
+            }
+            let rest = [0, 1, 2].map(|c| self.sum[c] - sum[c]);
+            let value = score(sum, count) + score(rest, self.count - count);
+            if value > best.0 {
+                best = (value, index + 1)
+            }
+        }
+        let upper = self.bins.split_off(best.1);
+        (Self::new(self.bins, stats), Self::new(upper, stats))
+    }
+}
+
+fn nearest(palette: &[[f64; 3]], color: [f64; 3]) -> usize {
+    let dist = |p: &[f64; 3]| (0..3).map(|c| (p[c] - color[c]).powi(2)).sum::<f64>();
+    let dists = palette.iter().map(dist).enumerate();
+    dists.min_by(|(_, d1), (_, d2)| d1.total_cmp(d2)).map_or(0, |(index, _)| index)
+}
+
+// Computes a palette by repeatedly splitting the box of colors with the largest squared
+// error, the palette is then refined with a few k-means iterations over the bins.
+fn variance_cut(pixels: &[[u8; 3]]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    const KMEANS_ITERATIONS: usize = 4;
+    let mut stats = Bins {
+        counts: vec![0; 1 << 15],
+        sums: vec![[0.; 3]; 1 << 15],
+        sums_sq: vec![[0.; 3]; 1 << 15],
+    };
+    for &color in pixels.iter() {
+        let bin = bin(color);
+        stats.counts[bin] += 1;
+        for (c, &v) in color.iter().enumerate() {
+            let v = v as f64;
+            stats.sums[bin][c] += v;
+            stats.sums_sq[bin][c] += v * v;
+        }
+    }
+    let bins: Vec<usize> = (0..1 << 15).filter(|&bin| stats.counts[bin] > 0).collect();
+    let mut boxes = vec![ColorBox::new(bins.clone(), &stats)];
+    while boxes.len() < 256 {
+        let best = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.bins.len() > 1)
+            .max_by(|(_, b1), (_, b2)| b1.error.total_cmp(&b2.error));
+        let index = match best {
+            Some((index, b)) if b.error > 0. => index,
+            _ => break,
+        };
+        let (lower, upper) = boxes.swap_remove(index).split(&stats);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+    let mut palette: Vec<[f64; 3]> =
+        boxes.iter().map(|b| b.sum.map(|s| s / b.count.max(1) as f64)).collect();
+    let mut bin_index = vec![0u8; 1 << 15];
+    for iteration in 0..=KMEANS_ITERATIONS {
+        let mut sums = vec![([0.; 3], 0u64); palette.len()];
+        for &bin in bins.iter() {
+            let index = nearest(&palette, stats.mean(bin));
+            bin_index[bin] = index as u8;
+            let (sum, count) = &mut sums[index];
+            for (s, v) in sum.iter_mut().zip(stats.sums[bin]) {
+                *s += v
+            }
+            *count += stats.counts[bin];
+        }
+        if iteration == KMEANS_ITERATIONS {
+            break;
+        }
+        // The colors without any pixel are kept as is.
+        for (color, (sum, count)) in palette.iter_mut().zip(sums.iter()) {
+            if *count > 0 {
+                *color = sum.map(|s| s / *count as f64)
+            }
+        }
+    }
+    let palette = palette.iter().map(|color| color.map(|v| v.round() as u8)).collect();
+    let indexes = pixels.iter().map(|&color| bin_index[bin(color)]).collect();
+    (palette, indexes)
+}
+
+// Returns the palette and the palette index of each pixel.
+fn quantize(pixels: &[[u8; 3]]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut indexes = Vec::with_capacity(pixels.len());
+    for &color in pixels.iter() {
+        let next_index = colors.len();
+        match colors.get(&color) {
+            Some(&index) => indexes.push(index),
+            None if next_index < 256 => {
+                colors.insert(color, next_index as u8);
+                indexes.push(next_index as u8)
+            }
+            None => return variance_cut(pixels),
+        }
+    }
+    let mut palette = vec![[0u8; 3]; colors.len()];
+    for (color, index) in colors.into_iter() {
+        palette[index as usize] = color
+    }
+    (palette, indexes)
+}
+
+/// Write some frames as a GIF looping forever, `frames` contains the frames one after the
+/// other, each in HWC order with 1 (grayscale) or 3 (RGB) channels. The frame delay is
+/// rounded to hundredths of a second as required by the format, and is at least 0.02s as
+/// most viewers slow down the GIFs with shorter delays.
+#[allow(clippy::too_many_arguments)]
+pub fn write_frames_as_gif<W: Write, T: Pixel>(
+    w: &mut W,
+    frames: &[T],
+    n_frames: usize,
+    height: usize,
+    width: usize,
+    channels: usize,
+    fps: f32,
+) -> Result<()> {
+    let len = n_frames.checked_mul(height).and_then(|v| v.checked_mul(width));
+    if len.and_then(|v| v.checked_mul(channels)) != Some(frames.len()) {
+        crate::bail!(
+            "unexpected number of values {}, expected {n_frames}x{height}x{width}x{channels}",
+            frames.len()
+        )
+    }
+    if channels != 1 && channels != 3 {
+        crate::bail!("unsupported number of channels {channels}, expected 1 or 3")
+    }
+    if n_frames == 0 || width == 0 || height == 0 || width > 65535 || height > 65535 {
+        crate::bail!("unsupported video shape {n_frames}x{height}x{width}")
+    }
+    if !(fps.is_finite() && fps > 0.) {
+        crate::bail!("unexpected fps {fps}")
+    }
+    let delay = (100. / fps).round().clamp(2., 65535.) as u16;
+    let pixels: Vec<[u8; 3]> = match channels {
+        1 => frames.iter().map(|v| [v.to_u8(); 3]).collect(),
+        _ => frames.chunks_exact(3).map(|v| [v[0].to_u8(), v[1].to_u8(), v[2].to_u8()]).collect(),
+    };
+    let (palette, indexes) = quantize(&pixels);
+    // The color table has 2^(table_bits + 1) entries.
+    let table_bits = (palette.len().max(2).next_power_of_two().trailing_zeros() - 1) as u8;
+    let min_code_size = (table_bits as u32 + 1).max(2);
+
+    w.write_all(b"GIF89a")?;
+    // Logical screen descriptor, with a global color table using 8 bits per channel.
+    w.write_all(&(width as u16).to_le_bytes())?;
+    w.write_all(&(height as u16).to_le_bytes())?;
+    w.write_all(&[0x80 | 0x70 | table_bits, 0, 0])?;
+    for index in 0..2usize << table_bits {
+        w.write_all(palette.get(index).unwrap_or(&[0, 0, 0]))?
+    }
+    if n_frames > 1 {
+        // Netscape application extension, 0 for looping forever.
+        w.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+    }
+    for frame in indexes.chunks_exact(height * width) {
+        // Graphic control extension with the frame delay.
+        w.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+        w.write_all(&delay.to_le_bytes())?;
+        w.write_all(&[0x00, 0x00])?;
+        // Image descriptor covering the whole screen, followed by the data sub-blocks.
+        w.write_all(&[0x2c, 0, 0, 0, 0])?;
+        w.write_all(&(width as u16).to_le_bytes())?;
+        w.write_all(&(height as u16).to_le_bytes())?;
+        w.write_all(&[0x00, min_code_size as u8])?;
+        for block in lzw_encode(frame, min_code_size).chunks(255) {
+            w.write_all(&[block.len() as u8])?;
+            w.write_all(block)?
+        }
+        w.write_all(&[0x00])?;
+    }
+    w.write_all(&[0x3b])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decodes the GIF flavor of LZW.
+    fn lzw_decode(data: &[u8], min_code_size: u32) -> Vec<u8> {
+        let clear_code = 1usize << min_code_size;
+        let mut table: Vec<Vec<u8>> = vec![];
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear_code + 2).map(|i| vec![i as u8]).collect();
+        };
+        reset(&mut table);
+        let (mut code_size, mut pos, mut out) = (min_code_size + 1, 0, vec![]);
+        let mut prev: Option<Vec<u8>> = None;
+        loop {
+            let mut code = 0;
+            for i in 0..code_size as usize {
+                let bit = (data[(pos + i) / 8] >> ((pos + i) % 8)) & 1;
+                code |= (bit as usize) << i;
+            }
+            pos += code_size as usize;
+            if code == clear_code {
+                reset(&mut table);
+                code_size = min_code_size + 1;
+                prev = None;
+                continue;
+            }
+            if code == clear_code + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [prev.as_slice(), &prev[..1]].concat(),
+                (None, None) => panic!("invalid code {code}"),
+            };
+            out.extend_from_slice(&entry);
+            if let Some(prev) = prev {
+                if table.len() < MAX_CODES as usize {
+                    table.push([prev.as_slice(), &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << code_size && code_size < 12 {
+                code_size += 1
+            }
+            prev = Some(entry);
+        }
+    }
+
+    // Returns the width, height, frame delays, and RGB pixels of each frame.
+    fn decode(gif: &[u8]) -> (usize, usize, Vec<u16>, Vec<Vec<[u8; 3]>>) {
+        assert_eq!(&gif[..6], b"GIF89a");
+        let u16_at = |pos: usize| u16::from_le_bytes([gif[pos], gif[pos + 1]]);
+        let (width, height) = (u16_at(6) as usize, u16_at(8) as usize);
+        let table_len = 2 << (gif[10] & 7);
+        let palette: Vec<[u8; 3]> =
+            gif[13..13 + 3 * table_len].chunks(3).map(|c| [c[0], c[1], c[2]]).collect();
+        let (mut pos, mut delays, mut frames) = (13 + 3 * table_len, vec![], vec![]);
+        loop {
+            match gif[pos] {
+                0x3b => return (width, height, delays, frames),
+                0x21 => {
+                    if gif[pos + 1] == 0xf9 {
+                        delays.push(u16_at(pos + 4))
+                    }
+                    pos += 2;
+                    while gif[pos] != 0 {
+                        pos += gif[pos] as usize + 1
+                    }
+                    pos += 1;
+                }
+                0x2c => {
+                    assert_eq!((u16_at(pos + 5), u16_at(pos + 7)), (width as u16, height as u16));
+                    let min_code_size = gif[pos + 10] as u32;
+                    pos += 11;
+                    let mut data = vec![];
+                    while gif[pos] != 0 {
+                        data.extend_from_slice(&gif[pos + 1..pos + 1 + gif[pos] as usize]);
+                        pos += gif[pos] as usize + 1
+                    }
+                    pos += 1;
+                    let indexes = lzw_decode(&data, min_code_size);
+                    assert_eq!(indexes.len(), width * height);
+                    frames.push(indexes.iter().map(|&i| palette[i as usize]).collect());
+                }
+                block => panic!("unexpected block {block:x} at {pos}"),
+            }
+        }
+    }
+
+    fn encode(
+        frames: &[u8],
+        n_frames: usize,
+        height: usize,
+        width: usize,
+        channels: usize,
+    ) -> Vec<u8> {
+        let mut gif = vec![];
+        write_frames_as_gif(&mut gif, frames, n_frames, height, width, channels, 10.).unwrap();
+        gif
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // Long enough for the codes to reach 12 bits and for the table to be reset.
+        let indexes: Vec<u8> = (0..100_000u64).map(|i| ((i * i) >> 7) as u8 & 3).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&indexes, 2), 2), indexes);
+        let indexes: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8 ^ (i >> 9) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&indexes, 8), 8), indexes);
+        assert!(lzw_decode(&lzw_encode(&[], 2), 2).is_empty());
+    }
+
+    #[test]
+    fn exact_colors() {
+        // Two 2x3 grayscale frames with few colors are encoded exactly.
+        let frames: Vec<u8> = (0..12).map(|i| i * 20).collect();
+        let gif = encode(&frames, 2, 2, 3, 1);
+        let (width, height, delays, decoded) = decode(&gif);
+        assert_eq!((width, height, delays), (3, 2, vec![10, 10]));
+        let expected: Vec<Vec<[u8; 3]>> =
+            frames.chunks(6).map(|f| f.iter().map(|&v| [v; 3]).collect()).collect();
+        assert_eq!(decoded, expected);
+        #[cfg(feature = "image")]
+        {
+            use image::AnimationDecoder;
+            let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(&gif)).unwrap();
+            let frames = decoder.into_frames().collect_frames().unwrap();
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[1].buffer().get_pixel(2, 1).0, [220, 220, 220, 255]);
+        }
+    }
+
+    #[test]
+    fn quantized_gradient() {
+        let (width, height) = (300, 200);
+        let mut pixels = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g) = (x * 255 / (width - 1), y * 255 / (height - 1));
+                pixels.extend_from_slice(&[r as u8, g as u8, ((r + 2 * g) / 3) as u8 ^ 0x55]);
+            }
+        }
+        let gif = encode(&pixels, 1, height, width, 3);
+        let (_, _, _, decoded) = decode(&gif);
+        let errors: Vec<u32> = pixels
+            .chunks(3)
+            .zip(decoded[0].iter())
+            .map(|(p, q)| (0..3).map(|c| p[c].abs_diff(q[c]) as u32).max().unwrap_or(0))
+            .collect();
+        let max = errors.iter().max().copied().unwrap_or(0);
+        let mean = errors.iter().sum::<u32>() as f64 / errors.len() as f64;
+        assert!(max <= 24, "max error {max}");
+        assert!(mean <= 8., "mean error {mean}");
+    }
+
+    #[test]
+    fn invalid_shapes() {
+        let mut gif = vec![];
+        let err = |gif: &mut Vec<u8>, n, h, w, c, fps| {
+            let frames = vec![0u8; 12];
+            write_frames_as_gif(gif, &frames, n, h, w, c, fps).unwrap_err().to_string()
+        };
+        assert_eq!(
+            err(&mut gif, 1, 2, 3, 2, 1.),
+            "unsupported number of channels 2, expected 1 or 3"
+        );
+        assert_eq!(
+            err(&mut gif, 2, 2, 3, 3, 1.),
+            "unexpected number of values 12, expected 2x2x3x3"
+        );
+        assert_eq!(err(&mut gif, 4, 1, 3, 1, 0.), "unexpected fps 0");
+        // The number of values would wrap around to 12 without the overflow checks.
+        let msg = err(&mut gif, usize::MAX / 2 + 2, 4, 3, 1, 1.);
+        assert!(msg.starts_with("unexpected number of values 12, expected"), "{msg}");
+        assert!(gif.is_empty());
+    }
+}
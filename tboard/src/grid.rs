@@ -0,0 +1,327 @@
+// Writing batches of images, either tiled in a single grid image similar to torchvision
+// `make_grid`, or as a multi-image summary with one value per image as done by the
+// tensorflow image summaries, the tags being `<tag>/image/<index>`.
+use crate::{tensorboard, EventWriter, Result};
+
+/// The element types that can be written as pixels, floats are expected to be between 0
+/// and 1 and are clamped.
+pub trait Pixel: Copy {
+    fn to_u8(self) -> u8;
+    fn to_f32(self) -> f32;
+}
+
+impl Pixel for u8 {
+    fn to_u8(self) -> u8 {
+        self
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+}
+
+impl Pixel for f32 {
+    fn to_u8(self) -> u8 {
+        (self.clamp(0., 1.) * 255.).round() as u8
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+impl Pixel for f64 {
+    fn to_u8(self) -> u8 {
+        (self.clamp(0., 1.) * 255.).round() as u8
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+}
+
+/// The dimension order of the images, for batches the batch dimension comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageLayout {
+    /// Height, width, channels, as used by most image libraries.
+    Hwc,
+    /// Channels, height, width, as used by PyTorch.
+    Chw,
+}
+
+/// Rescale the values of the images to the full pixel range.
+#[derive(Debug, Clone, Default)]
+pub struct Normalize {
+    /// The values mapped to black and white, the min and max of the values by default.
+    pub value_range: Option<(f32, f32)>,
+    /// Compute the min and max for each image rather than for the whole batch.
+    pub scale_each: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct GridOptions {
+    /// The number of images per row.
+    pub ncols: usize,
+    /// The padding between the images and around the grid, in pixels.
+    pub padding: usize,
+    pub pad_value: u8,
+    pub normalize: Option<Normalize>,
+}
+
+impl Default for GridOptions {
+    fn default() -> Self {
+        Self { ncols: 8, padding: 2, pad_value: 0, normalize: None }
+    }
+}
+
+/// A batch of images with the dimensions `shape`, in NCHW or NHWC order depending on
+/// `layout`.
+#[derive(Debug, Clone, Copy)]
+pub struct Batch<'a, T> {
+    pub data: &'a [T],
+    pub shape: [usize; 4],
+    pub layout: ImageLayout,
+}
+
+impl<T: Pixel> Batch<'_, T> {
+    /// The number of images, height, width, and channels.
+    pub fn dims(&self) -> (usize, usize, usize, usize) {
+        let [n, d1, d2, d3] = self.shape;
+        match self.layout {
+            ImageLayout::Hwc => (n, d1, d2, d3),
+            ImageLayout::Chw => (n, d2, d3, d1),
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        let (n, h, w, c) = self.dims();
+        let len = n.checked_mul(h).and_then(|v| v.checked_mul(w)).and_then(|v| v.checked_mul(c));
+        if len != Some(self.data.len()) {
+            crate::bail!("unexpected number of values {}, shape {:?}", self.data.len(), self.shape)
+        }
+        if n == 0 || h == 0 || w == 0 || !(1..=4).contains(&c) {
+            crate::bail!("unsupported image batch shape {:?}", self.shape)
+        }
+        Ok(())
+    }
+
+    fn image(&self, index: usize) -> impl Iterator<Item = T> + '_ {
+        let (_, h, w, c) = self.dims();
+        let image = &self.data[index * h * w * c..(index + 1) * h * w * c];
+        let layout = self.layout;
+        (0..h * w * c).map(move |i| match layout {
+            ImageLayout::Hwc => image[i],
+            ImageLayout::Chw => image[(i % c) * h * w + i / c],
+        })
+    }
+
+    /// The pixels of each image, in HWC order.
+    fn pixels(&self, normalize: Option<&Normalize>) -> Result<Vec<Vec<u8>>> {
+        self.check()?;
+        let n = self.shape[0];
+        let normalize = match normalize {
+            None => {
+                return Ok((0..n).map(|i| self.image(i).map(|v| v.to_u8()).collect()).collect())
+            }
+            Some(normalize) => normalize,
+        };
+        let range = |values: &mut dyn Iterator<Item = f32>| match normalize.value_range {
+            Some(range) => range,
+            None => values.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+                if v.is_nan() {
+                    (lo, hi)
+                } else {
+                    (lo.min(v), hi.max(v))
+                }
+            }),
+        };
+        let batch_range = range(&mut self.data.iter().map(|v| v.to_f32()));
+        let images = (0..n).map(|i| {
+            let (lo, hi) = match normalize.scale_each {
+                true => range(&mut self.image(i).map(|v| v.to_f32())),
+                false => batch_range,
+            };
+            let scale = 1. / (hi - lo).max(1e-5);
+            self.image(i).map(|v| ((v.to_f32() - lo) * scale).to_u8()).collect()
+        });
+        Ok(images.collect())
+    }
+}
+
+/// Tile a batch of images in a grid, returning the width, height, and HWC pixels of the
+/// grid which has the same number of channels as the images. A single image is returned as
+/// is, without padding.
+pub fn make_grid<T: Pixel>(
+    batch: &Batch<T>,
+    opts: &GridOptions,
+) -> Result<(usize, usize, Vec<u8>)> {
+    let images = batch.pixels(opts.normalize.as_ref())?;
+    let (n, h, w, c) = batch.dims();
+    let pad = if n == 1 { 0 } else { opts.padding };
+    let ncols = opts.ncols.clamp(1, n);
+    let nrows = n.div_ceil(ncols);
+    let side =
+        |count: usize, len: usize| count.checked_mul(len.checked_add(pad)?)?.checked_add(pad);
+    let (width, height) = match (side(ncols, w), side(nrows, h)) {
+        (Some(width), Some(height)) => (width, height),
+        _ => crate::bail!("grid too large for {n} images of {h}x{w} with padding {pad}"),
+    };
+    let len = match width.checked_mul(height).and_then(|v| v.checked_mul(c)) {
+        None => crate::bail!("grid too large for {n} images of {h}x{w} with padding {pad}"),
+        Some(len) => len,
+    };
+    let mut grid = vec![opts.pad_value; len];
+    for (index, image) in images.iter().enumerate() {
+        let x0 = pad + (index % ncols) * (w + pad);
+        let y0 = pad + (index / ncols) * (h + pad);
+        for (y, row) in image.chunks_exact(w * c).enumerate() {
+            let start = ((y0 + y) * width + x0) * c;
+            grid[start..start + w * c].copy_from_slice(row)
+        }
+    }
+    Ok((width, height, grid))
+}
+
+impl<W: std::io::Write> EventWriter<W> {
+    /// Write a batch of images as a single image tiled with `make_grid`, similar to the
+    /// `add_images` method of the PyTorch `SummaryWriter`.
+    pub fn write_image_grid<T: Pixel>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        batch: &Batch<T>,
+        opts: &GridOptions,
+    ) -> Result<()> {
+        let (width, height, pixels) = make_grid(batch, opts)?;
+        let channels = batch.dims().3;
+        self.write_image_pixels(step, tag, width, height, channels, &pixels)
+    }
+
+    /// Write a batch of images as a single summary with one image per value, using the
+    /// tags `<tag>/image/<index>`.
+    pub fn write_images<T: Pixel>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        batch: &Batch<T>,
+        normalize: Option<&Normalize>,
+    ) -> Result<()> {
+        use tensorboard::summary::value::Value;
+
+        let (_, height, width, channels) = batch.dims();
+        let mut values = vec![];
+        for (index, pixels) in batch.pixels(normalize)?.iter().enumerate() {
+            let data = crate::png::encode(width, height, channels, pixels)?;
+            let image = tensorboard::summary::Image {
+                width: width as i32,
+                height: height as i32,
+                colorspace: channels as i32,
+                encoded_image_string: data,
+            };
+            values.push(tensorboard::summary::Value {
+                node_name: "".to_string(),
+                tag: format!("{tag}/image/{index}"),
+                metadata: None,
+                value: Some(Value::Image(image)),
+            })
+        }
+        let summary = tensorboard::Summary { value: values };
+        self.write(step, tensorboard::event::What::Summary(summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(data: &[u8], shape: [usize; 4], layout: ImageLayout) -> Batch<'_, u8> {
+        Batch { data, shape, layout }
+    }
+
+    #[test]
+    fn grid() {
+        // Three 2x2 grayscale images with the values 1, 2, and 3.
+        let data = [[1; 4], [2; 4], [3; 4]].concat();
+        let opts = GridOptions { ncols: 2, padding: 1, pad_value: 9, normalize: None };
+        let (width, height, pixels) =
+            make_grid(&batch(&data, [3, 2, 2, 1], ImageLayout::Hwc), &opts).unwrap();
+        assert_eq!((width, height), (7, 7));
+        #[rustfmt::skip]
+        let expected = [
+            9, 9, 9, 9, 9, 9, 9,
+            9, 1, 1, 9, 2, 2, 9,
+            9, 1, 1, 9, 2, 2, 9,
+            9, 9, 9, 9, 9, 9, 9,
+            9, 3, 3, 9, 9, 9, 9,
+            9, 3, 3, 9, 9, 9, 9,
+            9, 9, 9, 9, 9, 9, 9,
+        ];
+        assert_eq!(pixels, expected);
+
+        // A single image is returned without padding.
+        let (width, height, pixels) =
+            make_grid(&batch(&data[..4], [1, 2, 2, 1], ImageLayout::Hwc), &opts).unwrap();
+        assert_eq!((width, height, pixels), (2, 2, vec![1; 4]));
+    }
+
+    #[test]
+    fn layouts_and_normalize() {
+        // A 1x2 RGB image in CHW order.
+        let chw = [10, 11, 20, 21, 30, 31];
+        let b = batch(&chw, [1, 3, 1, 2], ImageLayout::Chw);
+        assert_eq!(b.dims(), (1, 1, 2, 3));
+        let (_, _, pixels) = make_grid(&b, &GridOptions::default()).unwrap();
+        assert_eq!(pixels, [10, 20, 30, 11, 21, 31]);
+
+        let data = [0f32, 2., 1., 4.];
+        let b = Batch { data: &data, shape: [2, 1, 2, 1], layout: ImageLayout::Hwc };
+        let normalize = Normalize { value_range: None, scale_each: false };
+        assert_eq!(b.pixels(Some(&normalize)).unwrap(), [vec![0, 128], vec![64, 255]]);
+        let normalize = Normalize { value_range: None, scale_each: true };
+        assert_eq!(b.pixels(Some(&normalize)).unwrap(), [vec![0, 255], vec![0, 255]]);
+        assert_eq!(b.pixels(None).unwrap(), [vec![0, 255], vec![255, 255]]);
+    }
+
+    #[test]
+    fn invalid_batches() {
+        let opts = GridOptions::default();
+        let err = |data: &[u8], shape| {
+            make_grid(&batch(data, shape, ImageLayout::Hwc), &opts).unwrap_err().to_string()
+        };
+        assert_eq!(err(&[], [1, 0, 2, 1]), "unsupported image batch shape [1, 0, 2, 1]");
+        assert_eq!(err(&[], [1, 2, 0, 1]), "unsupported image batch shape [1, 2, 0, 1]");
+        assert_eq!(err(&[0; 4], [1, 2, 2, 5]), "unexpected number of values 4, shape [1, 2, 2, 5]");
+        let shape = [usize::MAX, usize::MAX, 2, 1];
+        assert!(err(&[0; 4], shape).starts_with("unexpected number of values 4"));
+        let opts = GridOptions { padding: usize::MAX, ..opts };
+        let b = batch(&[0; 8], [2, 2, 2, 1], ImageLayout::Hwc);
+        let err = make_grid(&b, &opts).unwrap_err().to_string();
+        assert!(err.starts_with("grid too large for 2 images of 2x2"), "{err}");
+    }
+
+    #[test]
+    fn multi_image_summary() {
+        let mut out = vec![];
+        let mut writer = EventWriter::from_writer(&mut out, None).unwrap();
+        let data = [[0; 6], [255; 6]].concat();
+        writer.write_images(1, "img", &batch(&data, [2, 2, 3, 1], ImageLayout::Hwc), None).unwrap();
+        drop(writer);
+        let event = crate::SummaryReader::new(out.as_slice()).nth(1).unwrap().unwrap();
+        let summary = match event.what {
+            Some(tensorboard::event::What::Summary(summary)) => summary,
+            what => panic!("unexpected event {what:?}"),
+        };
+        let tags: Vec<_> = summary.value.iter().map(|v| v.tag.as_str()).collect();
+        assert_eq!(tags, ["img/image/0", "img/image/1"]);
+        for value in summary.value.iter() {
+            match &value.value {
+                Some(tensorboard::summary::value::Value::Image(image)) => {
+                    assert_eq!((image.width, image.height, image.colorspace), (3, 2, 1));
+                    let dims = crate::validate::check_image(&image.encoded_image_string);
+                    assert_eq!(dims, Ok((3, 2)));
+                }
+                value => panic!("unexpected value {value:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,363 @@
+// Rendering of 2d arrays as color images, e.g. for attention maps, feature maps, or
+// confusion matrices. The values are mapped to [0, 1] using vmin and vmax, by default the
+// min and max of the finite values, then to colors using a colormap. The non-finite values
+// are drawn in black.
+use crate::font;
+use crate::{EventWriter, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Magma,
+    Gray,
+    /// A diverging colormap from blue to red, for values centered on 0 use symmetric vmin
+    /// and vmax.
+    Coolwarm,
+}
+
+// Colors sampled from the matplotlib colormaps at regular intervals, the colors in between
+// are linearly interpolated.
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 45, 123],
+    [59, 82, 139],
+    [44, 114, 142],
+    [33, 145, 140],
+    [40, 174, 128],
+    [94, 201, 98],
+    [173, 220, 48],
+    [253, 231, 37],
+];
+
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+
+const COOLWARM: [[u8; 3]; 9] = [
+    [59, 76, 192],
+    [98, 130, 234],
+    [141, 176, 254],
+    [184, 208, 249],
+    [221, 221, 221],
+    [245, 196, 173],
+    [244, 154, 123],
+    [222, 96, 77],
+    [180, 4, 38],
+];
+
+impl Colormap {
+    /// The color for a value between 0 and 1, the values outside are clamped.
+    pub fn color(&self, t: f32) -> [u8; 3] {
+        let stops = match self {
+            Self::Gray => {
+                let v = (t.clamp(0., 1.) * 255.).round() as u8;
+                return [v, v, v];
+            }
+            Self::Viridis => &VIRIDIS,
+            Self::Magma => &MAGMA,
+            Self::Coolwarm => &COOLWARM,
+        };
+        let pos = t.clamp(0., 1.) * (stops.len() - 1) as f32;
+        let index = (pos.floor() as usize).min(stops.len() - 2);
+        let frac = pos - index as f32;
+        let (c0, c1) = (stops[index], stops[index + 1]);
+        [0, 1, 2].map(|i| (c0[i] as f32 + (c1[i] as f32 - c0[i] as f32) * frac).round() as u8)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HeatmapOptions {
+    pub colormap: Colormap,
+    pub vmin: Option<f32>,
+    pub vmax: Option<f32>,
+    /// The size in pixels of the square drawn for each value, by default the cells are
+    /// scaled so that the image is at least 256 pixels wide or high.
+    pub cell_size: Option<usize>,
+}
+
+impl From<Colormap> for HeatmapOptions {
+    fn from(colormap: Colormap) -> Self {
+        Self { colormap, ..Default::default() }
+    }
+}
+
+// An RGB image on which the cells and the labels are drawn.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize, color: [u8; 3]) -> Result<Self> {
+        if width.checked_mul(height).and_then(|v| v.checked_mul(3)).is_none() {
+            crate::bail!("image too large for {width}x{height} pixels")
+        }
+        Ok(Self { width, height, pixels: color.repeat(width * height) })
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: [u8; 3]) {
+        for y in y..(y + h).min(self.height) {
+            for x in x..(x + w).min(self.width) {
+                let index = (y * self.width + x) * 3;
+                self.pixels[index..index + 3].copy_from_slice(&color)
+            }
+        }
+    }
+
+    // Draws some text with its top-left corner at (x, y), when `vertical` is set the text is
+    // rotated to be read from bottom to top and (x, y) is its bottom-left corner.
+    fn text(
+        &mut self,
+        x: usize,
+        y: usize,
+        text: &str,
+        scale: usize,
+        vertical: bool,
+        color: [u8; 3],
+    ) {
+        for (index, c) in text.chars().enumerate() {
+            for (col, bits) in font::glyph(c).iter().enumerate() {
+                for row in 0..font::GLYPH_HEIGHT {
+                    if bits & (1 << row) == 0 {
+                        continue;
+                    }
+                    let along = (index * font::ADVANCE + col) * scale;
+                    match vertical {
+                        false => self.fill_rect(x + along, y + row * scale, scale, scale, color),
+                        true => match (y + 1).checked_sub(along + scale) {
+                            None => {}
+                            Some(y) => self.fill_rect(x + row * scale, y, scale, scale, color),
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn value_range(values: &[f32], opts: &HeatmapOptions) -> (f32, f32) {
+    let finite = values.iter().copied().filter(|v| v.is_finite());
+    let (min, max) =
+        finite.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+    (opts.vmin.unwrap_or(min), opts.vmax.unwrap_or(max))
+}
+
+fn check_shape(values: &[f32], rows: usize, cols: usize) -> Result<()> {
+    if rows == 0 || cols == 0 || rows.checked_mul(cols) != Some(values.len()) {
+        crate::bail!("unexpected number of values {} for {rows}x{cols}", values.len())
+    }
+    Ok(())
+}
+
+fn draw_cells(
+    canvas: &mut Canvas,
+    (x0, y0): (usize, usize),
+    values: &[f32],
+    cols: usize,
+    cell: usize,
+    opts: &HeatmapOptions,
+) -> Vec<[u8; 3]> {
+    let (vmin, vmax) = value_range(values, opts);
+    let mut colors = Vec::with_capacity(values.len());
+    for (index, &v) in values.iter().enumerate() {
+        let color = if v.is_finite() {
+            let t = if vmax > vmin { (v - vmin) / (vmax - vmin) } else { 0.5 };
+            opts.colormap.color(t)
+        } else {
+            [0, 0, 0]
+        };
+        let (x, y) = (x0 + (index % cols) * cell, y0 + (index / cols) * cell);
+        canvas.fill_rect(x, y, cell, cell, color);
+        colors.push(color)
+    }
+    colors
+}
+
+/// Render the values of a row-major matrix, returning the width, height, and RGB pixels of
+/// the image.
+pub fn render(
+    values: &[f32],
+    rows: usize,
+    cols: usize,
+    opts: &HeatmapOptions,
+) -> Result<(usize, usize, Vec<u8>)> {
+    check_shape(values, rows, cols)?;
+    let cell = opts.cell_size.unwrap_or(256 / rows.max(cols)).max(1);
+    // The cell size can be set by the caller so the image size may overflow.
+    let (width, height) = match (cols.checked_mul(cell), rows.checked_mul(cell)) {
+        (Some(width), Some(height)) => (width, height),
+        _ => crate::bail!("image too large for {rows}x{cols} cells of {cell} pixels"),
+    };
+    let mut canvas = Canvas::new(width, height, [0, 0, 0])?;
+    draw_cells(&mut canvas, (0, 0), values, cols, cell, opts);
+    Ok((canvas.width, canvas.height, canvas.pixels))
+}
+
+/// Count the occurrences of each (label, prediction) pair, returning a row-major matrix
+/// with one row per label and one column per prediction. The classes outside of
+/// `0..num_classes` are ignored.
+pub fn confusion_matrix(labels: &[usize], predictions: &[usize], num_classes: usize) -> Vec<f32> {
+    let mut matrix = vec![0f32; num_classes * num_classes];
+    for (&label, &prediction) in labels.iter().zip(predictions.iter()) {
+        if label < num_classes && prediction < num_classes {
+            matrix[label * num_classes + prediction] += 1.
+        }
+    }
+    matrix
+}
+
+fn format_value(v: f32) -> String {
+    if v.fract() == 0. && v.abs() < 1e7 {
+        format!("{v:.0}")
+    } else {
+        format!("{v:.2}")
+    }
+}
+
+/// Render a confusion matrix with the true classes as rows and the predicted classes as
+/// columns. The class labels are written on the left and on top of the matrix, and the
+/// value of each cell is written in the cell.
+pub fn render_confusion_matrix(
+    matrix: &[f32],
+    labels: &[&str],
+    opts: &HeatmapOptions,
+) -> Result<(usize, usize, Vec<u8>)> {
+    const SCALE: usize = 2;
+    const PAD: usize = 4;
+    let n = labels.len();
+    check_shape(matrix, n, n)?;
+    let texts: Vec<String> = matrix.iter().map(|&v| format_value(v)).collect();
+    let cell = opts.cell_size.unwrap_or_else(|| {
+        let text_width = texts.iter().map(|t| font::text_width(t, SCALE)).max().unwrap_or(0);
+        (text_width + 2 * PAD).max(font::GLYPH_HEIGHT * SCALE + 2 * PAD).max(256 / n)
+    });
+    let margin = labels.iter().map(|l| font::text_width(l, SCALE)).max().unwrap_or(0) + 2 * PAD;
+    let size = match n.checked_mul(cell).and_then(|v| v.checked_add(margin + PAD)) {
+        None => crate::bail!("image too large for {n}x{n} cells of {cell} pixels"),
+        Some(size) => size,
+    };
+    let mut canvas = Canvas::new(size, size, [255, 255, 255])?;
+    let colors = draw_cells(&mut canvas, (margin, margin), matrix, n, cell, opts);
+    let glyph_height = font::GLYPH_HEIGHT * SCALE;
+    for (index, label) in labels.iter().enumerate() {
+        // The row labels are right aligned, the column labels are vertical.
+        let start = (margin + index * cell + cell / 2).saturating_sub(glyph_height / 2);
+        let x = margin - PAD - font::text_width(label, SCALE);
+        canvas.text(x, start, label, SCALE, false, [0, 0, 0]);
+        canvas.text(start, margin - PAD, label, SCALE, true, [0, 0, 0]);
+    }
+    for (index, (text, color)) in texts.iter().zip(colors.iter()).enumerate() {
+        let text_width = font::text_width(text, SCALE);
+        if text_width + 2 > cell || glyph_height + 2 > cell {
+            continue;
+        }
+        let x = margin + (index % n) * cell + (cell - text_width) / 2;
+        let y = margin + (index / n) * cell + (cell - glyph_height) / 2;
+        let [r, g, b] = color.map(|v| v as f32);
+        let luminance = 0.299 * r + 0.587 * g + 0.114 * b;
+        let color = if luminance > 128. { [0, 0, 0] } else { [255, 255, 255] };
+        canvas.text(x, y, text, SCALE, false, color)
+    }
+    Ok((canvas.width, canvas.height, canvas.pixels))
+}
+
+impl<W: std::io::Write> EventWriter<W> {
+    /// Write a row-major matrix as a heatmap image, `opts` can be a `Colormap` or some
+    /// `HeatmapOptions`.
+    pub fn write_heatmap<O: Into<HeatmapOptions>>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        values: &[f32],
+        rows: usize,
+        cols: usize,
+        opts: O,
+    ) -> Result<()> {
+        let (width, height, pixels) = render(values, rows, cols, &opts.into())?;
+        self.write_image_pixels(step, tag, width, height, 3, &pixels)
+    }
+
+    /// Write a confusion matrix as an image, see `render_confusion_matrix`.
+    pub fn write_confusion_matrix<O: Into<HeatmapOptions>>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        matrix: &[f32],
+        labels: &[&str],
+        opts: O,
+    ) -> Result<()> {
+        let (width, height, pixels) = render_confusion_matrix(matrix, labels, &opts.into())?;
+        self.write_image_pixels(step, tag, width, height, 3, &pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colormaps() {
+        let viridis = Colormap::Viridis;
+        assert_eq!(viridis.color(0.), [68, 1, 84]);
+        assert_eq!(viridis.color(0.5), [33, 145, 140]);
+        assert_eq!(viridis.color(0.75), [94, 201, 98]);
+        assert_eq!(viridis.color(1.), [253, 231, 37]);
+        assert_eq!(viridis.color(2.), [253, 231, 37]);
+        // Halfway between #440154 and #472d7b.
+        assert_eq!(viridis.color(1. / 16.), [70, 23, 104]);
+        assert_eq!(Colormap::Magma.color(0.5), [181, 54, 122]);
+        assert_eq!(Colormap::Gray.color(0.5), [128, 128, 128]);
+        assert_eq!(Colormap::Coolwarm.color(0.5), [221, 221, 221]);
+    }
+
+    #[test]
+    fn render_values() {
+        let opts = HeatmapOptions { cell_size: Some(2), ..Colormap::Gray.into() };
+        let (width, height, pixels) = render(&[0., 1., f32::NAN, 4.], 2, 2, &opts).unwrap();
+        assert_eq!((width, height), (4, 4));
+        let gray = |v: u8| [v; 3];
+        let row0 = [gray(0), gray(0), gray(64), gray(64)].concat();
+        let row1 = [gray(0), gray(0), gray(255), gray(255)].concat();
+        assert_eq!(pixels, [row0.clone(), row0, row1.clone(), row1].concat());
+
+        let opts = HeatmapOptions { vmin: Some(-1.), vmax: Some(1.), ..opts };
+        let (_, _, pixels) = render(&[0.], 1, 1, &opts).unwrap();
+        assert_eq!(pixels[..3], gray(128));
+
+        let err = render(&[0.; 3], 2, 2, &opts).unwrap_err();
+        assert_eq!(err.to_string(), "unexpected number of values 3 for 2x2");
+        assert!(render(&[0.; 3], usize::MAX, 2, &opts).is_err());
+        let opts = HeatmapOptions { cell_size: Some(usize::MAX), ..opts };
+        let err = render(&[0.], 1, 1, &opts).unwrap_err();
+        assert_eq!(err.to_string(), format!("image too large for {0}x{0} pixels", usize::MAX));
+        let opts = HeatmapOptions { cell_size: Some(usize::MAX / 2 + 1), ..opts };
+        let err = render(&[0.; 4], 2, 2, &opts).unwrap_err();
+        assert!(err.to_string().starts_with("image too large for 2x2 cells"), "{err}");
+        let opts = HeatmapOptions { cell_size: Some(usize::MAX / 2), ..opts };
+        let err = render_confusion_matrix(&[0.], &["a"], &opts).unwrap_err();
+        assert!(err.to_string().starts_with("image too large for"), "{err}");
+    }
+
+    #[test]
+    fn confusion() {
+        let matrix = confusion_matrix(&[0, 0, 1, 2, 5], &[0, 1, 1, 1, 0], 3);
+        assert_eq!(matrix, [1., 1., 0., 0., 1., 0., 0., 1., 0.]);
+        let opts = HeatmapOptions::default();
+        let (width, height, pixels) =
+            render_confusion_matrix(&matrix, &["a", "b", "c"], &opts).unwrap();
+        assert_eq!(width, height);
+        assert_eq!(pixels.len(), width * height * 3);
+        // The top-left corner is in the white margin.
+        assert_eq!(pixels[..3], [255, 255, 255]);
+    }
+}
@@ -0,0 +1,194 @@
+// Histograms accumulating values into buckets, similar to the tensorflow histogram used
+// for the histogram summaries. The default buckets are the tensorflow ones: their limits
+// grow by 10% from 1e-12 to 1e20, mirrored for the negative values, with a bucket for 0.
+use crate::tensorboard::HistogramProto;
+use crate::Result;
+use std::cmp::Ordering;
+use std::sync::{Arc, OnceLock};
+
+/// The tensorflow default bucket limits, sorted, the last limit being `f64::MAX`.
+pub fn default_bucket_limits() -> Arc<[f64]> {
+    static LIMITS: OnceLock<Arc<[f64]>> = OnceLock::new();
+    LIMITS
+        .get_or_init(|| {
+            let mut pos = vec![];
+            let mut v = 1e-12;
+            while v < 1e20 {
+                pos.push(v);
+                v *= 1.1;
+            }
+            pos.push(f64::MAX);
+            let neg = pos.iter().rev().map(|v| -v);
+            neg.chain(std::iter::once(0.)).chain(pos.iter().copied()).collect()
+        })
+        .clone()
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    limits: Arc<[f64]>,
+    // The count for each bucket, bucket i contains the values in [limits[i-1], limits[i]).
+    buckets: Vec<f64>,
+    min: f64,
+    max: f64,
+    num: f64,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::empty(default_bucket_limits())
+    }
+}
+
+impl Histogram {
+    /// Create an empty histogram using some bucket limits, the limits must be strictly
+    /// increasing and the values larger than the last limit are counted in the last bucket.
+    pub fn with_limits(limits: Arc<[f64]>) -> Result<Self> {
+        if limits.is_empty() {
+            crate::bail!("no histogram bucket limits")
+        }
+        if let Some(w) = limits.windows(2).find(|w| w[0].partial_cmp(&w[1]) != Some(Ordering::Less))
+        {
+            crate::bail!("histogram bucket limits are not increasing: {} then {}", w[0], w[1])
+        }
+        if limits[0].is_nan() {
+            crate::bail!("histogram bucket limits contain NaN")
+        }
+        Ok(Self::empty(limits))
+    }
+
+    fn empty(limits: Arc<[f64]>) -> Self {
+        Self {
+            buckets: vec![0.; limits.len()],
+            limits,
+            min: f64::MAX,
+            max: -f64::MAX,
+            num: 0.,
+            sum: 0.,
+            sum_squares: 0.,
+        }
+    }
+
+    pub fn from_values<T: Into<f64> + Copy>(values: &[T]) -> Self {
+        let mut h = Self::default();
+        for &v in values.iter() {
+            h.add(v.into())
+        }
+        h
+    }
+
+    /// Add a value to the histogram, NaN values are ignored.
+    pub fn add(&mut self, v: f64) {
+        if v.is_nan() {
+            return;
+        }
+        let index = self.limits.partition_point(|&limit| limit <= v);
+        let last = self.buckets.len() - 1;
+        self.buckets[index.min(last)] += 1.;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.num += 1.;
+        self.sum += v;
+        self.sum_squares += v * v;
+    }
+
+    pub fn num(&self) -> f64 {
+        self.num
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num == 0.
+    }
+
+    /// Remove all the values, keeping the bucket limits.
+    pub fn clear(&mut self) {
+        *self = Self::empty(self.limits.clone())
+    }
+
+    /// The histogram proto, consecutive empty buckets are merged as done by tensorflow.
+    pub fn to_proto(&self) -> HistogramProto {
+        let mut bucket = vec![];
+        let mut bucket_limit = vec![];
+        let mut i = 0;
+        while i < self.buckets.len() {
+            let (mut limit, mut count) = (self.limits[i], self.buckets[i]);
+            i += 1;
+            if count <= 0. {
+                while i < self.buckets.len() && self.buckets[i] <= 0. {
+                    (limit, count) = (self.limits[i], self.buckets[i]);
+                    i += 1;
+                }
+            }
+            bucket_limit.push(limit);
+            bucket.push(count);
+        }
+        HistogramProto {
+            min: self.min,
+            max: self.max,
+            num: self.num,
+            sum: self.sum,
+            sum_squares: self.sum_squares,
+            bucket_limit,
+            bucket,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let limits = default_bucket_limits();
+        assert_eq!(limits.len(), 2 * 775 + 1);
+        assert_eq!(limits[limits.len() / 2], 0.);
+        assert_eq!((limits[0], limits[limits.len() - 1]), (-f64::MAX, f64::MAX));
+        assert!(limits.windows(2).all(|w| w[0] < w[1]));
+
+        let err = |limits: &[f64]| Histogram::with_limits(limits.into()).unwrap_err().to_string();
+        assert_eq!(err(&[]), "no histogram bucket limits");
+        assert_eq!(err(&[1., 0.]), "histogram bucket limits are not increasing: 1 then 0");
+        assert_eq!(err(&[0., 0.]), "histogram bucket limits are not increasing: 0 then 0");
+        assert_eq!(err(&[0., f64::NAN]), "histogram bucket limits are not increasing: 0 then NaN");
+        assert_eq!(err(&[f64::NAN]), "histogram bucket limits contain NaN");
+    }
+
+    #[test]
+    fn buckets() {
+        let mut h = Histogram::with_limits([0., 1., 2., 3.].into()).unwrap();
+        for v in [-1., 0.5, 1., 1.5, 5., f64::NAN] {
+            h.add(v)
+        }
+        let proto = h.to_proto();
+        // The values below 0 go in the first bucket, the ones at or above the last limit in
+        // the last bucket, and the empty bucket for [2, 3) is merged with the next one.
+        assert_eq!(proto.bucket_limit, [0., 1., 2., 3.]);
+        assert_eq!(proto.bucket, [1., 1., 2., 1.]);
+        assert_eq!((proto.min, proto.max, proto.num), (-1., 5., 5.));
+        assert_eq!((proto.sum, proto.sum_squares), (7., 29.5));
+
+        let mut h = Histogram::with_limits([0., 1., 2., 3., 4.].into()).unwrap();
+        h.add(3.5);
+        let proto = h.to_proto();
+        assert_eq!(proto.bucket_limit, [3., 4.]);
+        assert_eq!(proto.bucket, [0., 1.]);
+
+        let h = Histogram::from_values(&[1f32, 1., -1.]);
+        let proto = h.to_proto();
+        let nonzero: Vec<_> = proto
+            .bucket_limit
+            .iter()
+            .zip(proto.bucket.iter())
+            .filter(|(_, &c)| c > 0.)
+            .map(|(&l, &c)| (l, c))
+            .collect();
+        assert_eq!(nonzero.len(), 2);
+        assert_eq!(nonzero[0].1, 1.);
+        assert!(nonzero[0].0 > -1. && nonzero[0].0 < -0.9);
+        assert_eq!(nonzero[1].1, 2.);
+        assert!(nonzero[1].0 > 1. && nonzero[1].0 < 1.11);
+    }
+}
@@ -0,0 +1,104 @@
+// Interop with the `image` crate: writing images as PNG summaries and decoding the image
+// summaries. The 8 and 16 bits images are encoded as is, the floating point ones are
+// converted to 8 bits as PNG does not support them.
+use crate::{tensorboard, Error, EventWriter, Result};
+use ::image::{DynamicImage, ImageFormat};
+
+/// Encode an image as PNG, returning the encoded data and the image summary colorspace.
+pub fn encode_png(image: &DynamicImage) -> Result<(Vec<u8>, i32)> {
+    let converted;
+    let image = match image {
+        DynamicImage::ImageRgb32F(_) => {
+            converted = DynamicImage::ImageRgb8(image.to_rgb8());
+            &converted
+        }
+        DynamicImage::ImageRgba32F(_) => {
+            converted = DynamicImage::ImageRgba8(image.to_rgba8());
+            &converted
+        }
+        image => image,
+    };
+    let colorspace = image.color().channel_count() as i32;
+    let mut data = std::io::Cursor::new(vec![]);
+    image.write_to(&mut data, ImageFormat::Png).map_err(Error::wrap)?;
+    Ok((data.into_inner(), colorspace))
+}
+
+/// Decode the image of an image summary, the format is guessed from the data.
+pub fn decode(image: &tensorboard::summary::Image) -> Result<DynamicImage> {
+    ::image::load_from_memory(&image.encoded_image_string).map_err(Error::wrap)
+}
+
+impl<W: std::io::Write> EventWriter<W> {
+    /// Write an image from the `image` crate as a PNG image, the floating point images are
+    /// converted to 8 bits, their values being expected to be between 0 and 1.
+    pub fn write_dynamic_image(
+        &mut self,
+        step: i64,
+        tag: &str,
+        image: &DynamicImage,
+    ) -> Result<()> {
+        let (data, colorspace) = encode_png(image)?;
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        self.write_image(step, tag, width, height, colorspace, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{ImageBuffer, Luma, LumaA, Rgb, Rgba};
+
+    // Writes an image and decodes it back from the image summary.
+    fn round_trip(image: DynamicImage) -> (tensorboard::summary::Image, DynamicImage) {
+        use tensorboard::summary::value::Value;
+        let mut data = vec![];
+        let mut writer = EventWriter::from_writer(&mut data, None).unwrap();
+        writer.write_dynamic_image(1, "image", &image).unwrap();
+        drop(writer);
+        let event = crate::SummaryReader::new(data.as_slice()).nth(1).unwrap().unwrap();
+        let summary = match event.what {
+            Some(tensorboard::event::What::Summary(mut summary)) => summary.value.remove(0),
+            what => panic!("unexpected event {what:?}"),
+        };
+        match summary.value {
+            Some(Value::Image(image)) => {
+                let decoded = decode(&image).unwrap();
+                (image, decoded)
+            }
+            value => panic!("unexpected value {value:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let images = [
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(3, 2, |x, y| Luma([(x * 10 + y) as u8]))),
+            DynamicImage::ImageLumaA8(ImageBuffer::from_fn(3, 2, |x, y| LumaA([x as u8, y as u8]))),
+            DynamicImage::ImageRgb8(ImageBuffer::from_fn(3, 2, |x, y| Rgb([x as u8, y as u8, 7]))),
+            DynamicImage::ImageRgba16(ImageBuffer::from_fn(3, 2, |x, y| {
+                Rgba([x as u16 * 1000, y as u16 * 1000, 65535, 300])
+            })),
+        ];
+        for (image, colorspace) in images.into_iter().zip([1, 2, 3, 4]) {
+            let (summary, decoded) = round_trip(image.clone());
+            let dims = (summary.width, summary.height, summary.colorspace);
+            assert_eq!(dims, (3, 2, colorspace));
+            assert_eq!(decoded, image);
+        }
+
+        // The floating point images are converted to 8 bits.
+        let values = [0., 0.5, 1., 2., -1., 0.25];
+        let image = ImageBuffer::from_fn(2, 1, |x, _| {
+            let x = x as usize * 3;
+            Rgb([values[x], values[x + 1], values[x + 2]])
+        });
+        let (summary, decoded) = round_trip(DynamicImage::ImageRgb32F(image));
+        assert_eq!((summary.width, summary.height, summary.colorspace), (2, 1, 3));
+        let decoded = match decoded {
+            DynamicImage::ImageRgb8(decoded) => decoded,
+            decoded => panic!("unexpected image {:?}", decoded.color()),
+        };
+        assert_eq!(decoded.into_raw(), [0, 128, 255, 255, 0, 64]);
+    }
+}
@@ -0,0 +1,398 @@
+// Import of scalar series from CSV, JSON, or JSON Lines files into event files, this is
+// the reverse of `export`. The input is a list of records, each record being either a
+// single value with a tag column and a value column (long layout), or the values for a
+// step with one column per tag (wide layout). Nested JSON objects are flattened by joining
+// the keys with "/", e.g. {"train": {"loss": 0.1}} results in the "train/loss" tag.
+//
+// Some common sources can be imported by adjusting the column names:
+// - the files written by `export` use the default options.
+// - W&B history dumps are in the wide layout with the `_step` and `_timestamp` columns,
+//   the other columns starting with an underscore such as `_runtime` are bookkeeping and
+//   are not imported by default.
+// - MLflow metric histories are in the long layout with the `key`, `value`, `step`, and
+//   `timestamp` columns, the timestamps being in milliseconds.
+use crate::export::{Layout, Row};
+use crate::writer::{event_filename_at, wall_time_now, EventWriter};
+use crate::{tensorboard, Result};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// A JSON array of objects, or a sequence of JSON objects.
+    Json,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl Format {
+    /// Guess the format from a file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            "jsonl" | "ndjson" => Some(Self::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub format: Format,
+    pub layout: Layout,
+    /// When a record has no step, its index in the input is used.
+    pub step_column: String,
+    /// When a record has no wall time, the time of the import is used.
+    pub wall_time_column: String,
+    pub wall_time_unit: TimeUnit,
+    /// The columns holding the tag and the value in the long layout.
+    pub tag_column: String,
+    pub value_column: String,
+    /// The column holding the run name, the runs are written in sub-directories of the log
+    /// directory.
+    pub run_column: String,
+    /// The run for the records that do not have a run column.
+    pub run: String,
+    /// Only import the tags matching this regex.
+    pub tag_filter: Option<regex::Regex>,
+    /// Import the columns starting with an underscore in the wide layout, these are
+    /// skipped by default as they usually hold some bookkeeping, e.g. `_runtime` for W&B.
+    pub underscore_columns: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::Csv,
+            layout: Layout::Long,
+            step_column: "step".to_string(),
+            wall_time_column: "wall_time".to_string(),
+            wall_time_unit: TimeUnit::Seconds,
+            tag_column: "tag".to_string(),
+            value_column: "value".to_string(),
+            run_column: "run".to_string(),
+            run: ".".to_string(),
+            tag_filter: None,
+            underscore_columns: false,
+        }
+    }
+}
+
+type Record = serde_json::Map<String, serde_json::Value>;
+
+fn flatten(prefix: &str, value: serde_json::Value, record: &mut Record) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() { key } else { format!("{prefix}/{key}") };
+                flatten(&key, value, record)
+            }
+        }
+        value => {
+            record.insert(prefix.to_string(), value);
+        }
+    }
+}
+
+fn json_record(value: serde_json::Value) -> Result<Record> {
+    if !value.is_object() {
+        crate::bail!("expected a JSON object, got {value}")
+    }
+    let mut record = Record::new();
+    flatten("", value, &mut record);
+    Ok(record)
+}
+
+fn read_records<R: std::io::Read>(r: R, format: Format) -> Result<Vec<Record>> {
+    use std::io::BufRead;
+
+    let mut records = vec![];
+    match format {
+        Format::Csv => {
+            let mut r = csv::Reader::from_reader(r);
+            let header = r.headers().map_err(crate::Error::wrap)?.clone();
+            for row in r.records() {
+                let row = row.map_err(crate::Error::wrap)?;
+                let record = header
+                    .iter()
+                    .zip(row.iter())
+                    .filter(|(_, v)| !v.is_empty())
+                    .map(|(k, v)| (k.to_string(), v.into()))
+                    .collect();
+                records.push(record)
+            }
+        }
+        Format::Json => {
+            let values = serde_json::Deserializer::from_reader(r).into_iter();
+            for value in values {
+                match value.map_err(crate::Error::wrap)? {
+                    serde_json::Value::Array(values) => {
+                        for value in values {
+                            records.push(json_record(value)?)
+                        }
+                    }
+                    value => records.push(json_record(value)?),
+                }
+            }
+        }
+        Format::JsonLines => {
+            for (line_index, line) in std::io::BufReader::new(r).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let value = serde_json::from_str(&line)
+                    .map_err(|err| crate::Error::Msg(format!("line {}: {err}", line_index + 1)))?;
+                records.push(json_record(value)?)
+            }
+        }
+    }
+    Ok(records)
+}
+
+// Numbers can also be given as strings, this is always the case in CSV, and the parsing
+// accepts the non-finite values written by `export`, e.g. "NaN" or "-inf".
+fn as_f64(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(v) => v.as_f64(),
+        serde_json::Value::String(v) => match v.trim() {
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            v => v.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+fn as_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(v) => v.to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Read the rows from some CSV or JSON input.
+pub fn read_rows<R: std::io::Read>(r: R, opts: &ImportOptions) -> Result<Vec<Row>> {
+    let now = wall_time_now()?;
+    let keep_tag = |tag: &str| opts.tag_filter.as_ref().is_none_or(|re| re.is_match(tag));
+    let mut rows = vec![];
+    for (index, record) in read_records(r, opts.format)?.into_iter().enumerate() {
+        let run = record.get(&opts.run_column).map_or_else(|| opts.run.clone(), as_string);
+        let step = match record.get(&opts.step_column) {
+            None => index as i64,
+            Some(v) => match as_f64(v) {
+                Some(step) if step.fract() == 0. => step as i64,
+                _ => crate::bail!("record {index}: invalid step {v}"),
+            },
+        };
+        let wall_time = match record.get(&opts.wall_time_column) {
+            None => now,
+            Some(v) => match (as_f64(v), opts.wall_time_unit) {
+                (None, _) => crate::bail!("record {index}: invalid wall time {v}"),
+                (Some(v), TimeUnit::Seconds) => v,
+                (Some(v), TimeUnit::Milliseconds) => v / 1000.,
+            },
+        };
+        let mut push = |tag: String, value: f64| {
+            if keep_tag(&tag) {
+                rows.push(Row { run: run.clone(), tag, step, wall_time, value })
+            }
+        };
+        match opts.layout {
+            Layout::Long => {
+                let tag = match record.get(&opts.tag_column) {
+                    None => crate::bail!("record {index}: no {} column", opts.tag_column),
+                    Some(tag) => as_string(tag),
+                };
+                if let Some(value) = record.get(&opts.value_column).and_then(as_f64) {
+                    push(tag, value)
+                }
+            }
+            Layout::Wide => {
+                let columns = [&opts.run_column, &opts.step_column, &opts.wall_time_column];
+                for (tag, value) in record.iter() {
+                    if columns.contains(&tag) || !opts.underscore_columns && tag.starts_with('_') {
+                        continue;
+                    }
+                    if let Some(value) = as_f64(value) {
+                        push(tag.to_string(), value)
+                    }
+                }
+            }
+        }
+    }
+    Ok(rows)
+}
+
+// The run names are relative paths as returned by `logdir::runs`.
+fn run_dir(logdir: &Path, run: &str) -> Result<PathBuf> {
+    if run == "." {
+        return Ok(logdir.to_path_buf());
+    }
+    let path = Path::new(run);
+    if path.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+        crate::bail!("invalid run name {run:?}")
+    }
+    Ok(logdir.join(path))
+}
+
+/// Write some rows as scalars in a new event file for each run, returns the paths of the
+/// created files. The rows are sorted by step and the rows with the same step and wall time
+/// are written in a single event, the events keep the wall time from the rows. The file
+/// names use the first wall time of the run, as if the files had been written at the time.
+pub fn write_rows<P: AsRef<Path>>(rows: &[Row], logdir: P) -> Result<Vec<PathBuf>> {
+    let logdir = logdir.as_ref();
+    let mut runs: Vec<(&str, Vec<&Row>)> = vec![];
+    for row in rows.iter() {
+        match runs.iter_mut().find(|(run, _)| *run == row.run) {
+            Some((_, rows)) => rows.push(row),
+            None => runs.push((&row.run, vec![row])),
+        }
+    }
+    let mut filenames = vec![];
+    for (run, mut rows) in runs {
+        rows.sort_by_key(|row| row.step);
+        let mut start_time = rows.iter().map(|r| r.wall_time).fold(f64::INFINITY, f64::min);
+        if !start_time.is_finite() || start_time < 0. {
+            start_time = wall_time_now()?
+        }
+        let filename = event_filename_at(&run_dir(logdir, run)?, start_time as u64)?;
+        let file = std::io::BufWriter::new(std::fs::File::create(&filename)?);
+        let mut writer =
+            EventWriter::from_writer_with_wall_time(file, Some(filename.clone()), start_time)?;
+        for rows in rows.chunk_by(|r1, r2| r1.step == r2.step && r1.wall_time == r2.wall_time) {
+            let value = rows
+                .iter()
+                .map(|r| tensorboard::summary::Value {
+                    node_name: "".to_string(),
+                    tag: r.tag.to_string(),
+                    metadata: None,
+                    value: Some(tensorboard::summary::value::Value::SimpleValue(r.value as f32)),
+                })
+                .collect();
+            writer.write_event(tensorboard::Event {
+                wall_time: rows[0].wall_time,
+                step: rows[0].step,
+                source_metadata: None,
+                what: Some(tensorboard::event::What::Summary(tensorboard::Summary { value })),
+            })?
+        }
+        writer.flush()?;
+        filenames.push(filename)
+    }
+    Ok(filenames)
+}
+
+/// Import a CSV or JSON file in a log directory, returns the paths of the created event
+/// files.
+pub fn import_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    logdir: Q,
+    opts: &ImportOptions,
+) -> Result<Vec<PathBuf>> {
+    let file = std::fs::File::open(path.as_ref())?;
+    let rows = read_rows(std::io::BufReader::new(file), opts)?;
+    write_rows(&rows, logdir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{export_logdir, ExportOptions};
+
+    fn row(run: &str, tag: &str, step: i64, wall_time: f64, value: f64) -> Row {
+        Row { run: run.to_string(), tag: tag.to_string(), step, wall_time, value }
+    }
+
+    #[test]
+    fn mlflow_history() {
+        let input = r#"[{"key": "loss", "value": 0.5, "step": 1, "timestamp": 1500},
+                        {"key": "loss", "value": "NaN", "step": 2, "timestamp": 2500},
+                        {"key": "acc", "value": null, "step": 2, "timestamp": 2500}]"#;
+        let opts = ImportOptions {
+            format: Format::Json,
+            tag_column: "key".to_string(),
+            wall_time_column: "timestamp".to_string(),
+            wall_time_unit: TimeUnit::Milliseconds,
+            ..Default::default()
+        };
+        let rows = read_rows(input.as_bytes(), &opts).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], row(".", "loss", 1, 1.5, 0.5));
+        assert_eq!((rows[1].step, rows[1].wall_time), (2, 2.5));
+        assert!(rows[1].value.is_nan());
+    }
+
+    #[test]
+    fn wide_json_lines() {
+        let input =
+            "{\"_step\": 3, \"_timestamp\": 10, \"_runtime\": 5, \"train\": {\"loss\": 0.25}}\n\
+                     \n\
+                     {\"train\": {\"loss\": \"-inf\"}, \"run\": \"a/b\"}\n";
+        let opts = ImportOptions {
+            format: Format::JsonLines,
+            layout: Layout::Wide,
+            step_column: "_step".to_string(),
+            wall_time_column: "_timestamp".to_string(),
+            ..Default::default()
+        };
+        let rows = read_rows(input.as_bytes(), &opts).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], row(".", "train/loss", 3, 10., 0.25));
+        assert_eq!((rows[1].run.as_str(), rows[1].tag.as_str()), ("a/b", "train/loss"));
+        // The missing step defaults to the record index.
+        assert_eq!((rows[1].step, rows[1].value), (1, f64::NEG_INFINITY));
+
+        // The bookkeeping columns can be imported, the tags can be filtered.
+        let opts = ImportOptions { underscore_columns: true, ..opts };
+        let rows = read_rows(input.as_bytes(), &opts).unwrap();
+        let tags: Vec<_> = rows.iter().map(|r| (r.tag.as_str(), r.step)).collect();
+        assert_eq!(tags, [("_runtime", 3), ("train/loss", 3), ("train/loss", 1)]);
+        let opts = ImportOptions { tag_filter: Some(regex::Regex::new("loss").unwrap()), ..opts };
+        assert_eq!(read_rows(input.as_bytes(), &opts).unwrap().len(), 2);
+
+        let err = read_rows("{\"_step\": 1.5}".as_bytes(), &opts).unwrap_err();
+        assert_eq!(err.to_string(), "record 0: invalid step 1.5");
+        let err = read_rows("[1]".as_bytes(), &opts).unwrap_err();
+        assert_eq!(err.to_string(), "expected a JSON object, got [1]");
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = "run,tag,step,wall_time,value\n\
+                     .,loss,0,10,1.5\n\
+                     .,acc,1,11,0.25\n\
+                     .,loss,1,11,NaN\n\
+                     eval,loss,1,12,-inf\n";
+        let dir = crate::test_utils::temp_dir("import-round-trip");
+        let rows = read_rows(input.as_bytes(), &ImportOptions::default()).unwrap();
+        let files = write_rows(&rows, &dir).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].parent().unwrap(), dir.join("eval"));
+        // The file names use the first wall time of each run.
+        let name = |i: usize| files[i].file_name().unwrap().to_string_lossy().to_string();
+        assert!(name(0).starts_with("events.out.tfevents.0000000010."), "{}", name(0));
+        assert!(name(1).starts_with("events.out.tfevents.0000000012."), "{}", name(1));
+        let mut out = vec![];
+        export_logdir(&dir, &mut out, &ExportOptions::default()).unwrap();
+        let expected = "run,tag,step,wall_time,value\n\
+                        .,acc,1,11,0.25\n\
+                        .,loss,0,10,1.5\n\
+                        .,loss,1,11,NaN\n\
+                        eval,loss,1,12,-inf\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        let rows = [row("../escape", "loss", 0, 0., 0.)];
+        let err = write_rows(&rows, &dir).unwrap_err();
+        assert_eq!(err.to_string(), "invalid run name \"../escape\"");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+// Index of the record offsets in an event file, used for random access via
+// `SummaryReader::seek_to_step` or `SummaryReader::last_events`. The index can be cached
+// in a sidecar file next to the event file, this cache is invalidated when the size or
+// modification time of the event file changes.
+use crate::{tensorboard, Error, Result, SummaryReader};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Seek, Write};
+
+const MAGIC: &[u8; 8] = b"TBIDX001";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    /// Byte offset of the start of the record.
+    pub offset: u64,
+    pub step: i64,
+    pub wall_time: f64,
+    /// Indexes in `RecordIndex::tags` of the summary values tags for this record.
+    pub tag_ids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordIndex {
+    file_len: u64,
+    mtime_ns: u64,
+    end_offset: u64,
+    tags: Vec<String>,
+    entries: Vec<IndexEntry>,
+}
+
+// Returns the length and modification time of a file, used to validate the cached index.
+fn file_stamp(path: &std::path::Path) -> Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let mtime = metadata.modified()?.duration_since(std::time::UNIX_EPOCH)?;
+    Ok((metadata.len(), mtime.as_nanos() as u64))
+}
+
+impl RecordIndex {
+    /// Build the index by decoding all the records from the current reader position. A
+    /// truncated record at the end of the file, e.g. because it is still being written,
+    /// is not included in the index.
+    pub fn from_reader<R: Read>(reader: &mut SummaryReader<R>) -> Result<Self> {
+        let end_offset = reader.offset();
+        let mut slf = Self { file_len: 0, mtime_ns: 0, end_offset, tags: vec![], entries: vec![] };
+        slf.extend(reader)?;
+        slf.file_len = slf.end_offset;
+        Ok(slf)
+    }
+
+    /// Add the records from the current reader position to the index, the reader should be
+    /// at the end offset of the index.
+    pub fn extend<R: Read>(&mut self, reader: &mut SummaryReader<R>) -> Result<()> {
+        let mut tag_ids: std::collections::HashMap<String, u32> =
+            self.tags.iter().enumerate().map(|(i, tag)| (tag.clone(), i as u32)).collect();
+        loop {
+            let offset = reader.offset();
+            let event = match reader.next() {
+                None | Some(Err(Error::TruncatedRecord { .. })) => {
+                    // The reader has consumed the partial record, the index stops before it
+                    // so that it can be extended once the record has been fully written.
+                    self.end_offset = offset;
+                    return Ok(());
+                }
+                Some(event) => event?,
+            };
+            let entry_tag_ids = match &event.what {
+                Some(tensorboard::event::What::Summary(summary)) => summary
+                    .value
+                    .iter()
+                    .map(|v| {
+                        *tag_ids.entry(v.tag.clone()).or_insert_with(|| {
+                            self.tags.push(v.tag.clone());
+                            self.tags.len() as u32 - 1
+                        })
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            let entry = IndexEntry {
+                offset,
+                step: event.step,
+                wall_time: event.wall_time,
+                tag_ids: entry_tag_ids,
+            };
+            self.entries.push(entry)
+        }
+    }
+
+    /// Build the index for an event file.
+    pub fn build<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let (file_len, mtime_ns) = file_stamp(path)?;
+        let reader = std::fs::File::open(path)?;
+        let mut reader = SummaryReader::new(std::io::BufReader::new(reader)).with_path(path);
+        let slf = Self::from_reader(&mut reader)?;
+        Ok(Self { file_len, mtime_ns, ..slf })
+    }
+
+    /// Extend the index with the records that have been appended to the event file since
+    /// the index was built, only the new records are decoded.
+    pub fn update<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let (file_len, mtime_ns) = file_stamp(path)?;
+        if file_len < self.file_len {
+            crate::bail!("{path:?} is smaller than when its index was built")
+        }
+        let reader = std::fs::File::open(path)?;
+        let mut reader = SummaryReader::new(std::io::BufReader::new(reader)).with_path(path);
+        reader.seek(self.end_offset, self.entries.len() as u64)?;
+        self.extend(&mut reader)?;
+        (self.file_len, self.mtime_ns) = (file_len, mtime_ns);
+        Ok(())
+    }
+
+    /// The path of the sidecar file used to cache the index of an event file.
+    pub fn sidecar_path<P: AsRef<std::path::Path>>(path: P) -> std::path::PathBuf {
+        let mut path = path.as_ref().as_os_str().to_os_string();
+        path.push(".tbidx");
+        path.into()
+    }
+
+    /// Load the index from the sidecar file if it is still valid, otherwise build it and
+    /// write it to the sidecar file. When the event file has grown since the index was
+    /// cached, the cached index is extended with the new records.
+    pub fn load_or_build<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let sidecar_path = Self::sidecar_path(path);
+        let stamp = file_stamp(path)?;
+        let cached = std::fs::File::open(&sidecar_path)
+            .ok()
+            .and_then(|file| Self::read(&mut std::io::BufReader::new(file)).ok());
+        let slf = match cached {
+            Some(slf) if (slf.file_len, slf.mtime_ns) == stamp => return Ok(slf),
+            // The extension fails if the file has been rewritten rather than appended to,
+            // in which case the index is rebuilt from scratch.
+            Some(mut slf) if stamp.0 > slf.file_len => match slf.update(path) {
+                Ok(()) => slf,
+                Err(_) => Self::build(path)?,
+            },
+            _ => Self::build(path)?,
+        };
+        // The cache is only an optimization, e.g. the directory could be read-only so
+        // failing to write it is not an error.
+        let _ = slf.write_sidecar(&sidecar_path);
+        Ok(slf)
+    }
+
+    // Writes the index to a temporary file that is then renamed, so that the concurrent
+    // readers never see a partially written sidecar.
+    fn write_sidecar(&self, sidecar_path: &std::path::Path) -> Result<()> {
+        let uid = crate::writer::global_uid();
+        let tmp_name = format!(".tboard-index-{}-{uid}.tmp", std::process::id());
+        let tmp_path = sidecar_path.with_file_name(tmp_name);
+        let written = std::fs::File::create(&tmp_path).map_err(Error::from).and_then(|file| {
+            let mut file = std::io::BufWriter::new(file);
+            self.write(&mut file)?;
+            file.flush()?;
+            std::fs::rename(&tmp_path, sidecar_path)?;
+            Ok(())
+        });
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        written
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u64::<LittleEndian>(self.file_len)?;
+        w.write_u64::<LittleEndian>(self.mtime_ns)?;
+        w.write_u64::<LittleEndian>(self.end_offset)?;
+        w.write_u32::<LittleEndian>(self.tags.len() as u32)?;
+        for tag in self.tags.iter() {
+            w.write_u32::<LittleEndian>(tag.len() as u32)?;
+            w.write_all(tag.as_bytes())?;
+        }
+        w.write_u64::<LittleEndian>(self.entries.len() as u64)?;
+        for entry in self.entries.iter() {
+            w.write_u64::<LittleEndian>(entry.offset)?;
+            w.write_i64::<LittleEndian>(entry.step)?;
+            w.write_f64::<LittleEndian>(entry.wall_time)?;
+            w.write_u32::<LittleEndian>(entry.tag_ids.len() as u32)?;
+            for &tag_id in entry.tag_ids.iter() {
+                w.write_u32::<LittleEndian>(tag_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            crate::bail!("unexpected magic for record index {magic:?}")
+        }
+        let file_len = r.read_u64::<LittleEndian>()?;
+        let mtime_ns = r.read_u64::<LittleEndian>()?;
+        let end_offset = r.read_u64::<LittleEndian>()?;
+        // The counts and lengths are not used to preallocate, a corrupted sidecar results
+        // in an error when reaching the end of the data rather than in a large allocation.
+        let n_tags = r.read_u32::<LittleEndian>()?;
+        let mut tags = Vec::new();
+        for _ in 0..n_tags {
+            let len = r.read_u32::<LittleEndian>()?;
+            let mut tag = Vec::new();
+            r.take(len as u64).read_to_end(&mut tag)?;
+            if tag.len() != len as usize {
+                crate::bail!("truncated record index tag")
+            }
+            tags.push(String::from_utf8(tag).map_err(Error::wrap)?)
+        }
+        let n_entries = r.read_u64::<LittleEndian>()?;
+        let mut entries = Vec::new();
+        for _ in 0..n_entries {
+            let offset = r.read_u64::<LittleEndian>()?;
+            let step = r.read_i64::<LittleEndian>()?;
+            let wall_time = r.read_f64::<LittleEndian>()?;
+            let n_tag_ids = r.read_u32::<LittleEndian>()?;
+            let tag_ids = (0..n_tag_ids)
+                .map(|_| r.read_u32::<LittleEndian>())
+                .collect::<std::io::Result<_>>()?;
+            entries.push(IndexEntry { offset, step, wall_time, tag_ids })
+        }
+        Ok(Self { file_len, mtime_ns, end_offset, tags, entries })
+    }
+
+    /// The length of the event file when the index was built.
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    /// The offset of the end of the last record covered by this index.
+    pub fn end_offset(&self) -> u64 {
+        self.end_offset
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The ordinal of the first record with a step greater or equal to `step`.
+    pub fn find_step(&self, step: i64) -> Option<usize> {
+        self.entries.iter().position(|e| e.step >= step)
+    }
+
+    /// The ordinals of the records that contain a value for `tag`.
+    pub fn records_for_tag(&self, tag: &str) -> Vec<usize> {
+        let tag_id = match self.tags.iter().position(|t| t == tag) {
+            None => return vec![],
+            Some(tag_id) => tag_id as u32,
+        };
+        let entries = self.entries.iter().enumerate();
+        entries.filter(|(_, e)| e.tag_ids.contains(&tag_id)).map(|(i, _)| i).collect()
+    }
+}
+
+impl<R: Read + Seek> SummaryReader<R> {
+    /// Move the reader to a given record, `index` is the ordinal of the record at `offset`.
+    pub fn seek(&mut self, offset: u64, index: u64) -> Result<()> {
+        self.reader.seek(std::io::SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.index = index;
+        Ok(())
+    }
+
+    /// Move the reader to the record with the given ordinal in the index.
+    pub fn seek_to_record(&mut self, index: &RecordIndex, record: usize) -> Result<()> {
+        match index.entries.get(record) {
+            Some(entry) => self.seek(entry.offset, record as u64),
+            None => self.seek(index.end_offset, index.entries.len() as u64),
+        }
+    }
+
+    /// Move the reader to the first record with a step greater or equal to `step`, returns
+    /// false if there is no such record.
+    pub fn seek_to_step(&mut self, index: &RecordIndex, step: i64) -> Result<bool> {
+        match index.find_step(step) {
+            None => Ok(false),
+            Some(record) => {
+                self.seek_to_record(index, record)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Decode the last `k` events covered by the index.
+    pub fn last_events(
+        &mut self,
+        index: &RecordIndex,
+        k: usize,
+    ) -> Result<Vec<tensorboard::Event>> {
+        let record = index.len().saturating_sub(k);
+        self.seek_to_record(index, record)?;
+        self.take(index.len() - record).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{scalar_events, temp_dir};
+    use crate::EventWriter;
+
+    fn scalars() -> Vec<u8> {
+        scalar_events(&[("loss", 0, 1.), ("acc", 0, 0.5), ("loss", 10, 0.5), ("loss", 20, 0.2)])
+    }
+
+    #[test]
+    fn random_access() {
+        let data = scalars();
+        let mut reader = SummaryReader::new(std::io::Cursor::new(&data));
+        let index = RecordIndex::from_reader(&mut reader).unwrap();
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.end_offset(), data.len() as u64);
+        assert_eq!(index.tags(), ["loss", "acc"]);
+        assert_eq!(index.records_for_tag("loss"), [1, 3, 4]);
+        assert_eq!(index.records_for_tag("acc"), [2]);
+        assert!(index.records_for_tag("lr").is_empty());
+
+        assert!(reader.seek_to_step(&index, 5).unwrap());
+        assert_eq!(reader.index(), 3);
+        let event = reader.next().unwrap().unwrap();
+        assert_eq!(event.step, 10);
+        assert!(!reader.seek_to_step(&index, 21).unwrap());
+
+        let last = reader.last_events(&index, 2).unwrap();
+        assert_eq!(last.iter().map(|e| e.step).collect::<Vec<_>>(), [10, 20]);
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let data = scalars();
+        let full = RecordIndex::from_reader(&mut SummaryReader::new(data.as_slice())).unwrap();
+        let last_offset = full.entries()[4].offset;
+        let data = &data[..data.len() - 3];
+        let index = RecordIndex::from_reader(&mut SummaryReader::new(data)).unwrap();
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.end_offset(), last_offset);
+    }
+
+    #[test]
+    fn sidecar_round_trip() {
+        let data = scalars();
+        let index = RecordIndex::from_reader(&mut SummaryReader::new(data.as_slice())).unwrap();
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        assert_eq!(RecordIndex::read(&mut buf.as_slice()).unwrap(), index);
+        // Truncated sidecars are rejected.
+        assert!(RecordIndex::read(&mut &buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn corrupted_sidecar_counts() {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&[0u8; 24]);
+        let mut huge_tags = buf.clone();
+        huge_tags.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(RecordIndex::read(&mut huge_tags.as_slice()).is_err());
+        let mut huge_tag = buf.clone();
+        huge_tag.extend_from_slice(&1u32.to_le_bytes());
+        huge_tag.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(RecordIndex::read(&mut huge_tag.as_slice()).is_err());
+        let mut huge_entries = buf;
+        huge_entries.extend_from_slice(&0u32.to_le_bytes());
+        huge_entries.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(RecordIndex::read(&mut huge_entries.as_slice()).is_err());
+    }
+
+    #[test]
+    fn load_or_build_extends() {
+        let dir = temp_dir("index-extends");
+        let mut writer = EventWriter::create(&dir).unwrap();
+        writer.write_scalar(0, "loss", 1.).unwrap();
+        writer.flush().unwrap();
+        let path = writer.filename().unwrap().clone();
+        let index = RecordIndex::load_or_build(&path).unwrap();
+        assert_eq!(index.len(), 2);
+        assert!(RecordIndex::sidecar_path(&path).exists());
+        assert_eq!(RecordIndex::load_or_build(&path).unwrap(), index);
+
+        // Tamper with the cached index to check that it is extended rather than rebuilt.
+        let mut cached = index.clone();
+        cached.entries[1].step = 42;
+        let mut file = std::fs::File::create(RecordIndex::sidecar_path(&path)).unwrap();
+        cached.write(&mut file).unwrap();
+        writer.write_scalar(1, "acc", 0.5).unwrap();
+        writer.write_scalar(2, "loss", 0.2).unwrap();
+        writer.flush().unwrap();
+        let index = RecordIndex::load_or_build(&path).unwrap();
+        assert_eq!(index.entries().iter().map(|e| e.step).collect::<Vec<_>>(), [0, 42, 1, 2]);
+        assert_eq!(index.tags(), ["loss", "acc"]);
+        assert_eq!(index.records_for_tag("loss"), [1, 3]);
+        assert_eq!(index.file_len(), std::fs::metadata(&path).unwrap().len());
+        assert_eq!(index.end_offset(), index.file_len());
+        let names: Vec<_> =
+            std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names.len(), 2, "{names:?}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,12 +1,66 @@
+pub mod accumulator;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+#[cfg(feature = "candle")]
+pub mod candle;
+pub mod diff;
 mod error;
+#[cfg(feature = "export")]
+pub mod export;
+mod font;
+pub mod gif;
+pub mod grid;
+pub mod heatmap;
+pub mod histogram;
+#[cfg(feature = "image")]
+pub mod image;
+#[cfg(feature = "export")]
+pub mod import;
+pub mod index;
+pub mod logdir;
+#[cfg(feature = "metrics")]
+pub mod metrics_recorder;
+pub mod multiplexer;
+#[cfg(feature = "ndarray")]
+pub mod ndarray;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod plot;
+pub mod png;
 mod reader;
+pub mod repair;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod step;
+pub mod surgery;
+#[cfg(test)]
+mod test_utils;
+#[cfg(feature = "tracing")]
+pub mod tracing_layer;
+pub mod validate;
+pub mod view;
 pub mod wave;
 mod writer;
-pub use error::{Error, Result};
+pub use accumulator::EventAccumulator;
+#[cfg(feature = "tokio")]
+pub use async_io::{AsyncEventWriter, EventStream};
+pub use error::{Error, RecordPos, Result};
+pub use index::RecordIndex;
+#[cfg(feature = "metrics")]
+pub use metrics_recorder::MetricsRecorder;
 pub use reader::SummaryReader;
-pub use writer::EventWriter;
+pub use step::StepSource;
+#[cfg(feature = "tracing")]
+pub use tracing_layer::TracingLayer;
+#[cfg(feature = "mmap")]
+pub use view::MmapReader;
+pub use view::SliceReader;
+pub use writer::{EventWriter, IntoTensor, TensorType};
 
 // Protobuf types.
+#[allow(clippy::large_enum_variant)]
 pub mod tensorboard {
     include!(concat!(env!("OUT_DIR"), "/tensorboard.rs"));
 }
@@ -17,3 +71,16 @@ pub(crate) fn masked_crc(buf: &[u8]) -> u32 {
     let csum = crc32c.checksum(buf);
     (csum.wrapping_shr(15) | csum.wrapping_shl(17)).wrapping_add(0xa282ead8)
 }
+
+/// Guess the mime type of an encoded image from its magic bytes.
+pub fn image_content_type(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else {
+        "application/octet-stream"
+    }
+}
@@ -0,0 +1,167 @@
+// Discovery of the runs in a log directory. Similar to tensorboard, a run is a directory
+// that directly contains some event files and its name is the path of this directory
+// relative to the log directory, "." being used for the log directory itself.
+use crate::{Error, EventAccumulator, Result, SummaryReader};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Returns true for files that follow the tensorboard event file naming scheme.
+pub fn is_event_file<P: AsRef<Path>>(path: P) -> bool {
+    match path.as_ref().file_name().and_then(|v| v.to_str()) {
+        None => false,
+        Some(name) => name.contains("tfevents") && !name.ends_with(".tbidx"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub name: String,
+    /// The event files for this run, sorted by name which for the files generated by
+    /// `EventWriter` is also the creation order.
+    pub files: Vec<PathBuf>,
+}
+
+/// The result of loading all the event files of a run. A file that cannot be read fully
+/// does not prevent the other files from being loaded, the events read before the error
+/// are kept.
+#[derive(Debug)]
+pub struct LoadedRun {
+    pub name: String,
+    pub accumulator: EventAccumulator,
+    pub errors: Vec<(PathBuf, Error)>,
+}
+
+impl Run {
+    pub fn load(&self) -> LoadedRun {
+        let mut accumulator = EventAccumulator::default();
+        let mut errors = vec![];
+        for path in self.files.iter() {
+            if let Err(err) = load_file(&mut accumulator, path) {
+                errors.push((path.clone(), err))
+            }
+        }
+        LoadedRun { name: self.name.clone(), accumulator, errors }
+    }
+}
+
+impl LoadedRun {
+    /// Returns the first error, ignoring the truncated records at the end of the files as
+    /// these are typically being written: similar to following a live run, the events
+    /// before such a record are kept.
+    pub fn check_errors(self) -> Result<Self> {
+        let mut errors = self.errors.iter();
+        match errors.position(|(_, err)| !matches!(err, Error::TruncatedRecord { .. })) {
+            Some(index) => Err(self.errors.into_iter().nth(index).unwrap().1),
+            None => Ok(self),
+        }
+    }
+}
+
+fn load_file(accumulator: &mut EventAccumulator, path: &Path) -> Result<()> {
+    let file = std::fs::File::open(path)?;
+    let reader = SummaryReader::new(std::io::BufReader::new(file)).with_path(path);
+    accumulator.load(reader)
+}
+
+fn walk(
+    logdir: &Path,
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    runs: &mut Vec<Run>,
+) -> Result<()> {
+    let mut files = vec![];
+    let mut subdirs = vec![];
+    // The entries that cannot be read, e.g. because they have been removed concurrently, are
+    // skipped.
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            subdirs.push(path)
+        } else if is_event_file(&path) {
+            files.push(path)
+        }
+    }
+    if !files.is_empty() {
+        files.sort();
+        let name = match dir.strip_prefix(logdir) {
+            Ok(name) if name.as_os_str().is_empty() => ".".to_string(),
+            Ok(name) => name.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/"),
+            Err(_) => dir.to_string_lossy().to_string(),
+        };
+        runs.push(Run { name, files })
+    }
+    subdirs.sort();
+    for subdir in subdirs {
+        // The symlinks are followed but each directory is only visited once, so that a
+        // symlink loop does not result in an infinite recursion.
+        let visit = std::fs::canonicalize(&subdir).map(|dir| visited.insert(dir));
+        if !matches!(visit, Ok(true)) {
+            continue;
+        }
+        // An unreadable subdirectory does not prevent the discovery of the other runs.
+        let _ = walk(logdir, &subdir, visited, runs);
+    }
+    Ok(())
+}
+
+/// Returns the runs in a log directory, sorted by name. The subdirectories that cannot be
+/// read are skipped.
+pub fn runs<P: AsRef<Path>>(logdir: P) -> Result<Vec<Run>> {
+    let logdir = logdir.as_ref();
+    if !logdir.is_dir() {
+        crate::bail!("{logdir:?} is not a directory")
+    }
+    let mut runs = vec![];
+    let mut visited = HashSet::from([std::fs::canonicalize(logdir)?]);
+    walk(logdir, logdir, &mut visited, &mut runs)?;
+    runs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(runs)
+}
+
+/// Load all the runs from a log directory, sequentially.
+pub fn load<P: AsRef<Path>>(logdir: P) -> Result<Vec<LoadedRun>> {
+    Ok(runs(logdir)?.iter().map(|run| run.load()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    fn write_run(dir: &Path, step: i64) {
+        let mut writer = crate::EventWriter::create(dir).unwrap();
+        writer.write_scalar(step, "loss", 1.).unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn discover_runs() {
+        let logdir = temp_dir("logdir-runs");
+        write_run(&logdir, 0);
+        write_run(&logdir.join("a"), 1);
+        write_run(&logdir.join("a"), 2);
+        write_run(&logdir.join("b/c"), 3);
+        std::fs::create_dir_all(logdir.join("empty")).unwrap();
+        std::fs::write(logdir.join("b/notes.txt"), "not an event file").unwrap();
+        let runs = runs(&logdir).unwrap();
+        let names: Vec<_> = runs.iter().map(|r| (r.name.as_str(), r.files.len())).collect();
+        assert_eq!(names, [(".", 1), ("a", 2), ("b/c", 1)]);
+        let loaded = load(&logdir).unwrap();
+        assert!(loaded.iter().all(|run| run.errors.is_empty()));
+        assert_eq!(loaded[1].accumulator.scalars("loss").unwrap().len(), 2);
+        std::fs::remove_dir_all(logdir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop() {
+        let logdir = temp_dir("logdir-symlinks");
+        write_run(&logdir.join("a/b"), 0);
+        std::os::unix::fs::symlink(logdir.join("a"), logdir.join("a/b/loop")).unwrap();
+        std::os::unix::fs::symlink(logdir.join("missing"), logdir.join("dangling")).unwrap();
+        let runs = runs(&logdir).unwrap();
+        let names: Vec<_> = runs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["a/b"]);
+        std::fs::remove_dir_all(logdir).unwrap();
+    }
+}
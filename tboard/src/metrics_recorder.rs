@@ -0,0 +1,227 @@
+// A recorder for the `metrics` facade. The counters, gauges, and histograms are accumulated
+// in memory and written on each snapshot: counters and gauges as scalars with their current
+// value, and histograms with the values recorded since the previous snapshot, the histograms
+// without new values being skipped. Snapshots are taken explicitly or periodically from a
+// background thread.
+//
+// The tag of a metric is its name with the dots replaced by slashes so that tensorboard
+// groups the tags, followed by its labels, e.g. `pipeline.latency{stage=decode}` is written
+// as `pipeline/latency/stage=decode`.
+use crate::histogram::Histogram;
+use crate::step::StepSource;
+use crate::{EventWriter, Result};
+use metrics::{Counter, Gauge, Key, KeyName, Metadata, SharedString, Unit};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct HistogramHandle(Mutex<Histogram>);
+
+impl metrics::HistogramFn for HistogramHandle {
+    fn record(&self, value: f64) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).add(value)
+    }
+}
+
+struct Inner<W: std::io::Write> {
+    writer: Mutex<EventWriter<W>>,
+    steps: StepSource,
+    counters: Mutex<BTreeMap<String, Arc<AtomicU64>>>,
+    gauges: Mutex<BTreeMap<String, Arc<AtomicU64>>>,
+    histograms: Mutex<BTreeMap<String, Arc<HistogramHandle>>>,
+}
+
+pub struct MetricsRecorder<W: std::io::Write> {
+    inner: Arc<Inner<W>>,
+}
+
+impl<W: std::io::Write> Clone for MetricsRecorder<W> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn tag(key: &Key) -> String {
+    let mut tag = key.name().replace('.', "/");
+    for label in key.labels() {
+        tag.push_str(&format!("/{}={}", label.key(), label.value()))
+    }
+    tag
+}
+
+// Returns the handle for a tag, creating it if needed.
+fn handle<T>(map: &Mutex<BTreeMap<String, Arc<T>>>, key: &Key, f: impl FnOnce() -> T) -> Arc<T> {
+    lock(map).entry(tag(key)).or_insert_with(|| Arc::new(f())).clone()
+}
+
+impl<W: std::io::Write> MetricsRecorder<W> {
+    /// Create a recorder, the steps are counted per tag, i.e. they are the snapshot indexes
+    /// for the counters and gauges. The recorder can then be installed with
+    /// `metrics::set_global_recorder`.
+    pub fn new(writer: EventWriter<W>) -> Self {
+        Self::with_step_source(writer, StepSource::default())
+    }
+
+    pub fn with_step_source(writer: EventWriter<W>, steps: StepSource) -> Self {
+        let inner = Inner {
+            writer: Mutex::new(writer),
+            steps,
+            counters: Mutex::new(BTreeMap::new()),
+            gauges: Mutex::new(BTreeMap::new()),
+            histograms: Mutex::new(BTreeMap::new()),
+        };
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Write the current value of the metrics and flush the writer.
+    pub fn snapshot(&self) -> Result<()> {
+        let inner = self.inner.as_ref();
+        // The values are collected before locking the writer so that the metrics can still
+        // be updated while writing.
+        let mut scalars = vec![];
+        for (tag, counter) in lock(&inner.counters).iter() {
+            scalars.push((tag.clone(), counter.load(Ordering::Relaxed) as f32))
+        }
+        for (tag, gauge) in lock(&inner.gauges).iter() {
+            scalars.push((tag.clone(), f64::from_bits(gauge.load(Ordering::Relaxed)) as f32))
+        }
+        let mut histograms = vec![];
+        for (tag, histogram) in lock(&inner.histograms).iter() {
+            let mut histogram = lock(&histogram.0);
+            if !histogram.is_empty() {
+                histograms.push((tag.clone(), histogram.clone()));
+                histogram.clear()
+            }
+        }
+        let mut writer = lock(&inner.writer);
+        for (tag, value) in scalars {
+            writer.write_scalar(inner.steps.step(&tag), &tag, value)?
+        }
+        for (tag, histogram) in histograms {
+            writer.write_histogram(inner.steps.step(&tag), &tag, &histogram)?
+        }
+        writer.flush()
+    }
+
+    /// Take a snapshot every `interval` from a background thread, until the returned handle
+    /// is stopped or dropped. A last snapshot is taken when stopping.
+    pub fn spawn_snapshots(&self, interval: std::time::Duration) -> SnapshotThread
+    where
+        W: Send + 'static,
+    {
+        let (stop, rx) = std::sync::mpsc::channel();
+        let recorder = self.clone();
+        let thread = std::thread::spawn(move || loop {
+            match rx.recv_timeout(interval) {
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    // The errors are only reported for the last snapshot.
+                    let _ = recorder.snapshot();
+                }
+                _ => return recorder.snapshot(),
+            }
+        });
+        SnapshotThread { stop, thread: Some(thread) }
+    }
+}
+
+pub struct SnapshotThread {
+    stop: std::sync::mpsc::Sender<()>,
+    thread: Option<std::thread::JoinHandle<Result<()>>>,
+}
+
+impl SnapshotThread {
+    /// Stop the thread, returning the result of the last snapshot.
+    pub fn stop(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<()> {
+        let _ = self.stop.send(());
+        match self.thread.take().map(|t| t.join()) {
+            None => Ok(()),
+            Some(Ok(res)) => res,
+            Some(Err(_)) => crate::bail!("snapshot thread panicked"),
+        }
+    }
+}
+
+impl Drop for SnapshotThread {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+impl<W: std::io::Write> metrics::Recorder for MetricsRecorder<W> {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _desc: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _desc: SharedString) {}
+
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _desc: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        Counter::from_arc(handle(&self.inner.counters, key, || AtomicU64::new(0)))
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(handle(&self.inner.gauges, key, || AtomicU64::new(0f64.to_bits())))
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> metrics::Histogram {
+        let histogram = || HistogramHandle(Mutex::new(Histogram::default()));
+        metrics::Histogram::from_arc(handle(&self.inner.histograms, key, histogram))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::SharedBuffer;
+
+    #[test]
+    fn snapshots() {
+        let buffer = SharedBuffer::default();
+        let recorder =
+            MetricsRecorder::new(EventWriter::from_writer(buffer.clone(), None).unwrap());
+        let (items, queue, latency) = metrics::with_local_recorder(&recorder, || {
+            let items = metrics::counter!("items.count");
+            let queue = metrics::gauge!("queue.size");
+            let latency = metrics::histogram!("pipeline.latency", "stage" => "decode");
+            (items, queue, latency)
+        });
+        items.increment(3);
+        queue.set(1.5);
+        latency.record(0.1);
+        latency.record(0.3);
+        recorder.snapshot().unwrap();
+        // The histogram has no new samples so it is skipped.
+        items.increment(2);
+        queue.set(-2.);
+        recorder.snapshot().unwrap();
+
+        // The thread takes a last snapshot when stopped.
+        let thread = recorder.spawn_snapshots(std::time::Duration::from_secs(3600));
+        items.increment(1);
+        latency.record(0.2);
+        thread.stop().unwrap();
+
+        let values = buffer.values();
+        let values: Vec<_> = values.iter().map(|(t, s, v)| (t.as_str(), *s, *v)).collect();
+        assert_eq!(
+            values,
+            [
+                ("items/count", 0, 3.),
+                ("queue/size", 0, 1.5),
+                ("pipeline/latency/stage=decode", 0, 2.),
+                ("items/count", 1, 5.),
+                ("queue/size", 1, -2.),
+                ("items/count", 2, 6.),
+                ("queue/size", 2, -2.),
+                ("pipeline/latency/stage=decode", 1, 1.),
+            ]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+// An accumulator per run of a log directory that is kept up to date as the event files
+// grow, similar to the tensorboard EventMultiplexer. Each reload discovers the new runs and
+// files, and only reads the records appended since the previous reload.
+//
+// A reload can be split in two steps so that a multiplexer shared between threads is only
+// locked while the new data is added: `Positions::read_updates` reads the new events from
+// a snapshot of the file positions, and `Multiplexer::apply` adds them to the accumulators.
+use crate::accumulator::SizeGuidance;
+use crate::{tensorboard, Error, EventAccumulator, Result, SummaryReader};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+struct FileState {
+    // The position of the next record to be read.
+    offset: u64,
+    index: u64,
+    // The last error reading this file, if any.
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RunState {
+    accumulator: EventAccumulator,
+    files: BTreeMap<PathBuf, FileState>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Multiplexer {
+    logdir: PathBuf,
+    size_guidance: SizeGuidance,
+    runs: BTreeMap<String, RunState>,
+}
+
+/// The positions in the event files of a log directory up to which the events have been
+/// read, obtained with `Multiplexer::positions`.
+#[derive(Debug, Clone)]
+pub struct Positions {
+    logdir: PathBuf,
+    files: BTreeMap<PathBuf, FileState>,
+}
+
+// A file with its new state and the events read since the previous state.
+type FileUpdate = (PathBuf, FileState, Vec<tensorboard::Event>);
+
+/// The events read since some positions, to be added to a multiplexer with
+/// `Multiplexer::apply`.
+#[derive(Debug, Clone, Default)]
+pub struct Updates {
+    // The updated files for each run, in order.
+    runs: Vec<(String, Vec<FileUpdate>)>,
+}
+
+// Reads the records appended to a file since the last read. A truncated record at the end
+// of the file is read again on the next call as it may still be written, whereas records
+// with an invalid crc are skipped.
+fn read_new_events(
+    path: &Path,
+    state: &mut FileState,
+    events: &mut Vec<tensorboard::Event>,
+) -> Result<()> {
+    let file = std::fs::File::open(path)?;
+    if file.metadata()?.len() == state.offset {
+        return Ok(());
+    }
+    let mut reader = SummaryReader::new(std::io::BufReader::new(file)).with_path(path);
+    reader.seek(state.offset, state.index)?;
+    state.error = None;
+    loop {
+        match reader.next() {
+            None => break,
+            Some(Ok(event)) => events.push(event),
+            Some(Err(err @ (Error::CrcMismatch { .. } | Error::RecordDecode { .. }))) => {
+                state.error = Some(err.to_string())
+            }
+            Some(Err(err)) => {
+                if let Some(pos) = err.record_pos() {
+                    (state.offset, state.index) = (pos.offset, pos.index)
+                }
+                if !matches!(err, Error::TruncatedRecord { .. }) {
+                    state.error = Some(err.to_string())
+                }
+                return Ok(());
+            }
+        }
+    }
+    (state.offset, state.index) = (reader.offset(), reader.index());
+    Ok(())
+}
+
+impl Positions {
+    /// Discover the new runs and files, and read the data appended to the known files. With
+    /// the `rayon` feature the runs are read in parallel, the files within a run are always
+    /// read in order.
+    pub fn read_updates(&self) -> Result<Updates> {
+        let runs = crate::logdir::runs(&self.logdir)?;
+        let read_run = |run: crate::logdir::Run| {
+            let mut files = vec![];
+            for path in run.files {
+                let mut state = self.files.get(&path).cloned().unwrap_or_default();
+                let mut events = vec![];
+                if let Err(err) = read_new_events(&path, &mut state, &mut events) {
+                    state.error = Some(err.to_string())
+                }
+                files.push((path, state, events))
+            }
+            (run.name, files)
+        };
+        #[cfg(feature = "rayon")]
+        let runs = {
+            use rayon::prelude::*;
+            runs.into_par_iter().map(read_run).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let runs = runs.into_iter().map(read_run).collect();
+        Ok(Updates { runs })
+    }
+}
+
+impl Multiplexer {
+    /// Create a multiplexer for a log directory, no data is read before calling `reload`.
+    /// The accumulators use the tensorboard size guidance.
+    pub fn new<P: AsRef<Path>>(logdir: P) -> Self {
+        Self {
+            logdir: logdir.as_ref().to_path_buf(),
+            size_guidance: SizeGuidance::TENSORBOARD,
+            runs: BTreeMap::new(),
+        }
+    }
+
+    /// The size guidance for the accumulators of the runs discovered after this call.
+    pub fn with_size_guidance(mut self, size_guidance: SizeGuidance) -> Self {
+        self.size_guidance = size_guidance;
+        self
+    }
+
+    pub fn logdir(&self) -> &Path {
+        self.logdir.as_path()
+    }
+
+    /// Discover the new runs and files, and read the data appended to the known files.
+    pub fn reload(&mut self) -> Result<()> {
+        let updates = self.positions().read_updates()?;
+        self.apply(updates);
+        Ok(())
+    }
+
+    pub fn positions(&self) -> Positions {
+        let files = self.runs.values().flat_map(|run| run.files.clone()).collect();
+        Positions { logdir: self.logdir.clone(), files }
+    }
+
+    /// Add the events read since the positions from this multiplexer. The updates are
+    /// expected to be applied in order, once each.
+    pub fn apply(&mut self, updates: Updates) {
+        for (run, files) in updates.runs {
+            let state = self.runs.entry(run).or_insert_with(|| RunState {
+                accumulator: EventAccumulator::new(true).with_size_guidance(self.size_guidance),
+                files: BTreeMap::new(),
+            });
+            for (path, file_state, events) in files {
+                for event in events {
+                    state.accumulator.add_event(event)
+                }
+                state.files.insert(path, file_state);
+            }
+        }
+    }
+
+    /// The run names, sorted.
+    pub fn runs(&self) -> impl Iterator<Item = &str> {
+        self.runs.keys().map(|v| v.as_str())
+    }
+
+    pub fn accumulator(&self, run: &str) -> Option<&EventAccumulator> {
+        self.runs.get(run).map(|v| &v.accumulator)
+    }
+
+    /// The last error for each file that could not be read fully.
+    pub fn errors(&self) -> impl Iterator<Item = (&Path, &str)> {
+        self.runs.values().flat_map(|run| {
+            run.files.iter().filter_map(|(path, s)| s.error.as_deref().map(|e| (path.as_path(), e)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{scalar_events, temp_dir};
+
+    fn steps(mux: &Multiplexer, run: &str) -> Vec<i64> {
+        let scalars = mux.accumulator(run).and_then(|acc| acc.scalars("loss"));
+        scalars.unwrap_or_default().iter().map(|e| e.step).collect()
+    }
+
+    #[test]
+    fn reload() {
+        let dir = temp_dir("multiplexer-reload");
+        std::fs::create_dir_all(dir.join("eval")).unwrap();
+        let path = dir.join("events.out.tfevents.1.test");
+        let data = scalar_events(&[("loss", 1, 1.), ("loss", 2, 0.5), ("loss", 3, 0.25)]);
+        let first_len = scalar_events(&[("loss", 1, 1.)]).len();
+        std::fs::write(&path, &data[..first_len + 5]).unwrap();
+        let mut mux = Multiplexer::new(&dir);
+        mux.reload().unwrap();
+        assert_eq!(mux.runs().collect::<Vec<_>>(), ["."]);
+        assert_eq!(steps(&mux, "."), [1]);
+
+        // The updates are read from the positions without borrowing the multiplexer.
+        std::fs::write(&path, &data).unwrap();
+        let eval = dir.join("eval/events.out.tfevents.1.test");
+        std::fs::write(&eval, scalar_events(&[("loss", 7, 0.)])).unwrap();
+        let updates = mux.positions().read_updates().unwrap();
+        assert_eq!(steps(&mux, "."), [1]);
+        mux.apply(updates);
+        assert_eq!(mux.runs().collect::<Vec<_>>(), [".", "eval"]);
+        assert_eq!(steps(&mux, "."), [1, 2, 3]);
+        assert_eq!(steps(&mux, "eval"), [7]);
+        mux.reload().unwrap();
+        assert_eq!(steps(&mux, "."), [1, 2, 3]);
+        assert_eq!(mux.errors().count(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn size_guidance() {
+        let dir = temp_dir("multiplexer-size");
+        let scalars: Vec<_> = (0..50).map(|step| ("loss", step, 0.)).collect();
+        std::fs::write(dir.join("events.out.tfevents.1.test"), scalar_events(&scalars)).unwrap();
+        let limits = SizeGuidance { scalars: 5, ..SizeGuidance::TENSORBOARD };
+        let mut mux = Multiplexer::new(&dir).with_size_guidance(limits);
+        mux.reload().unwrap();
+        let steps = steps(&mux, ".");
+        assert_eq!((steps.len(), steps.last()), (5, Some(&49)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
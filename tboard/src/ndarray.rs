@@ -0,0 +1,183 @@
+// Conversions between ndarray arrays and the tensor, histogram, and image summaries. The
+// arrays are written in their logical row-major order whatever their memory layout, and
+// the tensor shapes are preserved.
+pub use crate::grid::{ImageLayout, Pixel};
+use crate::histogram::Histogram;
+use crate::writer::{tensor_shape, IntoTensor, TensorType};
+use crate::{tensorboard, EventWriter, Result};
+use ::ndarray::{ArrayBase, ArrayD, Data, Dimension, Ix3, IxDyn};
+
+impl<A, S, D> IntoTensor for &ArrayBase<S, D>
+where
+    A: TensorType + Clone,
+    S: Data<Elem = A>,
+    D: Dimension,
+{
+    fn into_tensor_proto(self) -> Result<tensorboard::TensorProto> {
+        let mut tensor = A::into_proto(self.iter().cloned().collect());
+        tensor.tensor_shape = Some(tensor_shape(self.shape()));
+        Ok(tensor)
+    }
+}
+
+impl<A, S, D> IntoTensor for ArrayBase<S, D>
+where
+    A: TensorType + Clone,
+    S: Data<Elem = A>,
+    D: Dimension,
+{
+    fn into_tensor_proto(self) -> Result<tensorboard::TensorProto> {
+        (&self).into_tensor_proto()
+    }
+}
+
+/// Decode a tensor with elements of type `T` into an array. The tensors without a shape
+/// are decoded as 1d arrays, and a single value is broadcast to the whole shape as this is
+/// how tensorflow encodes tensors where all the values are equal.
+pub fn to_array<T: TensorType + Clone>(tensor: &tensorboard::TensorProto) -> Result<ArrayD<T>> {
+    let dtype = T::into_proto(vec![]).dtype();
+    if tensor.dtype() != dtype {
+        crate::bail!("unexpected tensor dtype {:?}, expected {dtype:?}", tensor.dtype())
+    }
+    let values = match T::from_proto(tensor) {
+        None => crate::bail!(
+            "the tensor content of {} bytes cannot be decoded as {dtype:?} values",
+            tensor.tensor_content.len()
+        ),
+        Some(values) => values,
+    };
+    let shape = match tensor.tensor_shape.as_ref() {
+        None => vec![values.len()],
+        Some(shape) => {
+            let dims = shape.dim.iter().map(|d| usize::try_from(d.size));
+            match dims.collect::<std::result::Result<Vec<_>, _>>() {
+                Ok(dims) => dims,
+                Err(_) => crate::bail!("unexpected tensor shape {shape:?}"),
+            }
+        }
+    };
+    let numel = shape.iter().try_fold(1usize, |numel, &d| numel.checked_mul(d));
+    let numel = match numel {
+        None => crate::bail!("unexpected tensor shape {shape:?}"),
+        Some(numel) => numel,
+    };
+    let values = match values.as_slice() {
+        [v] if numel != 1 => {
+            let mut broadcast = vec![];
+            if broadcast.try_reserve_exact(numel).is_err() {
+                crate::bail!("cannot broadcast a tensor to shape {shape:?}")
+            }
+            broadcast.resize(numel, v.clone());
+            broadcast
+        }
+        _ => values,
+    };
+    match ArrayD::from_shape_vec(IxDyn(&shape), values) {
+        Ok(array) => Ok(array),
+        Err(err) => crate::bail!("unexpected tensor shape {shape:?}: {err}"),
+    }
+}
+
+impl<W: std::io::Write> EventWriter<W> {
+    /// Write a histogram of the values of an array using the tensorflow default buckets.
+    pub fn write_histogram_array<A, S, D>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        array: &ArrayBase<S, D>,
+    ) -> Result<()>
+    where
+        A: Into<f64> + Copy,
+        S: Data<Elem = A>,
+        D: Dimension,
+    {
+        let mut histogram = Histogram::default();
+        for &v in array.iter() {
+            histogram.add(v.into())
+        }
+        self.write_histogram(step, tag, &histogram)
+    }
+
+    /// Write an image from an array with 1 to 4 channels, see `write_image_pixels`.
+    pub fn write_image_array<A, S>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        array: &ArrayBase<S, Ix3>,
+        layout: ImageLayout,
+    ) -> Result<()>
+    where
+        A: Pixel,
+        S: Data<Elem = A>,
+    {
+        let array = match layout {
+            ImageLayout::Hwc => array.view(),
+            ImageLayout::Chw => array.view().permuted_axes([1, 2, 0]),
+        };
+        let (height, width, channels) = array.dim();
+        let pixels: Vec<u8> = array.iter().map(|v| v.to_u8()).collect();
+        self.write_image_pixels(step, tag, width, height, channels, &pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let array = ::ndarray::Array::from_shape_fn((2, 3), |(i, j)| (i * 3 + j) as f32);
+        let tensor = array.t().into_tensor_proto().unwrap();
+        let dims: Vec<_> =
+            tensor.tensor_shape.as_ref().unwrap().dim.iter().map(|d| d.size).collect();
+        assert_eq!(dims, [3, 2]);
+        assert_eq!(tensor.float_val, [0., 3., 1., 4., 2., 5.]);
+        let decoded = to_array::<f32>(&tensor).unwrap();
+        assert_eq!(decoded, array.t().into_dyn());
+        let err = to_array::<f64>(&tensor).unwrap_err();
+        assert_eq!(err.to_string(), "unexpected tensor dtype DtFloat, expected DtDouble");
+    }
+
+    #[test]
+    fn content_and_broadcast() {
+        let mut tensor = f32::into_proto(vec![]);
+        tensor.tensor_content = [1f32, 2.].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(to_array::<f32>(&tensor).unwrap().into_raw_vec_and_offset().0, [1., 2.]);
+        tensor.tensor_content.pop();
+        let err = to_array::<f32>(&tensor).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the tensor content of 7 bytes cannot be decoded as DtFloat values"
+        );
+
+        let mut tensor = i64::into_proto(vec![7]);
+        tensor.tensor_shape = Some(tensor_shape(&[2, 2]));
+        assert_eq!(to_array::<i64>(&tensor).unwrap(), ArrayD::from_elem(IxDyn(&[2, 2]), 7));
+        tensor.tensor_shape = Some(tensor_shape(&[1 << 40, 1 << 40]));
+        let err = to_array::<i64>(&tensor).unwrap_err();
+        assert!(err.to_string().starts_with("unexpected tensor shape"), "{err}");
+    }
+
+    #[test]
+    fn write_only_type() {
+        // A type implementing only `into_proto` can be written but not read back.
+        #[derive(Debug, Clone)]
+        struct Half(u16);
+        impl TensorType for Half {
+            fn into_proto(v: Vec<Self>) -> tensorboard::TensorProto {
+                tensorboard::TensorProto {
+                    dtype: tensorboard::DataType::DtHalf.into(),
+                    half_val: v.into_iter().map(|v| v.0 as i32).collect(),
+                    ..Default::default()
+                }
+            }
+        }
+        let tensor = ::ndarray::arr1(&[Half(1), Half(2)]).into_tensor_proto().unwrap();
+        assert_eq!(tensor.half_val, [1, 2]);
+        let err = to_array::<Half>(&tensor).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the tensor content of 0 bytes cannot be decoded as DtHalf values"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// Parallel ingestion of multiple event files or runs using rayon. Each file or run is
+// processed independently so that an error only impacts the file where it occurred.
+use crate::logdir::{LoadedRun, Run};
+use crate::{tensorboard, EventAccumulator, Result, SummaryReader};
+use rayon::prelude::*;
+use std::path::Path;
+
+/// Load some runs in parallel, the files within a run are read sequentially so that the
+/// restart purging semantics are preserved.
+pub fn load_runs(runs: &[Run]) -> Vec<LoadedRun> {
+    runs.par_iter().map(|run| run.load()).collect()
+}
+
+/// Load all the runs from a log directory in parallel.
+pub fn load_logdir<P: AsRef<Path>>(logdir: P) -> Result<Vec<LoadedRun>> {
+    let runs = crate::logdir::runs(logdir)?;
+    Ok(load_runs(&runs))
+}
+
+/// Accumulate each file separately, in parallel.
+pub fn load_files<P: AsRef<Path> + Sync>(files: &[P]) -> Vec<Result<EventAccumulator>> {
+    files
+        .par_iter()
+        .map(|path| {
+            let mut accumulator = EventAccumulator::default();
+            accumulator.load(open(path.as_ref())?)?;
+            Ok(accumulator)
+        })
+        .collect()
+}
+
+fn open(path: &Path) -> Result<SummaryReader<std::io::BufReader<std::fs::File>>> {
+    let file = std::fs::File::open(path)?;
+    Ok(SummaryReader::new(std::io::BufReader::new(file)).with_path(path))
+}
+
+/// Fold the events of each file in parallel, the results are returned in the same order
+/// as `files`. For each file the fold starts from `init()` and the result is an error if
+/// the file could not be fully read.
+pub fn fold_files<P, T, I, F>(files: &[P], init: I, fold: F) -> Vec<Result<T>>
+where
+    P: AsRef<Path> + Sync,
+    T: Send,
+    I: Fn() -> T + Sync,
+    F: Fn(T, tensorboard::Event) -> T + Sync,
+{
+    files
+        .par_iter()
+        .map(|path| {
+            let mut acc = init();
+            for event in open(path.as_ref())? {
+                acc = fold(acc, event?)
+            }
+            Ok(acc)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{scalar_events, temp_dir};
+
+    #[test]
+    fn error_isolation() {
+        let dir = temp_dir("parallel");
+        let good = dir.join("events.out.tfevents.1.good");
+        let corrupt = dir.join("events.out.tfevents.2.corrupt");
+        let data = scalar_events(&[("loss", 0, 1.), ("loss", 1, 0.5)]);
+        std::fs::write(&good, &data).unwrap();
+        let mut corrupted = data.clone();
+        let len = corrupted.len();
+        corrupted[len - 10] ^= 1;
+        std::fs::write(&corrupt, &corrupted).unwrap();
+        let missing = dir.join("events.out.tfevents.3.missing");
+
+        let files = [&good, &corrupt, &missing];
+        let results = load_files(&files);
+        assert_eq!(results[0].as_ref().unwrap().scalars("loss").unwrap().len(), 2);
+        assert!(matches!(results[1], Err(crate::Error::CrcMismatch { .. })));
+        assert!(matches!(results[2], Err(crate::Error::Io(_))));
+
+        let steps = fold_files(&files, Vec::new, |mut steps, event| {
+            steps.push(event.step);
+            steps
+        });
+        assert_eq!(steps[0].as_ref().unwrap(), &[0, 0, 1]);
+        assert!(steps[1].is_err() && steps[2].is_err());
+
+        // Within a run, the events read before an error are kept: the first scalar of the
+        // corrupted file is loaded after the two scalars of the valid file.
+        let runs = load_logdir(&dir).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].errors.len(), 1);
+        assert_eq!(runs[0].errors[0].0, corrupt);
+        let scalars = runs[0].accumulator.scalars("loss").unwrap();
+        assert_eq!(scalars.iter().map(|s| s.step).collect::<Vec<_>>(), [0, 1, 0]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
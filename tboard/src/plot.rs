@@ -0,0 +1,290 @@
+// Text rendering of scalar series for terminals: sparklines using the block characters and
+// line charts using the braille characters, each character cell holding 2x4 dots.
+use std::fmt::Write;
+
+/// Smooth some values with the debiased exponential moving average used by tensorboard,
+/// `weight` is between 0 (no smoothing) and 1. Non-finite values are kept as is and do not
+/// contribute to the average, constant series are not smoothed.
+pub fn smooth(values: &[f64], weight: f64) -> Vec<f64> {
+    if values.iter().all(|&v| v == values[0]) {
+        return values.to_vec();
+    }
+    let mut last = 0.;
+    let mut num_accum = 0;
+    values
+        .iter()
+        .map(|&v| {
+            if !v.is_finite() {
+                return v;
+            }
+            last = last * weight + (1. - weight) * v;
+            num_accum += 1;
+            let debias = if weight == 1. { 1. } else { 1. - weight.powi(num_accum) };
+            last / debias
+        })
+        .collect()
+}
+
+// Returns the min and max of the finite values, `None` if there is no such value.
+fn finite_range(values: impl Iterator<Item = f64>) -> Option<(f64, f64)> {
+    values.filter(|v| v.is_finite()).fold(None, |acc, v| match acc {
+        None => Some((v, v)),
+        Some((min, max)) => Some((f64::min(min, v), f64::max(max, v))),
+    })
+}
+
+/// Render some values as a sparkline of at most `width` characters, the values are averaged
+/// over buckets when there are more values than characters. The buckets without finite
+/// values are rendered as spaces.
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let width = width.min(values.len());
+    let buckets: Vec<f64> = (0..width)
+        .map(|i| {
+            let bucket = &values[i * values.len() / width..(i + 1) * values.len() / width];
+            let finite: Vec<f64> = bucket.iter().copied().filter(|v| v.is_finite()).collect();
+            finite.iter().sum::<f64>() / finite.len() as f64
+        })
+        .collect();
+    let (min, max) = match finite_range(buckets.iter().copied()) {
+        None => return " ".repeat(width),
+        Some(range) => range,
+    };
+    buckets
+        .iter()
+        .map(|&v| {
+            if !v.is_finite() {
+                ' '
+            } else if max == min {
+                BLOCKS[BLOCKS.len() / 2]
+            } else {
+                let index = ((v - min) / (max - min) * (BLOCKS.len() - 1) as f64).round();
+                BLOCKS[index as usize]
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub label: String,
+    /// The (x, y) points, usually (step, value), sorted by x.
+    pub points: Vec<(f64, f64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChartOptions {
+    /// The size of the plotting area in characters, the axes and labels come on top.
+    pub width: usize,
+    pub height: usize,
+    /// Use a log scale for the y axis, the non-positive values are not plotted.
+    pub log_scale: bool,
+    /// Use ANSI escape codes to draw each series in a different color.
+    pub color: bool,
+}
+
+impl Default for ChartOptions {
+    fn default() -> Self {
+        Self { width: 70, height: 16, log_scale: false, color: false }
+    }
+}
+
+const COLORS: [u8; 6] = [34, 31, 32, 33, 35, 36];
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    dots: Vec<u8>,
+    colors: Vec<Option<usize>>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self { width, height, dots: vec![0; width * height], colors: vec![None; width * height] }
+    }
+
+    // Sets a dot using pixel coordinates, y = 0 being the bottom of the canvas.
+    fn set(&mut self, x: i64, y: i64, series: usize) {
+        // The bits for the dots of the left and right columns, from top to bottom.
+        const BITS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        let (w, h) = (self.width as i64 * 2, self.height as i64 * 4);
+        if x < 0 || y < 0 || x >= w || y >= h {
+            return;
+        }
+        let y = h - 1 - y;
+        let cell = (y / 4) as usize * self.width + (x / 2) as usize;
+        self.dots[cell] |= BITS[(x % 2) as usize][(y % 4) as usize];
+        self.colors[cell] = Some(series)
+    }
+
+    fn line(&mut self, (x0, y0): (i64, i64), (x1, y1): (i64, i64), series: usize) {
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).max(1);
+        for i in 0..=steps {
+            let x = x0 + (x1 - x0) * i / steps;
+            let y = y0 + (y1 - y0) * i / steps;
+            self.set(x, y, series)
+        }
+    }
+
+    fn row(&self, row: usize, color: bool) -> String {
+        let mut s = String::new();
+        for col in 0..self.width {
+            let cell = row * self.width + col;
+            let c = char::from_u32(0x2800 + self.dots[cell] as u32).unwrap_or(' ');
+            match self.colors[cell] {
+                Some(series) if color && self.dots[cell] != 0 => {
+                    let _ = write!(s, "\x1b[{}m{c}\x1b[0m", COLORS[series % COLORS.len()]);
+                }
+                _ => s.push(c),
+            }
+        }
+        s
+    }
+}
+
+fn format_label(v: f64) -> String {
+    let abs = v.abs();
+    if abs != 0. && !(1e-3..1e5).contains(&abs) {
+        format!("{v:.2e}")
+    } else {
+        let s = format!("{v:.4}");
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+/// Render some series as a line chart using braille characters, with the y axis labels
+/// on the left, the x range below the chart, and a legend when there are multiple series.
+pub fn braille_chart(series: &[Series], opts: &ChartOptions) -> String {
+    let y_of = |y: f64| if opts.log_scale { y.log10() } else { y };
+    let points = || series.iter().flat_map(|s| s.points.iter());
+    let x_range = finite_range(points().map(|p| p.0));
+    let y_range = finite_range(points().map(|p| y_of(p.1)));
+    let ((x_min, x_max), (y_min, y_max)) = match (x_range, y_range) {
+        (Some(x_range), Some(y_range)) => (x_range, y_range),
+        _ => return "no data\n".to_string(),
+    };
+    let (width, height) = (opts.width.max(2), opts.height.max(2));
+    let mut canvas = Canvas::new(width, height);
+    let scale = |v: f64, min: f64, max: f64, pixels: usize| {
+        if max == min {
+            (pixels / 2) as i64
+        } else {
+            ((v - min) / (max - min) * (pixels - 1) as f64).round() as i64
+        }
+    };
+    for (index, s) in series.iter().enumerate() {
+        let mut prev = None;
+        for &(x, y) in s.points.iter() {
+            let y = y_of(y);
+            if !x.is_finite() || !y.is_finite() {
+                prev = None;
+                continue;
+            }
+            let p = (scale(x, x_min, x_max, width * 2), scale(y, y_min, y_max, height * 4));
+            match prev {
+                None => canvas.set(p.0, p.1, index),
+                Some(prev) => canvas.line(prev, p, index),
+            }
+            prev = Some(p)
+        }
+    }
+    let label = |y: f64| format_label(if opts.log_scale { 10f64.powf(y) } else { y });
+    let labels = [label(y_max), label((y_min + y_max) / 2.), label(y_min)];
+    let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for row in 0..height {
+        let label = match row {
+            0 => labels[0].as_str(),
+            _ if row == height / 2 => labels[1].as_str(),
+            _ if row == height - 1 => labels[2].as_str(),
+            _ => "",
+        };
+        let tick = if label.is_empty() { '│' } else { '┤' };
+        let _ = writeln!(out, "{label:>label_width$} {tick}{}", canvas.row(row, opts.color));
+    }
+    let _ = writeln!(out, "{:>label_width$} └{}", "", "─".repeat(width));
+    let (x_min, x_max) = (format_label(x_min), format_label(x_max));
+    let pad = width.saturating_sub(x_min.len() + x_max.len());
+    let _ = writeln!(out, "{:>label_width$}  {x_min}{}{x_max}", "", " ".repeat(pad));
+    if series.len() > 1 {
+        for (index, s) in series.iter().enumerate() {
+            if opts.color {
+                let color = COLORS[index % COLORS.len()];
+                let _ = writeln!(out, "\x1b[{color}m⣿\x1b[0m {}", s.label);
+            } else {
+                let _ = writeln!(out, "{}", s.label);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(values: &[f64], expected: &[f64]) {
+        assert_eq!(values.len(), expected.len(), "{values:?}");
+        for (v, e) in values.iter().zip(expected.iter()) {
+            assert!((v - e).abs() < 1e-9 || (v.is_nan() && e.is_nan()), "{values:?} {expected:?}")
+        }
+    }
+
+    #[test]
+    fn smoothing() {
+        // The values from the tensorboard frontend for a smoothing weight of 0.6.
+        assert_close(&smooth(&[1., 2., 3.], 0.6), &[1., 1.625, 2.326530612244898]);
+        assert_close(&smooth(&[1., 2., 3.], 0.), &[1., 2., 3.]);
+        assert_close(&smooth(&[2.5; 4], 0.9), &[2.5; 4]);
+        assert!(smooth(&[], 0.6).is_empty());
+        // The non-finite values are kept and are skipped by the average.
+        let values = smooth(&[1., f64::NAN, 3., f64::INFINITY], 0.5);
+        assert_close(&values[..3], &[1., f64::NAN, 1.75 / 0.75]);
+        assert_eq!(values[3], f64::INFINITY);
+    }
+
+    #[test]
+    fn sparklines() {
+        assert_eq!(sparkline(&[], 10), "");
+        assert_eq!(sparkline(&[1., 2., 3.], 10), "▁▅█");
+        assert_eq!(sparkline(&[0., 2., 4., 6.], 2), "▁█");
+        assert_eq!(sparkline(&[3., 3.], 5), "▅▅");
+        assert_eq!(sparkline(&[1., f64::NAN, 3.], 3), "▁ █");
+        assert_eq!(sparkline(&[f64::NAN; 2], 3), "  ");
+    }
+
+    #[test]
+    fn braille_charts() {
+        let opts = ChartOptions { width: 10, height: 4, ..Default::default() };
+        assert_eq!(braille_chart(&[], &opts), "no data\n");
+        let empty = Series { label: "empty".to_string(), points: vec![] };
+        assert_eq!(braille_chart(&[empty], &opts), "no data\n");
+
+        // The chart is wider than the number of points.
+        let points = vec![(0., 0.), (1., 1.), (2., 2.)];
+        let line = Series { label: "line".to_string(), points };
+        let chart = braille_chart(std::slice::from_ref(&line), &opts);
+        let lines: Vec<&str> = chart.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "2 ┤⠀⠀⠀⠀⠀⠀⠀⠀⡠⠊");
+        assert_eq!(lines[2], "1 ┤⠀⠀⢀⡠⠊⠀⠀⠀⠀⠀");
+        assert_eq!(lines[3], "0 ┤⣀⠔⠁⠀⠀⠀⠀⠀⠀⠀");
+        assert_eq!(lines[4], "  └──────────");
+        assert_eq!(lines[5], "   0        2");
+
+        // With a log scale the non-positive values are dropped.
+        let points = vec![(0., -1.), (1., 0.), (2., 10.), (3., 1000.)];
+        let log = Series { label: "log".to_string(), points };
+        let opts = ChartOptions { log_scale: true, ..opts };
+        let other = Series { label: "other".to_string(), points: vec![(1., 100.)] };
+        let chart = braille_chart(&[log, other], &opts);
+        let lines: Vec<&str> = chart.lines().collect();
+        assert!(lines[0].starts_with("1000 ┤"), "{chart}");
+        assert!(lines[2].starts_with(" 100 ┤"), "{chart}");
+        assert!(lines[3].starts_with("  10 ┤"), "{chart}");
+        assert_eq!(&lines[6..], ["log", "other"]);
+        let points = vec![(0., -1.), (1., 0.)];
+        let negative = Series { label: "negative".to_string(), points };
+        assert_eq!(braille_chart(&[negative], &opts), "no data\n");
+    }
+}
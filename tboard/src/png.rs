@@ -0,0 +1,157 @@
+// A minimal PNG encoder for 8-bit images, so that raw pixels can be written as image
+// summaries without an image codec dependency. The image data is stored in uncompressed
+// deflate blocks, which results in larger files than a proper encoder but is fast and
+// simple.
+use crate::Result;
+
+const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = CRC.checksum(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream using stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 65535;
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &v in chunk {
+            a += v as u32;
+            b += a;
+        }
+        (a, b) = (a % 65521, b % 65521);
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+/// Encode an image as PNG, `pixels` contains the rows from top to bottom with the
+/// interleaved channels for each pixel, i.e. in HWC order.
+pub fn encode(width: usize, height: usize, channels: usize, pixels: &[u8]) -> Result<Vec<u8>> {
+    let color_type = match channels {
+        1 => 0,
+        2 => 4,
+        3 => 2,
+        4 => 6,
+        _ => crate::bail!("unsupported number of channels {channels}, expected 1 to 4"),
+    };
+    let len = width.checked_mul(height).and_then(|v| v.checked_mul(channels));
+    if len != Some(pixels.len()) {
+        crate::bail!(
+            "unexpected number of pixel values {}, expected {width}x{height}x{channels}",
+            pixels.len()
+        )
+    }
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize {
+        crate::bail!("unsupported image size {width}x{height}")
+    }
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+    // Each row starts with the filter type, 0 meaning no filtering.
+    let mut data = Vec::with_capacity(height * (width * channels + 1));
+    for row in pixels.chunks_exact(width * channels) {
+        data.push(0);
+        data.extend_from_slice(row);
+    }
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &ihdr);
+    chunk(&mut out, b"IDAT", &zlib_stored(&data));
+    chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the content of the IDAT chunk, inflated from its stored blocks, after checking
+    // the zlib checksum.
+    fn decode_idat(png: &[u8]) -> Vec<u8> {
+        let ihdr_len = 8 + 12 + 13;
+        let len = u32::from_be_bytes(png[ihdr_len..ihdr_len + 4].try_into().unwrap()) as usize;
+        assert_eq!(&png[ihdr_len + 4..ihdr_len + 8], b"IDAT");
+        let zlib = &png[ihdr_len + 8..ihdr_len + 8 + len];
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        let (mut pos, mut data) = (2, vec![]);
+        loop {
+            let is_final = zlib[pos] == 1;
+            let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]);
+            assert_eq!(!len, u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]));
+            data.extend_from_slice(&zlib[pos + 5..pos + 5 + len as usize]);
+            pos += 5 + len as usize;
+            if is_final {
+                break;
+            }
+        }
+        let adler = &zlib[pos..];
+        let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &v| {
+            let a = (a + v as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        assert_eq!(adler, ((b << 16) | a).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn encode_rgb() {
+        let pixels: Vec<u8> = (0..18).collect();
+        let png = encode(3, 2, 3, &pixels).unwrap();
+        assert_eq!(crate::validate::check_image(&png), Ok((3, 2)));
+        // The IHDR chunk: width, height, bit depth, color type, and no interlacing.
+        assert_eq!(png[16..29], [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        let mut expected = vec![0];
+        expected.extend_from_slice(&pixels[..9]);
+        expected.push(0);
+        expected.extend_from_slice(&pixels[9..]);
+        assert_eq!(decode_idat(&png), expected);
+    }
+
+    #[test]
+    fn encode_large() {
+        // The filtered data does not fit in a single stored block.
+        let (width, height) = (300, 250);
+        let pixels: Vec<u8> = (0..width * height).map(|i| (i % 251) as u8).collect();
+        let png = encode(width, height, 1, &pixels).unwrap();
+        let data = decode_idat(&png);
+        assert_eq!(data.len(), height * (width + 1));
+        for (row, expected) in data.chunks(width + 1).zip(pixels.chunks(width)) {
+            assert_eq!((row[0], &row[1..]), (0, expected));
+        }
+        #[cfg(feature = "image")]
+        {
+            let image = image::load_from_memory(&png).unwrap().to_luma8();
+            assert_eq!(image.dimensions(), (width as u32, height as u32));
+            assert_eq!(image.into_raw(), pixels);
+        }
+    }
+
+    #[test]
+    fn invalid_shapes() {
+        let err = |w, h, c, pixels: &[u8]| encode(w, h, c, pixels).unwrap_err().to_string();
+        assert_eq!(err(2, 2, 5, &[0; 20]), "unsupported number of channels 5, expected 1 to 4");
+        assert_eq!(err(2, 2, 1, &[0; 3]), "unexpected number of pixel values 3, expected 2x2x1");
+        let expected = format!("unexpected number of pixel values 0, expected {}x2x1", usize::MAX);
+        assert_eq!(err(usize::MAX, 2, 1, &[]), expected);
+        assert_eq!(err(0, 2, 1, &[]), "unsupported image size 0x2");
+    }
+}
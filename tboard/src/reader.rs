@@ -1,17 +1,168 @@
 // https://github.com/google/tsl/blob/2a6d8ef9f36c70eed0fe6400b248160d95afb817/tsl/lib/io/record_writer.cc#L99
-use crate::{masked_crc, tensorboard, Result};
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use crate::{masked_crc, tensorboard, Error, RecordPos, Result};
+use byteorder::{ByteOrder, LittleEndian};
 use prost::Message;
+use std::io::Read;
 
+// Length of the record header (u64 length + u32 crc) and footer (u32 crc).
+pub(crate) const HEADER_LEN: usize = 12;
+pub(crate) const FOOTER_LEN: usize = 4;
+
+// The maximum payload length, protobuf messages are limited to 2GB. Capping the length
+// ensures that the record length computations cannot overflow.
+pub(crate) const MAX_EVENT_LEN: u64 = i32::MAX as u64;
+
+// Checks the crc of a record header and returns the payload length.
+pub(crate) fn parse_header(header: &[u8], pos: &RecordPos) -> Result<u64> {
+    let event_len = LittleEndian::read_u64(&header[..8]);
+    let file_crc = LittleEndian::read_u32(&header[8..HEADER_LEN]);
+    let computed_crc = masked_crc(&header[..8]);
+    if file_crc != computed_crc {
+        return Err(Error::LenCrcMismatch { file_crc, computed_crc, pos: pos.clone() });
+    }
+    if event_len > MAX_EVENT_LEN {
+        let msg = format!("payload length {event_len} exceeds {MAX_EVENT_LEN}");
+        return Err(Error::InvalidRecord { msg, pos: pos.clone() });
+    }
+    Ok(event_len)
+}
+
+// Checks the crc of a record payload against the record footer.
+pub(crate) fn check_payload(payload: &[u8], footer: &[u8], pos: &RecordPos) -> Result<()> {
+    let file_crc = LittleEndian::read_u32(footer);
+    let computed_crc = masked_crc(payload);
+    if file_crc != computed_crc {
+        let len = payload.len() as u64;
+        return Err(Error::CrcMismatch { file_crc, computed_crc, len, pos: pos.clone() });
+    }
+    Ok(())
+}
+
+/// An iterator over the events of an event file.
+///
+/// The iteration carries on after an error: after a length crc mismatch the next record is
+/// read from the bytes following the header, so it usually results in more errors. It ends
+/// with the file, a file ending in the middle of a record, including in the middle of its
+/// header, yielding a final `Error::TruncatedRecord`.
 pub struct SummaryReader<R: std::io::Read> {
-    reader: R,
-    buf_len: [u8; 8],
+    pub(crate) reader: R,
+    buf_len: [u8; HEADER_LEN],
     buf: Vec<u8>,
+    path: Option<std::path::PathBuf>,
+    pub(crate) offset: u64,
+    pub(crate) index: u64,
+}
+
+impl SummaryReader<std::fs::File> {
+    /// Open an event file, the path is reported in the errors.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = std::fs::File::open(path)?;
+        Ok(Self::new(reader).with_path(path))
+    }
+}
+
+// Similar to `read_exact` but returns the number of bytes that have been read when
+// reaching the end of the stream.
+fn read_full<R: std::io::Read>(reader: &mut R, mut buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while !buf.is_empty() {
+        match reader.read(buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                read += n;
+                buf = &mut buf[n..];
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
 }
 
 impl<R: std::io::Read> SummaryReader<R> {
     pub fn new(reader: R) -> Self {
-        Self { reader, buf_len: Default::default(), buf: vec![0u8, 128] }
+        Self {
+            reader,
+            buf_len: Default::default(),
+            buf: vec![0u8, 128],
+            path: None,
+            offset: 0,
+            index: 0,
+        }
+    }
+
+    /// Set the path reported in errors.
+    pub fn with_path<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn path(&self) -> Option<&std::path::PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// The byte offset of the next record to be read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The ordinal of the next record to be read.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    fn pos(&self) -> RecordPos {
+        RecordPos { path: self.path.clone(), offset: self.offset, index: self.index }
+    }
+
+    // The bytes of the last record that has been read successfully, including its header
+    // and footer.
+    pub(crate) fn last_record(&self) -> Vec<u8> {
+        [self.buf_len.as_slice(), self.buf.as_slice()].concat()
+    }
+
+    fn read_record(&mut self) -> Option<Result<tensorboard::Event>> {
+        let pos = self.pos();
+        let read = match read_full(&mut self.reader, &mut self.buf_len) {
+            Ok(read) => read,
+            Err(source) => return Some(Err(Error::RecordIo { source, pos })),
+        };
+        self.offset += read as u64;
+        if read == 0 {
+            return None;
+        }
+        if read < HEADER_LEN {
+            let (expected_len, actual_len) = (None, 0);
+            return Some(Err(Error::TruncatedRecord { expected_len, actual_len, pos }));
+        }
+        let event_len = match parse_header(&self.buf_len, &pos) {
+            Ok(event_len) => event_len,
+            Err(err) => return Some(Err(err)),
+        };
+        let record_len = event_len as usize + FOOTER_LEN;
+        // The buffer grows with the data that is actually read rather than being allocated
+        // upfront, so that a corrupted length results in a truncated record rather than in
+        // a large allocation.
+        self.buf.clear();
+        let mut reader = (&mut self.reader).take(record_len as u64);
+        let read = match reader.read_to_end(&mut self.buf) {
+            Ok(read) => read,
+            Err(source) => return Some(Err(Error::RecordIo { source, pos })),
+        };
+        self.offset += read as u64;
+        if read < record_len {
+            let (expected_len, actual_len) = (Some(event_len), (read as u64).min(event_len));
+            return Some(Err(Error::TruncatedRecord { expected_len, actual_len, pos }));
+        }
+        let (payload, footer) = self.buf.split_at(event_len as usize);
+        if let Err(err) = check_payload(payload, footer, &pos) {
+            return Some(Err(err));
+        }
+        match tensorboard::Event::decode(payload) {
+            Ok(event) => Some(Ok(event)),
+            Err(source) => Some(Err(Error::RecordDecode { source, pos })),
+        }
     }
 }
 
@@ -19,41 +170,120 @@ impl<R: std::io::Read> Iterator for SummaryReader<R> {
     type Item = Result<tensorboard::Event>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.reader.read_exact(&mut self.buf_len) {
-            Ok(()) => {}
-            Err(err) => {
-                if err.kind() == std::io::ErrorKind::UnexpectedEof {
-                    return None;
-                }
-                return Some(Err(err.into()));
-            }
-        };
-        let computed_crc = masked_crc(&self.buf_len);
-        let event_len = LittleEndian::read_u64(&self.buf_len);
-        let file_crc = match self.reader.read_u32::<LittleEndian>() {
-            Ok(crc) => crc,
-            Err(err) => return Some(Err(err.into())),
-        };
-        if file_crc != computed_crc {
-            return Some(Err(crate::Error::LenCrcMismatch { file_crc, computed_crc }));
+        let record = self.read_record();
+        if record.is_some() {
+            self.index += 1;
         }
-        self.buf.resize(event_len as usize, 0u8);
-        match self.reader.read_exact(&mut self.buf) {
-            Ok(()) => {}
-            Err(err) => return Some(Err(err.into())),
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventWriter;
+
+    // An event file with the file version followed by 3 scalar events.
+    fn events() -> Vec<u8> {
+        let mut data = vec![];
+        let mut writer = EventWriter::from_writer(&mut data, None).unwrap();
+        for step in 0..3 {
+            writer.write_scalar(step, "loss", step as f32).unwrap();
         }
-        let event = match tensorboard::Event::decode(self.buf.as_slice()) {
-            Ok(event) => event,
-            Err(err) => return Some(Err(err.into())),
-        };
-        let file_crc = match self.reader.read_u32::<LittleEndian>() {
-            Ok(crc) => crc,
-            Err(err) => return Some(Err(err.into())),
-        };
-        let computed_crc = masked_crc(&self.buf);
-        if file_crc != computed_crc {
-            return Some(Err(crate::Error::CrcMismatch { file_crc, computed_crc }));
+        drop(writer);
+        data
+    }
+
+    fn record_offsets(data: &[u8]) -> Vec<u64> {
+        let mut reader = SummaryReader::new(data);
+        let mut offsets = vec![reader.offset()];
+        while let Some(event) = reader.next() {
+            event.unwrap();
+            offsets.push(reader.offset())
+        }
+        offsets
+    }
+
+    #[test]
+    fn read_all() {
+        let data = events();
+        let events: Vec<_> = SummaryReader::new(data.as_slice()).map(|e| e.unwrap()).collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events.iter().map(|e| e.step).collect::<Vec<_>>(), [0, 0, 1, 2]);
+        assert_eq!(record_offsets(&data).last(), Some(&(data.len() as u64)));
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let data = events();
+        let offsets = record_offsets(&data);
+        let payload_len = offsets[4] - offsets[3] - (HEADER_LEN + FOOTER_LEN) as u64;
+        let data = &data[..data.len() - 7];
+        let reader = SummaryReader::new(data).with_path("events.out.tfevents.test");
+        let results: Vec<_> = reader.collect();
+        assert_eq!(results.len(), 4);
+        match &results[3] {
+            Err(Error::TruncatedRecord { expected_len, actual_len, pos }) => {
+                assert_eq!(*expected_len, Some(payload_len));
+                assert_eq!(*actual_len, payload_len - 3);
+                assert_eq!(pos.offset, offsets[3]);
+                assert_eq!(pos.index, 3);
+                assert_eq!(pos.path.as_deref(), Some("events.out.tfevents.test".as_ref()));
+            }
+            err => panic!("unexpected result {err:?}"),
         }
-        Some(Ok(event))
+    }
+
+    #[test]
+    fn truncated_header() {
+        let data = events();
+        let offsets = record_offsets(&data);
+        let data = &data[..offsets[2] as usize + 5];
+        let err = SummaryReader::new(data).nth(2).unwrap().unwrap_err();
+        assert!(matches!(err, Error::TruncatedRecord { expected_len: None, actual_len: 0, .. }));
+        assert_eq!(err.record_pos().map(|p| (p.offset, p.index)), Some((offsets[2], 2)));
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut data = events();
+        let offsets = record_offsets(&data);
+        data[offsets[1] as usize + HEADER_LEN + 2] ^= 1;
+        let results: Vec<_> = SummaryReader::new(data.as_slice()).collect();
+        assert_eq!(results.len(), 4);
+        match &results[1] {
+            Err(Error::CrcMismatch { pos, .. }) => {
+                assert_eq!((pos.offset, pos.index), (offsets[1], 1))
+            }
+            err => panic!("unexpected result {err:?}"),
+        }
+        // The length is valid so the reader continues with the next record.
+        assert_eq!(results[2].as_ref().unwrap().step, 1);
+
+        data[offsets[2] as usize] ^= 1;
+        let err = SummaryReader::new(data.as_slice()).nth(2).unwrap().unwrap_err();
+        assert!(matches!(err, Error::LenCrcMismatch { .. }));
+    }
+
+    fn header(len: u64) -> Vec<u8> {
+        let mut header = len.to_le_bytes().to_vec();
+        header.extend_from_slice(&masked_crc(&header).to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn corrupted_length() {
+        // A length with a valid crc that goes past the end of the file.
+        let mut data = header(1 << 30);
+        data.extend_from_slice(&[0u8; 100]);
+        let err = SummaryReader::new(data.as_slice()).next().unwrap().unwrap_err();
+        assert!(matches!(
+            err,
+            Error::TruncatedRecord { expected_len: Some(0x4000_0000), actual_len: 100, .. }
+        ));
+
+        let data = header(u64::MAX - 2);
+        let err = SummaryReader::new(data.as_slice()).next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::InvalidRecord { .. }), "{err:?}");
     }
 }
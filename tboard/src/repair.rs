@@ -0,0 +1,312 @@
+// Repair of damaged event files: the valid records are copied as is to a new event file,
+// the corrupted records are dropped. Without resynchronization the copy stops at the first
+// corrupted record, which is enough for the files with a torn tail. With resynchronization
+// the reading continues after a corrupted region at the next offset where a record with
+// valid crcs starts.
+use crate::writer::EventWriter;
+use crate::{masked_crc, tensorboard, Error, Result, SummaryReader};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct RepairOptions {
+    /// Skip the corrupted regions rather than stopping at the first one.
+    pub resync: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// The number of events copied to the new file.
+    pub records: usize,
+    /// The number of records with a valid length that have been dropped because of an
+    /// invalid crc or of a payload that cannot be decoded.
+    pub dropped_records: usize,
+    /// The byte ranges (offset, length) from the original file that have been skipped.
+    pub skipped: Vec<(u64, u64)>,
+}
+
+impl RepairReport {
+    /// Returns true if the file did not need any repair.
+    pub fn is_clean(&self) -> bool {
+        self.dropped_records == 0 && self.skipped.is_empty()
+    }
+
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped.iter().map(|(_, len)| len).sum()
+    }
+}
+
+// Returns the first offset after `from` where a valid record starts.
+fn find_record(data: &[u8], from: usize) -> Option<usize> {
+    use crate::reader::{FOOTER_LEN, HEADER_LEN};
+
+    (from..data.len().saturating_sub(HEADER_LEN)).find(|&offset| {
+        let header = &data[offset..offset + HEADER_LEN];
+        let len = u64::from_le_bytes([
+            header[0], header[1], header[2], header[3], header[4], header[5], header[6], header[7],
+        ]);
+        let len_crc = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let fits = usize::try_from(len)
+            .ok()
+            .and_then(|len| (offset + HEADER_LEN).checked_add(len)?.checked_add(FOOTER_LEN))
+            .is_some_and(|end| end <= data.len());
+        masked_crc(&header[..8]) == len_crc
+            && fits
+            && matches!(SummaryReader::new(&data[offset..]).next(), Some(Ok(_)))
+    })
+}
+
+/// Copy the valid records from the content of an event file to a writer. The records are
+/// copied without being re-encoded, except for the file version events that are not copied
+/// as the writer already wrote one.
+pub fn repair_data<W: std::io::Write>(
+    data: &[u8],
+    writer: &mut EventWriter<W>,
+    opts: &RepairOptions,
+) -> Result<RepairReport> {
+    let mut report = RepairReport::default();
+    // The offset in `data` of the current reader.
+    let mut start = 0;
+    let mut reader = SummaryReader::new(data);
+    loop {
+        let offset = start + reader.offset() as usize;
+        let err = match reader.next() {
+            None => break,
+            Some(Ok(event)) => {
+                if !matches!(event.what, Some(tensorboard::event::What::FileVersion(_))) {
+                    let end = start + reader.offset() as usize;
+                    writer.write_raw_record(&data[offset..end])?;
+                    report.records += 1;
+                }
+                continue;
+            }
+            Some(Err(err)) => err,
+        };
+        if !opts.resync {
+            report.skipped.push((offset as u64, (data.len() - offset) as u64));
+            break;
+        }
+        match err {
+            // The length of the record is valid so the reader is at the next record.
+            Error::CrcMismatch { .. } | Error::RecordDecode { .. } => {
+                report.dropped_records += 1;
+                let len = start + reader.offset() as usize - offset;
+                report.skipped.push((offset as u64, len as u64))
+            }
+            _ => match find_record(data, offset + 1) {
+                None => {
+                    report.skipped.push((offset as u64, (data.len() - offset) as u64));
+                    break;
+                }
+                Some(next) => {
+                    report.skipped.push((offset as u64, (next - offset) as u64));
+                    start = next;
+                    reader = SummaryReader::new(&data[next..])
+                }
+            },
+        }
+    }
+    writer.flush()?;
+    Ok(report)
+}
+
+// The wall time of the first event, used for the file version of the repaired file.
+fn first_wall_time(data: &[u8]) -> Result<f64> {
+    match SummaryReader::new(data).next() {
+        Some(Ok(event)) => Ok(event.wall_time),
+        _ => crate::writer::wall_time_now(),
+    }
+}
+
+/// Write the valid events from the event file `path` to a new event file `output`, the
+/// output is synced to disk before returning.
+pub fn repair_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Q,
+    opts: &RepairOptions,
+) -> Result<RepairReport> {
+    let data = std::fs::read(path.as_ref())?;
+    let output = output.as_ref();
+    let file = std::fs::File::create(output)?;
+    // Syncing requires a writable handle on some platforms, so keep one around.
+    let sync_file = file.try_clone()?;
+    let wall_time = first_wall_time(&data)?;
+    let mut writer = EventWriter::from_writer_with_wall_time(
+        std::io::BufWriter::new(file),
+        Some(output.to_path_buf()),
+        wall_time,
+    )?;
+    let report = repair_data(&data, &mut writer, opts)?;
+    sync_file.sync_all()?;
+    Ok(report)
+}
+
+/// The default backup path for an event file, "tfevents" is replaced in the file name so
+/// that the backup is not considered as an event file by tensorboard.
+pub fn backup_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let name = path.file_name().map_or_else(String::new, |n| n.to_string_lossy().to_string());
+    path.with_file_name(format!("{}.bak", name.replace("tfevents", "tfbackup")))
+}
+
+/// Repair an event file in place, the file is only replaced if it needed some repair. The
+/// repaired file is written next to the original one and then atomically renamed over it,
+/// when `backup` is set the original file is kept at this path.
+pub fn repair_in_place<P: AsRef<Path>>(
+    path: P,
+    backup: Option<&Path>,
+    opts: &RepairOptions,
+) -> Result<RepairReport> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let uid = crate::writer::global_uid();
+    let tmp_path = dir.join(format!(".tboard-repair-{}-{uid}.tmp", std::process::id()));
+    let report = match repair_file(path, &tmp_path, opts) {
+        Ok(report) => report,
+        Err(err) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    };
+    if report.is_clean() {
+        std::fs::remove_file(&tmp_path)?;
+        return Ok(report);
+    }
+    if let Some(backup) = backup {
+        if std::fs::hard_link(path, backup).is_err() {
+            std::fs::copy(path, backup)?;
+        }
+    }
+    std::fs::rename(&tmp_path, path)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scalar_events;
+
+    fn record(payload: &[u8]) -> Vec<u8> {
+        let len = (payload.len() as u64).to_le_bytes();
+        let mut record = len.to_vec();
+        record.extend_from_slice(&masked_crc(&len).to_le_bytes());
+        record.extend_from_slice(payload);
+        record.extend_from_slice(&masked_crc(payload).to_le_bytes());
+        record
+    }
+
+    fn repair(data: &[u8], resync: bool) -> (RepairReport, Vec<i64>, Vec<u8>) {
+        let mut out = vec![];
+        let mut writer = EventWriter::from_writer(&mut out, None).unwrap();
+        let report = repair_data(data, &mut writer, &RepairOptions { resync }).unwrap();
+        drop(writer);
+        let steps = SummaryReader::new(out.as_slice()).skip(1).map(|e| e.unwrap().step).collect();
+        (report, steps, out)
+    }
+
+    #[test]
+    fn torn_tail() {
+        let data = scalar_events(&[("a", 1, 1.), ("a", 2, 2.)]);
+        let (report, steps, _) = repair(&data, false);
+        assert!(report.is_clean());
+        assert_eq!(steps, [1, 2]);
+        let (report, steps, _) = repair(&data[..data.len() - 3], false);
+        let record_len = data.len() - scalar_events(&[("a", 1, 1.)]).len();
+        let offset = (data.len() - record_len) as u64;
+        assert_eq!(report.skipped, [(offset, record_len as u64 - 3)]);
+        assert_eq!((report.records, report.dropped_records), (1, 0));
+        assert_eq!(steps, [1]);
+    }
+
+    #[test]
+    fn resync() {
+        let data = scalar_events(&[("a", 1, 1.), ("a", 2, 2.), ("a", 3, 3.), ("a", 4, 4.)]);
+        let record_len =
+            data.len() - scalar_events(&[("a", 1, 1.), ("a", 2, 2.), ("a", 3, 3.)]).len();
+        let start = data.len() - 4 * record_len;
+        let mut corrupted = data[..start + record_len].to_vec();
+        // A record with an invalid payload crc, followed by some garbage that includes a header
+        // with a valid crc but a length beyond the end of the file.
+        let mut bad_crc = data[start + record_len..start + 2 * record_len].to_vec();
+        bad_crc[20] ^= 1;
+        corrupted.extend_from_slice(&bad_crc);
+        corrupted.extend_from_slice(b"garbage");
+        corrupted.extend_from_slice(&record(&[0; 64])[..12]);
+        corrupted.extend_from_slice(&data[start + 2 * record_len..]);
+
+        let (report, steps, _) = repair(&corrupted, false);
+        assert_eq!((report.records, steps), (1, vec![1]));
+        let (report, steps, _) = repair(&corrupted, true);
+        assert_eq!(steps, [1, 3, 4]);
+        assert_eq!((report.records, report.dropped_records), (3, 1));
+        let offset = (start + record_len) as u64;
+        let garbage_len = 7 + 12;
+        let record_len = record_len as u64;
+        let expected = [(offset, record_len), (offset + record_len, garbage_len)];
+        assert_eq!(report.skipped, expected);
+        assert_eq!(report.skipped_bytes(), garbage_len + record_len);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        use prost::Message;
+
+        let event = tensorboard::Event {
+            step: 5,
+            what: Some(crate::writer::what::scalar("a", 1.)),
+            ..Default::default()
+        };
+        // An event with an extra field 100 unknown to the protos.
+        let mut payload = event.encode_to_vec();
+        payload.extend_from_slice(&[0xa0, 0x06, 0x01]);
+        let mut data = scalar_events(&[]);
+        data.extend_from_slice(&record(&payload));
+        let (report, steps, out) = repair(&data, true);
+        assert!(report.is_clean());
+        assert_eq!(steps, [5]);
+        assert!(out.ends_with(&record(&payload)));
+    }
+
+    #[test]
+    fn in_place() {
+        let dir = crate::test_utils::temp_dir("repair-in-place");
+        let path = dir.join("events.out.tfevents.1.test");
+        let data = scalar_events(&[("a", 1, 1.), ("a", 2, 2.)]);
+        std::fs::write(&path, &data).unwrap();
+        let backup = backup_path(&path);
+        assert_eq!(backup, dir.join("events.out.tfbackup.1.test.bak"));
+        let report = repair_in_place(&path, Some(&backup), &RepairOptions::default()).unwrap();
+        assert!(report.is_clean());
+        assert!(!backup.exists());
+
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let report = repair_in_place(&path, Some(&backup), &RepairOptions::default()).unwrap();
+        assert_eq!(report.records, 1);
+        assert_eq!(std::fs::read(&backup).unwrap(), &data[..data.len() - 1]);
+        let events = SummaryReader::new(std::fs::read(&path).unwrap().as_slice())
+            .map(|e| e.unwrap().wall_time)
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        // The repaired file keeps the wall time of the original file version.
+        assert_eq!(
+            events[0],
+            SummaryReader::new(data.as_slice()).next().unwrap().unwrap().wall_time
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // Concurrent repairs in the same directory use distinct temporary files.
+        let paths = (2..6).map(|i| dir.join(format!("events.out.tfevents.{i}.test")));
+        let threads = paths
+            .map(|path| {
+                std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+                std::thread::spawn(move || {
+                    repair_in_place(&path, None, &RepairOptions::default()).unwrap().records
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 1);
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 6);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
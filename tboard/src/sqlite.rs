@@ -0,0 +1,527 @@
+// Ingestion of log directories in a SQLite database, in the spirit of the tensorboard "db
+// mode". The database can then be queried with arbitrary SQL, the schema is:
+//   runs(id, name)
+//   tags(id, run_id, name, kind) where kind is scalar, tensor, histogram, image, or audio
+//   scalars(tag_id, file_id, step, wall_time, value)
+//   tensors(tag_id, file_id, step, wall_time, dtype, shape, data)
+//   blobs(tag_id, file_id, step, wall_time, content_type, data)
+//   files(id, path, run_id, first_crc, offset, records)
+// Tensor data is stored as little-endian f64 values and the shape as a JSON array, the
+// histograms are stored as tensors of shape [k, 3] with the left edge, right edge, and
+// count for each bucket. The `files` table tracks how much of each event file has been
+// ingested so that re-ingesting a log directory only reads the appended records. The crc
+// of the first record identifies the file content, when it changes the file has been
+// rewritten and its values are ingested again from the start.
+use crate::accumulator::{is_scalar_plugin, scalar_of_tensor, tensor_to_f64, ScalarEvent};
+use crate::reader::{parse_header, HEADER_LEN};
+use crate::{tensorboard, Error, RecordPos, Result, SummaryReader};
+use rusqlite::params;
+use std::path::{Path, PathBuf};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    UNIQUE(run_id, name, kind)
+);
+CREATE TABLE IF NOT EXISTS files (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    first_crc INTEGER,
+    offset INTEGER NOT NULL,
+    records INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS scalars (
+    tag_id INTEGER NOT NULL REFERENCES tags(id),
+    file_id INTEGER NOT NULL REFERENCES files(id),
+    step INTEGER NOT NULL,
+    wall_time REAL NOT NULL,
+    value REAL
+);
+CREATE INDEX IF NOT EXISTS scalars_tag_step ON scalars(tag_id, step);
+CREATE TABLE IF NOT EXISTS tensors (
+    tag_id INTEGER NOT NULL REFERENCES tags(id),
+    file_id INTEGER NOT NULL REFERENCES files(id),
+    step INTEGER NOT NULL,
+    wall_time REAL NOT NULL,
+    dtype TEXT NOT NULL,
+    shape TEXT NOT NULL,
+    data BLOB
+);
+CREATE INDEX IF NOT EXISTS tensors_tag_step ON tensors(tag_id, step);
+CREATE TABLE IF NOT EXISTS blobs (
+    tag_id INTEGER NOT NULL REFERENCES tags(id),
+    file_id INTEGER NOT NULL REFERENCES files(id),
+    step INTEGER NOT NULL,
+    wall_time REAL NOT NULL,
+    content_type TEXT NOT NULL,
+    data BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS blobs_tag_step ON blobs(tag_id, step);
+";
+
+fn w(err: rusqlite::Error) -> Error {
+    Error::wrap(err)
+}
+
+fn f64_bytes(values: &[f64]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+// Returns the payload crc of the first record, `None` if this record is not complete or
+// has an invalid header, the latter being reported when reading the records.
+fn first_record_crc(file: &mut std::fs::File) -> Result<Option<u32>> {
+    use std::io::{Read, Seek};
+
+    let mut header = [0u8; HEADER_LEN];
+    if file.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let event_len = match parse_header(&header, &RecordPos::default()) {
+        Ok(event_len) => event_len,
+        Err(_) => return Ok(None),
+    };
+    file.seek(std::io::SeekFrom::Current(event_len as i64))?;
+    let mut footer = [0u8; 4];
+    let crc = file.read_exact(&mut footer).ok().map(|()| u32::from_le_bytes(footer));
+    file.rewind()?;
+    Ok(crc)
+}
+
+/// Statistics about an ingestion, files that could not be read are reported in `errors`
+/// and do not prevent the other files from being ingested.
+#[derive(Debug, Default)]
+pub struct IngestStats {
+    pub files: usize,
+    pub records: usize,
+    pub errors: Vec<(PathBuf, Error)>,
+}
+
+pub struct Database {
+    conn: rusqlite::Connection,
+}
+
+impl Database {
+    /// Open or create a database.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let conn = rusqlite::Connection::open(path).map_err(w)?;
+        Self::from_connection(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = rusqlite::Connection::open_in_memory().map_err(w)?;
+        Self::from_connection(conn)
+    }
+
+    pub fn from_connection(conn: rusqlite::Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA).map_err(w)?;
+        Ok(Self { conn })
+    }
+
+    /// The underlying connection, to be used for custom queries.
+    pub fn connection(&self) -> &rusqlite::Connection {
+        &self.conn
+    }
+
+    fn run_id(tx: &rusqlite::Transaction, run: &str) -> Result<i64> {
+        tx.execute("INSERT OR IGNORE INTO runs(name) VALUES (?1)", params![run]).map_err(w)?;
+        tx.query_row("SELECT id FROM runs WHERE name = ?1", params![run], |r| r.get(0)).map_err(w)
+    }
+
+    fn tag_id(tx: &rusqlite::Transaction, run_id: i64, tag: &str, kind: &str) -> Result<i64> {
+        let mut stmt = tx
+            .prepare_cached("INSERT OR IGNORE INTO tags(run_id, name, kind) VALUES (?1, ?2, ?3)")
+            .map_err(w)?;
+        stmt.execute(params![run_id, tag, kind]).map_err(w)?;
+        let mut stmt = tx
+            .prepare_cached("SELECT id FROM tags WHERE run_id = ?1 AND name = ?2 AND kind = ?3")
+            .map_err(w)?;
+        stmt.query_row(params![run_id, tag, kind], |r| r.get(0)).map_err(w)
+    }
+
+    // Removes the values of a run with a step greater or equal to `step`, this is used
+    // when a restart is detected via a SessionLog START event.
+    fn purge(tx: &rusqlite::Transaction, run_id: i64, step: i64) -> Result<()> {
+        for table in ["scalars", "tensors", "blobs"] {
+            let sql = format!(
+                "DELETE FROM {table} WHERE step >= ?1 AND tag_id IN (SELECT id FROM tags WHERE run_id = ?2)"
+            );
+            tx.execute(&sql, params![step, run_id]).map_err(w)?;
+        }
+        Ok(())
+    }
+
+    fn insert_event(
+        tx: &rusqlite::Transaction,
+        run_id: i64,
+        file_id: i64,
+        event: tensorboard::Event,
+    ) -> Result<()> {
+        use tensorboard::event::What;
+        use tensorboard::session_log::SessionStatus;
+        use tensorboard::summary::value::Value;
+
+        let (step, wall_time) = (event.step, event.wall_time);
+        let summary = match event.what {
+            Some(What::Summary(summary)) => summary,
+            Some(What::SessionLog(session_log)) => {
+                if session_log.status() == SessionStatus::Start {
+                    Self::purge(tx, run_id, step)?
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        let mut insert_scalar = tx
+            .prepare_cached(
+                "INSERT INTO scalars(tag_id, file_id, step, wall_time, value) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .map_err(w)?;
+        let mut insert_tensor = tx
+            .prepare_cached(
+                "INSERT INTO tensors(tag_id, file_id, step, wall_time, dtype, shape, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )
+            .map_err(w)?;
+        let mut insert_blob = tx
+            .prepare_cached(
+                "INSERT INTO blobs(tag_id, file_id, step, wall_time, content_type, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .map_err(w)?;
+        for value in summary.value {
+            let tag = value.tag.as_str();
+            match value.value {
+                None | Some(Value::ObsoleteOldStyleHistogram(_)) => {}
+                Some(Value::SimpleValue(v)) => {
+                    let tag_id = Self::tag_id(tx, run_id, tag, "scalar")?;
+                    insert_scalar
+                        .execute(params![tag_id, file_id, step, wall_time, v as f64])
+                        .map_err(w)?;
+                }
+                // The scalars written as tensors by the scalars plugin, e.g. with TF2.
+                Some(Value::Tensor(t))
+                    if is_scalar_plugin(&value.metadata) && scalar_of_tensor(&t).is_some() =>
+                {
+                    let v = scalar_of_tensor(&t);
+                    let tag_id = Self::tag_id(tx, run_id, tag, "scalar")?;
+                    insert_scalar
+                        .execute(params![tag_id, file_id, step, wall_time, v])
+                        .map_err(w)?;
+                }
+                Some(Value::Tensor(t)) => {
+                    let tag_id = Self::tag_id(tx, run_id, tag, "tensor")?;
+                    let dims = t.tensor_shape.as_ref().map_or(&[][..], |v| v.dim.as_slice());
+                    let shape = dims.iter().map(|d| d.size.to_string()).collect::<Vec<_>>();
+                    let shape = format!("[{}]", shape.join(","));
+                    let data = tensor_to_f64(&t).map(|v| f64_bytes(&v));
+                    let dtype = t.dtype().as_str_name();
+                    insert_tensor
+                        .execute(params![tag_id, file_id, step, wall_time, dtype, shape, data])
+                        .map_err(w)?;
+                }
+                Some(Value::Histo(h)) => {
+                    let tag_id = Self::tag_id(tx, run_id, tag, "histogram")?;
+                    let mut data = vec![];
+                    let mut left = h.min;
+                    for (&right, &count) in h.bucket_limit.iter().zip(h.bucket.iter()) {
+                        let right = right.min(h.max);
+                        data.extend_from_slice(&[left, right, count]);
+                        left = right;
+                    }
+                    let shape = format!("[{},3]", data.len() / 3);
+                    let data = f64_bytes(&data);
+                    insert_tensor
+                        .execute(params![
+                            tag_id,
+                            file_id,
+                            step,
+                            wall_time,
+                            "DT_DOUBLE",
+                            shape,
+                            data
+                        ])
+                        .map_err(w)?;
+                }
+                Some(Value::Image(image)) => {
+                    let tag_id = Self::tag_id(tx, run_id, tag, "image")?;
+                    let data = image.encoded_image_string;
+                    let content_type = crate::image_content_type(&data);
+                    insert_blob
+                        .execute(params![tag_id, file_id, step, wall_time, content_type, data])
+                        .map_err(w)?;
+                }
+                Some(Value::Audio(audio)) => {
+                    let tag_id = Self::tag_id(tx, run_id, tag, "audio")?;
+                    let data = audio.encoded_audio_string;
+                    insert_blob
+                        .execute(params![
+                            tag_id,
+                            file_id,
+                            step,
+                            wall_time,
+                            audio.content_type,
+                            data
+                        ])
+                        .map_err(w)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Ingest the records of an event file that have not been ingested yet, returns the
+    /// number of new records. A truncated record at the end of the file is not an error
+    /// as the file may still be written, it is ingested on the next call. When the file
+    /// has been rewritten since the last ingestion, the values previously ingested from it
+    /// are removed and the whole file is ingested again.
+    pub fn ingest_file<P: AsRef<Path>>(&mut self, run: &str, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let key = path.to_string_lossy().to_string();
+        let tx = self.conn.transaction().map_err(w)?;
+        let run_id = Self::run_id(&tx, run)?;
+        let mut file = std::fs::File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let first_crc = first_record_crc(&mut file)?;
+        tx.execute(
+            "INSERT OR IGNORE INTO files(path, run_id, offset, records) VALUES (?1, ?2, 0, 0)",
+            params![key, run_id],
+        )
+        .map_err(w)?;
+        let (file_id, ingested_crc, offset, records) = tx
+            .query_row(
+                "SELECT id, first_crc, offset, records FROM files WHERE path = ?1",
+                params![key],
+                |r| {
+                    let crc: Option<i64> = r.get(1)?;
+                    let (offset, records) = (r.get::<_, i64>(2)? as u64, r.get::<_, i64>(3)?);
+                    Ok((r.get::<_, i64>(0)?, crc.map(|v| v as u32), offset, records as u64))
+                },
+            )
+            .map_err(w)?;
+        let (offset, records) = if offset > 0 && (ingested_crc != first_crc || file_len < offset) {
+            for table in ["scalars", "tensors", "blobs"] {
+                let sql = format!("DELETE FROM {table} WHERE file_id = ?1");
+                tx.execute(&sql, params![file_id]).map_err(w)?;
+            }
+            (0, 0)
+        } else {
+            (offset, records)
+        };
+        let mut reader = SummaryReader::new(std::io::BufReader::new(file)).with_path(path);
+        reader.seek(offset, records)?;
+        let mut new_records = 0;
+        let mut err = None;
+        // Only the records before the first error are marked as ingested.
+        let (offset, records) = loop {
+            match reader.next() {
+                None => break (reader.offset(), reader.index()),
+                Some(Ok(event)) => {
+                    Self::insert_event(&tx, run_id, file_id, event)?;
+                    new_records += 1;
+                }
+                Some(Err(e)) => {
+                    let pos = match e.record_pos() {
+                        None => return Err(e),
+                        Some(pos) => (pos.offset, pos.index),
+                    };
+                    if !matches!(e, Error::TruncatedRecord { .. }) {
+                        err = Some(e)
+                    }
+                    break pos;
+                }
+            }
+        };
+        tx.execute(
+            "UPDATE files SET run_id = ?2, first_crc = ?3, offset = ?4, records = ?5 WHERE id = ?1",
+            params![file_id, run_id, first_crc, offset as i64, records as i64],
+        )
+        .map_err(w)?;
+        tx.commit().map_err(w)?;
+        match err {
+            None => Ok(new_records),
+            Some(err) => Err(err),
+        }
+    }
+
+    /// Ingest all the runs of a log directory.
+    pub fn ingest_logdir<P: AsRef<Path>>(&mut self, logdir: P) -> Result<IngestStats> {
+        let mut stats = IngestStats::default();
+        for run in crate::logdir::runs(logdir)? {
+            for path in run.files.iter() {
+                stats.files += 1;
+                match self.ingest_file(&run.name, path) {
+                    Ok(records) => stats.records += records,
+                    Err(err) => stats.errors.push((path.clone(), err)),
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    pub fn runs(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM runs ORDER BY name").map_err(w)?;
+        let runs = stmt.query_map([], |r| r.get(0)).map_err(w)?;
+        runs.collect::<rusqlite::Result<_>>().map_err(w)
+    }
+
+    pub fn scalars(&self, run: &str, tag: &str) -> Result<Vec<ScalarEvent>> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT s.step, s.wall_time, s.value FROM scalars s
+                 JOIN tags t ON s.tag_id = t.id JOIN runs r ON t.run_id = r.id
+                 WHERE r.name = ?1 AND t.name = ?2 ORDER BY s.step, s.wall_time",
+            )
+            .map_err(w)?;
+        let scalars = stmt
+            .query_map(params![run, tag], |r| {
+                let value: Option<f64> = r.get(2)?;
+                Ok(ScalarEvent {
+                    step: r.get(0)?,
+                    wall_time: r.get(1)?,
+                    value: value.unwrap_or(f64::NAN),
+                })
+            })
+            .map_err(w)?;
+        scalars.collect::<rusqlite::Result<_>>().map_err(w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{scalar_events, temp_dir};
+
+    fn scalar_plugin_tensor(step: i64, tag: &str, value: f32) -> tensorboard::Event {
+        use tensorboard::summary::value::Value;
+        // A rank 0 tensor, as written by `tf.summary.scalar`.
+        let mut tensor = crate::IntoTensor::into_tensor_proto(vec![value]).unwrap();
+        tensor.tensor_shape = Some(Default::default());
+        let metadata = tensorboard::SummaryMetadata {
+            plugin_data: Some(tensorboard::summary_metadata::PluginData {
+                plugin_name: "scalars".to_string(),
+                content: vec![],
+            }),
+            ..Default::default()
+        };
+        let value = tensorboard::summary::Value {
+            tag: tag.to_string(),
+            metadata: Some(metadata),
+            value: Some(Value::Tensor(tensor)),
+            ..Default::default()
+        };
+        let summary = tensorboard::Summary { value: vec![value] };
+        let what = Some(tensorboard::event::What::Summary(summary));
+        tensorboard::Event { wall_time: 1., step, source_metadata: None, what }
+    }
+
+    fn steps(db: &Database, run: &str, tag: &str) -> Vec<(i64, f64)> {
+        db.scalars(run, tag).unwrap().iter().map(|e| (e.step, e.value)).collect()
+    }
+
+    #[test]
+    fn ingest_incrementally() {
+        let dir = temp_dir("sqlite-ingest");
+        let path = dir.join("events.out.tfevents.1.test");
+        let data = scalar_events(&[("loss", 0, 1.), ("loss", 1, 0.5), ("loss", 2, 0.25)]);
+        // The last record is partially written.
+        std::fs::write(&path, &data[..data.len() - 5]).unwrap();
+        let mut db = Database::open_in_memory().unwrap();
+        let stats = db.ingest_logdir(&dir).unwrap();
+        assert!(stats.errors.is_empty(), "{:?}", stats.errors);
+        // The file version event and the first two scalars.
+        assert_eq!((stats.files, stats.records), (1, 3));
+        assert_eq!(steps(&db, ".", "loss"), [(0, 1.), (1, 0.5)]);
+
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(db.ingest_file(".", &path).unwrap(), 1);
+        assert_eq!(db.ingest_file(".", &path).unwrap(), 0);
+        assert_eq!(steps(&db, ".", "loss"), [(0, 1.), (1, 0.5), (2, 0.25)]);
+        assert_eq!(db.runs().unwrap(), ["."]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ingest_rewritten_file() {
+        let events = |wall_time: f64, steps: std::ops::Range<i64>| {
+            let mut data = vec![];
+            let mut writer =
+                crate::EventWriter::from_writer_with_wall_time(&mut data, None, wall_time).unwrap();
+            for step in steps {
+                writer.write_scalar(step, "loss", step as f32).unwrap();
+                writer.write_tensor(step, "t", vec![step as f32]).unwrap();
+            }
+            drop(writer);
+            data
+        };
+        let tensor_count = |db: &Database| -> i64 {
+            let sql = "SELECT COUNT(*) FROM tensors";
+            db.connection().query_row(sql, [], |r| r.get(0)).unwrap()
+        };
+        let dir = temp_dir("sqlite-rewrite");
+        let path = dir.join("events.out.tfevents.1.test");
+        let mut db = Database::open_in_memory().unwrap();
+        std::fs::write(&path, events(1., 0..3)).unwrap();
+        assert_eq!(db.ingest_file(".", &path).unwrap(), 7);
+        // The file is rewritten by another run and grows past the ingested offset.
+        std::fs::write(&path, events(2., 10..15)).unwrap();
+        assert_eq!(db.ingest_file(".", &path).unwrap(), 11);
+        let expected: Vec<_> = (10..15).map(|s| (s, s as f64)).collect();
+        assert_eq!(steps(&db, ".", "loss"), expected);
+        assert_eq!(tensor_count(&db), 5);
+        // The file is rewritten with less data.
+        std::fs::write(&path, events(3., 20..21)).unwrap();
+        assert_eq!(db.ingest_file(".", &path).unwrap(), 3);
+        assert_eq!(steps(&db, ".", "loss"), [(20, 20.)]);
+        assert_eq!(tensor_count(&db), 1);
+        // Appending to the file only ingests the new records.
+        std::fs::write(&path, events(3., 20..22)).unwrap();
+        assert_eq!(db.ingest_file(".", &path).unwrap(), 2);
+        assert_eq!(steps(&db, ".", "loss"), [(20, 20.), (21, 21.)]);
+        // Another file of the same run is not affected.
+        let other = dir.join("events.out.tfevents.2.test");
+        std::fs::write(&other, events(4., 30..31)).unwrap();
+        assert_eq!(db.ingest_file(".", &other).unwrap(), 3);
+        std::fs::write(&path, events(5., 40..41)).unwrap();
+        db.ingest_file(".", &path).unwrap();
+        assert_eq!(steps(&db, ".", "loss"), [(30, 30.), (40, 40.)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn scalar_plugin_tensors() {
+        let dir = temp_dir("sqlite-tensors");
+        let path = dir.join("events.out.tfevents.1.test");
+        let mut data = vec![];
+        let mut writer = crate::EventWriter::from_writer(&mut data, None).unwrap();
+        writer.write_event(scalar_plugin_tensor(3, "loss", 0.5)).unwrap();
+        writer.write_tensor(4, "weights", vec![1f32, 2.]).unwrap();
+        drop(writer);
+        std::fs::write(&path, &data).unwrap();
+        let mut db = Database::open_in_memory().unwrap();
+        db.ingest_file("run", &path).unwrap();
+        assert_eq!(steps(&db, "run", "loss"), [(3, 0.5)]);
+        let conn = db.connection();
+        let kinds: Vec<(String, String)> = conn
+            .prepare("SELECT name, kind FROM tags ORDER BY name")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(kinds, [("loss".into(), "scalar".into()), ("weights".into(), "tensor".into())]);
+        let (shape, data): (String, Vec<u8>) = conn
+            .query_row("SELECT shape, data FROM tensors", [], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap();
+        // The shape of the tensors written from a `Vec` is unknown.
+        assert_eq!(shape, "[]");
+        assert_eq!(data, f64_bytes(&[1., 2.]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
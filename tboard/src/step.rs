@@ -0,0 +1,63 @@
+// The step attached to the values recorded through instrumentation, e.g. the tracing layer,
+// where the step is not passed explicitly along with each value.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub enum StepSource {
+    /// A step shared with the training loop which updates it, e.g. at each iteration.
+    Shared(Arc<AtomicI64>),
+    /// The step returned by a function.
+    Fn(Arc<dyn Fn() -> i64 + Send + Sync>),
+    /// A counter per tag starting from 0, incremented each time a value is recorded for
+    /// this tag.
+    PerTag(Arc<Mutex<HashMap<String, i64>>>),
+}
+
+impl StepSource {
+    pub fn from_fn<F: Fn() -> i64 + Send + Sync + 'static>(f: F) -> Self {
+        Self::Fn(Arc::new(f))
+    }
+
+    pub fn per_tag() -> Self {
+        Self::PerTag(Arc::new(Mutex::new(HashMap::new())))
+    }
+
+    /// The step to use for a value recorded for `tag`.
+    pub fn step(&self, tag: &str) -> i64 {
+        match self {
+            Self::Shared(step) => step.load(Ordering::Relaxed),
+            Self::Fn(f) => f(),
+            Self::PerTag(counters) => {
+                let mut counters = counters.lock().unwrap_or_else(|e| e.into_inner());
+                match counters.get_mut(tag) {
+                    Some(step) => {
+                        *step += 1;
+                        *step
+                    }
+                    None => {
+                        counters.insert(tag.to_string(), 0);
+                        0
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for StepSource {
+    fn default() -> Self {
+        Self::per_tag()
+    }
+}
+
+impl std::fmt::Debug for StepSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shared(step) => f.debug_tuple("Shared").field(step).finish(),
+            Self::Fn(_) => f.write_str("Fn"),
+            Self::PerTag(counters) => f.debug_tuple("PerTag").field(counters).finish(),
+        }
+    }
+}
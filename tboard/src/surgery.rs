@@ -0,0 +1,420 @@
+// Rewriting of event files: filtering the tags, cropping and offsetting the steps, renaming
+// the tags, merging multiple files, and splitting a file in one run per tag prefix. The
+// rewritten events keep their original wall time, and the output files start with a single
+// file version event.
+//
+// The step cropping and offset apply to the summaries, session logs, and run metadata
+// events, the other events (graphs, log messages, ...) are kept as is. The events that are
+// not modified are copied byte for byte so that the fields unknown to the protos are kept.
+use crate::writer::{event_filename, EventWriter};
+use crate::{tensorboard, Error, Result, SummaryReader};
+use std::path::{Path, PathBuf};
+use tensorboard::event::What;
+
+#[derive(Debug, Clone, Default)]
+pub struct RewriteOptions {
+    /// Only keep the tags matching this regex.
+    pub include: Option<regex::Regex>,
+    /// Remove the tags matching this regex, this applies after `include`.
+    pub exclude: Option<regex::Regex>,
+    /// Only keep the events with a step in this range, the bounds being inclusive.
+    pub min_step: Option<i64>,
+    pub max_step: Option<i64>,
+    /// Added to the step of the events that are kept.
+    pub step_offset: i64,
+    /// Rename the tags matching a regex using the replacement string, e.g. `$1` refers to
+    /// the first capture group. The renames apply in order after the filtering.
+    pub renames: Vec<(regex::Regex, String)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RewriteStats {
+    pub events_read: usize,
+    pub events_written: usize,
+}
+
+impl RewriteOptions {
+    fn keep_tag(&self, tag: &str) -> bool {
+        self.include.as_ref().is_none_or(|re| re.is_match(tag))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(tag))
+    }
+
+    fn keep_step(&self, step: i64) -> bool {
+        self.min_step.is_none_or(|min| step >= min) && self.max_step.is_none_or(|max| step <= max)
+    }
+
+    fn rename(&self, tag: &str) -> String {
+        let mut tag = tag.to_string();
+        for (re, replacement) in self.renames.iter() {
+            tag = re.replace_all(&tag, replacement.as_str()).into_owned()
+        }
+        tag
+    }
+
+    /// Apply the filters, renames, and step offset to an event, returns `None` when the
+    /// event should be dropped.
+    pub fn rewrite_event(&self, event: tensorboard::Event) -> Option<tensorboard::Event> {
+        self.rewrite(event).map(|(event, _modified)| event)
+    }
+
+    // Same as `rewrite_event`, also returns whether the event has been modified.
+    fn rewrite(&self, mut event: tensorboard::Event) -> Option<(tensorboard::Event, bool)> {
+        let mut modified = false;
+        let mut rename = |tag: &mut String| {
+            let renamed = self.rename(tag);
+            if *tag != renamed {
+                *tag = renamed;
+                modified = true
+            }
+        };
+        let steps_apply = match event.what.as_mut() {
+            Some(What::Summary(summary)) => {
+                let len = summary.value.len();
+                summary.value.retain(|v| self.keep_tag(&v.tag));
+                if summary.value.is_empty() {
+                    return None;
+                }
+                for value in summary.value.iter_mut() {
+                    rename(&mut value.tag)
+                }
+                modified |= summary.value.len() != len;
+                true
+            }
+            Some(What::TaggedRunMetadata(metadata)) => {
+                if !self.keep_tag(&metadata.tag) {
+                    return None;
+                }
+                rename(&mut metadata.tag);
+                true
+            }
+            Some(What::SessionLog(_)) => true,
+            _ => false,
+        };
+        if steps_apply {
+            if !self.keep_step(event.step) {
+                return None;
+            }
+            event.step += self.step_offset;
+            modified |= self.step_offset != 0
+        }
+        Some((event, modified))
+    }
+}
+
+// An event writer that is only created when the first event is written so that the file
+// version event can use the wall time of the input.
+struct LazyWriter {
+    logdir: PathBuf,
+    writer: Option<EventWriter<std::io::BufWriter<std::fs::File>>>,
+}
+
+impl LazyWriter {
+    fn new<P: AsRef<Path>>(logdir: P) -> Self {
+        Self { logdir: logdir.as_ref().to_path_buf(), writer: None }
+    }
+
+    fn writer(
+        &mut self,
+        wall_time: f64,
+    ) -> Result<&mut EventWriter<std::io::BufWriter<std::fs::File>>> {
+        if self.writer.is_none() {
+            let filename = event_filename(&self.logdir)?;
+            let file = std::io::BufWriter::new(std::fs::File::create(&filename)?);
+            let writer = EventWriter::from_writer_with_wall_time(file, Some(filename), wall_time)?;
+            self.writer = Some(writer)
+        }
+        Ok(self.writer.as_mut().unwrap())
+    }
+
+    // The file version events from the input are dropped as the output file has its own.
+    // `record` is the original record for an event that has not been modified, it is
+    // written as is.
+    fn write_event(&mut self, event: tensorboard::Event, record: Option<&[u8]>) -> Result<bool> {
+        if let Some(What::FileVersion(_)) = event.what {
+            return Ok(false);
+        }
+        let writer = self.writer(event.wall_time)?;
+        match record {
+            Some(record) => writer.write_raw_record(record)?,
+            None => writer.write_event(event)?,
+        }
+        Ok(true)
+    }
+
+    fn finish(self) -> Result<Option<PathBuf>> {
+        match self.writer {
+            None => Ok(None),
+            Some(mut writer) => {
+                writer.flush()?;
+                Ok(writer.filename().cloned())
+            }
+        }
+    }
+}
+
+type EventRecord = (tensorboard::Event, Vec<u8>);
+
+// Iterates over the events of a file together with their records. A truncated record at the
+// end of a file is considered as the end of the data, this is usually the case for the
+// files of jobs that have been killed.
+fn open_events(path: &Path) -> Result<impl Iterator<Item = Result<EventRecord>>> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut reader = SummaryReader::new(file).with_path(path);
+    Ok(std::iter::from_fn(move || match reader.next()? {
+        Err(Error::TruncatedRecord { .. }) => None,
+        Err(err) => Some(Err(err)),
+        Ok(event) => Some(Ok((event, reader.last_record()))),
+    }))
+}
+
+/// Rewrite the events from some event files in a new event file in `logdir`. When there
+/// are multiple inputs, the events are merged by wall time assuming that the events in
+/// each file are sorted by wall time, which is the case for the files generated by the
+/// writers. Returns the path of the new file, `None` if no event was written.
+pub fn rewrite<P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    logdir: Q,
+    opts: &RewriteOptions,
+) -> Result<(Option<PathBuf>, RewriteStats)> {
+    let mut readers = inputs.iter().map(|p| open_events(p.as_ref())).collect::<Result<Vec<_>>>()?;
+    // The next event for each input.
+    let mut heads = vec![];
+    for reader in readers.iter_mut() {
+        heads.push(reader.next().transpose()?)
+    }
+    let mut writer = LazyWriter::new(logdir);
+    let mut stats = RewriteStats::default();
+    loop {
+        let next = heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.as_ref().map(|(e, _)| (i, e.wall_time)))
+            .min_by(|(_, t1), (_, t2)| t1.total_cmp(t2));
+        let index = match next {
+            None => break,
+            Some((index, _)) => index,
+        };
+        let event = std::mem::replace(&mut heads[index], readers[index].next().transpose()?);
+        stats.events_read += 1;
+        let (event, record) = match event {
+            None => continue,
+            Some(event) => event,
+        };
+        if let Some((event, modified)) = opts.rewrite(event) {
+            let record = if modified { None } else { Some(record.as_slice()) };
+            if writer.write_event(event, record)? {
+                stats.events_written += 1
+            }
+        }
+    }
+    Ok((writer.finish()?, stats))
+}
+
+/// Split an event file in one run per tag prefix, the part of the tag before the first "/".
+/// The prefix is removed from the tags, and the tags without prefix go in `logdir` itself.
+/// The filters and renames apply before the split, and the events that do not have tags,
+/// e.g. session logs, are written to all the runs. Returns the run names and the paths of
+/// the new files.
+pub fn split<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    logdir: Q,
+    opts: &RewriteOptions,
+) -> Result<Vec<(String, PathBuf)>> {
+    let logdir = logdir.as_ref();
+    let mut writers: Vec<(String, LazyWriter)> = vec![];
+    // The untagged events seen so far, they are replayed for the runs created later on.
+    let mut untagged = vec![];
+    let mut first_wall_time = None;
+    for event in open_events(input.as_ref())? {
+        let (event, record) = event?;
+        first_wall_time.get_or_insert(event.wall_time);
+        let (mut event, modified) = match opts.rewrite(event) {
+            None => continue,
+            Some(event) => event,
+        };
+        let summary = match event.what.as_mut() {
+            Some(What::Summary(summary)) => std::mem::take(&mut summary.value),
+            Some(What::FileVersion(_)) => continue,
+            _ => {
+                let record = if modified { None } else { Some(record) };
+                for (_, writer) in writers.iter_mut() {
+                    writer.write_event(event.clone(), record.as_deref())?;
+                }
+                untagged.push((event, record));
+                continue;
+            }
+        };
+        let mut per_run: Vec<(String, Vec<tensorboard::summary::Value>)> = vec![];
+        for mut value in summary {
+            let run = match value.tag.split_once('/') {
+                Some((prefix, tag)) if !matches!(prefix, "" | "." | "..") => {
+                    let prefix = prefix.to_string();
+                    value.tag = tag.to_string();
+                    prefix
+                }
+                _ => ".".to_string(),
+            };
+            match per_run.iter_mut().find(|(r, _)| *r == run) {
+                None => per_run.push((run, vec![value])),
+                Some((_, values)) => values.push(value),
+            }
+        }
+        // The tags without prefix are kept as is, so the record can be copied.
+        let unmodified = !modified && per_run.len() == 1 && per_run[0].0 == ".";
+        for (run, value) in per_run {
+            let index = match writers.iter().position(|(r, _)| *r == run) {
+                Some(index) => index,
+                None => {
+                    let dir = if run == "." { logdir.to_path_buf() } else { logdir.join(&run) };
+                    let mut writer = LazyWriter::new(dir);
+                    writer.writer(first_wall_time.unwrap_or(event.wall_time))?;
+                    for (event, record) in untagged.iter() {
+                        writer.write_event(event.clone(), record.as_deref())?;
+                    }
+                    writers.push((run, writer));
+                    writers.len() - 1
+                }
+            };
+            let what = Some(What::Summary(tensorboard::Summary { value }));
+            let event = tensorboard::Event { what, ..event.clone() };
+            let record = if unmodified { Some(record.as_slice()) } else { None };
+            writers[index].1.write_event(event, record)?;
+        }
+    }
+    let mut files = vec![];
+    for (run, writer) in writers {
+        if let Some(filename) = writer.finish()? {
+            files.push((run, filename))
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::what;
+
+    fn write_file(path: &Path, events: &[(f64, i64, &str)]) {
+        let file = std::fs::File::create(path).unwrap();
+        let mut writer = EventWriter::from_writer_with_wall_time(file, None, 0.).unwrap();
+        for &(wall_time, step, tag) in events.iter() {
+            let what = Some(what::scalar(tag, step as f32));
+            writer
+                .write_event(tensorboard::Event { wall_time, step, what, ..Default::default() })
+                .unwrap()
+        }
+    }
+
+    // The wall time, step, and tags of the events after the file version.
+    fn read_file(path: &Path) -> Vec<(f64, i64, Vec<String>)> {
+        let file = std::fs::File::open(path).unwrap();
+        let mut events = SummaryReader::new(file).map(|e| e.unwrap());
+        assert!(matches!(events.next().unwrap().what, Some(What::FileVersion(_))));
+        events
+            .map(|e| {
+                let tags = match e.what {
+                    Some(What::Summary(s)) => s.value.into_iter().map(|v| v.tag).collect(),
+                    _ => vec![],
+                };
+                (e.wall_time, e.step, tags)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rewrite_merge() {
+        let dir = crate::test_utils::temp_dir("surgery-rewrite");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        write_file(&a, &[(1., 0, "train/loss"), (3., 1, "train/loss"), (5., 2, "train/acc")]);
+        write_file(&b, &[(2., 0, "eval/loss"), (4., 1, "eval/loss"), (6., 2, "other")]);
+        let opts = RewriteOptions {
+            include: Some(regex::Regex::new("loss").unwrap()),
+            exclude: Some(regex::Regex::new("^eval/").unwrap()),
+            max_step: Some(1),
+            step_offset: 10,
+            renames: vec![(regex::Regex::new("^train/(.*)").unwrap(), "t/$1".to_string())],
+            ..Default::default()
+        };
+        let (path, stats) = rewrite(&[&a, &b], dir.join("out"), &opts).unwrap();
+        assert_eq!(stats, RewriteStats { events_read: 8, events_written: 2 });
+        let events = read_file(&path.unwrap());
+        let tags = vec!["t/loss".to_string()];
+        assert_eq!(events, [(1., 10, tags.clone()), (3., 11, tags)]);
+
+        let opts = RewriteOptions { include: Some(regex::Regex::new("none").unwrap()), ..opts };
+        let (path, _) = rewrite(&[&a], dir.join("empty"), &opts).unwrap();
+        assert!(path.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // A record for a scalar event with an extra field that is not part of the protos.
+    fn record_with_unknown_field(wall_time: f64, step: i64, tag: &str) -> Vec<u8> {
+        use prost::Message;
+        let what = Some(what::scalar(tag, 1.));
+        let event = tensorboard::Event { wall_time, step, what, ..Default::default() };
+        let mut payload = event.encode_to_vec();
+        payload.extend_from_slice(&[0xa0, 0x06, 0x01]);
+        let mut record = (payload.len() as u64).to_le_bytes().to_vec();
+        record.extend_from_slice(&crate::masked_crc(&record).to_le_bytes());
+        record.extend_from_slice(&payload);
+        record.extend_from_slice(&crate::masked_crc(&payload).to_le_bytes());
+        record
+    }
+
+    fn records(path: &Path) -> Vec<Vec<u8>> {
+        let data = std::fs::read(path).unwrap();
+        let records = crate::SliceReader::new(&data).map(|r| r.unwrap().payload().to_vec());
+        records.skip(1).collect()
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let dir = crate::test_utils::temp_dir("surgery-unknown");
+        let input = dir.join("input");
+        write_file(&input, &[]);
+        let records_in = [
+            record_with_unknown_field(1., 1, "loss"),
+            record_with_unknown_field(2., 2, "train/loss"),
+        ];
+        let mut file = std::fs::OpenOptions::new().append(true).open(&input).unwrap();
+        std::io::Write::write_all(&mut file, &records_in.concat()).unwrap();
+        drop(file);
+        let payloads: Vec<_> = records_in.iter().map(|r| r[12..r.len() - 4].to_vec()).collect();
+
+        // The events that are not modified are copied as is.
+        let opts = RewriteOptions { min_step: Some(2), ..Default::default() };
+        let (path, _) = rewrite(&[&input], dir.join("copy"), &opts).unwrap();
+        assert_eq!(records(&path.unwrap()), payloads[1..]);
+        // The modified events are encoded again, which drops the unknown fields.
+        let opts = RewriteOptions { step_offset: 1, ..Default::default() };
+        let (path, _) = rewrite(&[&input], dir.join("offset"), &opts).unwrap();
+        let path = path.unwrap();
+        let rewritten = records(&path);
+        assert_eq!(rewritten.len(), 2);
+        assert_eq!(rewritten[0].len(), payloads[0].len() - 3);
+        assert_eq!(read_file(&path)[0], (1., 2, vec!["loss".to_string()]));
+
+        let mut files = split(&input, dir.join("split"), &RewriteOptions::default()).unwrap();
+        files.sort();
+        assert_eq!(records(&files[0].1), payloads[..1]);
+        assert_eq!(read_file(&files[1].1), [(2., 2, vec!["loss".to_string()])]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_runs() {
+        let dir = crate::test_utils::temp_dir("surgery-split");
+        let input = dir.join("input");
+        write_file(&input, &[(1., 0, "loss"), (2., 0, "train/loss"), (3., 1, "eval/acc/top1")]);
+        let mut files = split(&input, dir.join("out"), &RewriteOptions::default()).unwrap();
+        files.sort();
+        let runs: Vec<_> = files.iter().map(|(run, _)| run.as_str()).collect();
+        assert_eq!(runs, [".", "eval", "train"]);
+        assert_eq!(files[1].1.parent().unwrap(), dir.join("out/eval"));
+        assert_eq!(read_file(&files[0].1), [(1., 0, vec!["loss".to_string()])]);
+        assert_eq!(read_file(&files[1].1), [(3., 1, vec!["acc/top1".to_string()])]);
+        assert_eq!(read_file(&files[2].1), [(2., 0, vec!["loss".to_string()])]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+// Helpers shared by the unit tests.
+use std::path::PathBuf;
+
+/// An empty directory for a test, removed and recreated if it already exists.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tboard-test-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// An in-memory event file with a scalar event for each (tag, step, value).
+pub(crate) fn scalar_events(scalars: &[(&str, i64, f32)]) -> Vec<u8> {
+    let mut data = vec![];
+    let mut writer = crate::EventWriter::from_writer(&mut data, None).unwrap();
+    for &(tag, step, value) in scalars.iter() {
+        writer.write_scalar(step, tag, value).unwrap()
+    }
+    drop(writer);
+    data
+}
+
+/// An in-memory writer that can be read while an `EventWriter` owns a clone of it, e.g.
+/// when the writer is moved into a tracing layer or a metrics recorder.
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+impl SharedBuffer {
+    /// The (tag, step, value) of the scalars and histograms written so far, the value of a
+    /// histogram being its number of samples.
+    pub(crate) fn values(&self) -> Vec<(String, i64, f32)> {
+        use crate::tensorboard::{event::What, summary::value::Value};
+        let data = self.0.lock().unwrap().clone();
+        let mut values = vec![];
+        for event in crate::SummaryReader::new(data.as_slice()) {
+            let event = event.unwrap();
+            if let Some(What::Summary(summary)) = event.what {
+                for value in summary.value {
+                    match value.value {
+                        Some(Value::SimpleValue(v)) => values.push((value.tag, event.step, v)),
+                        Some(Value::Histo(h)) => values.push((value.tag, event.step, h.num as f32)),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        values
+    }
+}
@@ -0,0 +1,208 @@
+// A tracing layer writing the numeric fields of some events, and the duration of the
+// spans, as scalars. The events are selected by target, e.g.
+//   tracing::info!(target: "tb", step = 12, train.loss = 0.25, train.acc = 0.9);
+// writes `train/loss` and `train/acc` at step 12, the dots in the field names being
+// replaced with slashes so that tensorboard groups the tags. When there is no `step` field,
+// the step comes from the `StepSource` of the layer.
+//
+// The span durations are measured from the span creation until it is closed, the spans to
+// time can be selected using the tracing filters on the layer.
+use crate::step::StepSource;
+use crate::EventWriter;
+use std::sync::{Arc, Mutex};
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id};
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+pub struct TracingLayer<W: std::io::Write> {
+    writer: Arc<Mutex<EventWriter<W>>>,
+    target: String,
+    steps: StepSource,
+    spans: bool,
+    span_prefix: String,
+}
+
+struct SpanStart(std::time::Instant);
+
+#[derive(Default)]
+struct Fields {
+    step: Option<i64>,
+    values: Vec<(&'static str, f64)>,
+}
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.values.push((field.name(), value))
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == "step" {
+            self.step = Some(value)
+        } else {
+            self.values.push((field.name(), value as f64))
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "step" {
+            self.step = i64::try_from(value).ok()
+        } else {
+            self.values.push((field.name(), value as f64))
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+impl<W: std::io::Write> TracingLayer<W> {
+    /// Create a layer recording the events with the `tb` target and all the spans, the steps
+    /// are counted per tag.
+    pub fn new(writer: EventWriter<W>) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+            target: "tb".to_string(),
+            steps: StepSource::default(),
+            spans: true,
+            span_prefix: "timing/".to_string(),
+        }
+    }
+
+    /// Only record the events with this target or one of its sub-modules.
+    pub fn with_target(mut self, target: &str) -> Self {
+        self.target = target.to_string();
+        self
+    }
+
+    pub fn with_step_source(mut self, steps: StepSource) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Whether to record the span durations, in seconds.
+    pub fn with_spans(mut self, spans: bool) -> Self {
+        self.spans = spans;
+        self
+    }
+
+    /// The prefix of the span duration tags, followed by the span name.
+    pub fn with_span_prefix(mut self, prefix: &str) -> Self {
+        self.span_prefix = prefix.to_string();
+        self
+    }
+
+    /// The writer used by the layer, e.g. to flush it or to write other summaries. The
+    /// errors when writing from the layer are ignored as they cannot be reported.
+    pub fn writer(&self) -> Arc<Mutex<EventWriter<W>>> {
+        self.writer.clone()
+    }
+
+    fn is_selected(&self, target: &str) -> bool {
+        match target.strip_prefix(self.target.as_str()) {
+            None => false,
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        }
+    }
+
+    fn write_scalar(&self, step: Option<i64>, tag: &str, value: f64) {
+        let step = step.unwrap_or_else(|| self.steps.step(tag));
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writer.write_scalar(step, tag, value as f32);
+    }
+}
+
+impl<S, W> tracing_subscriber::Layer<S> for TracingLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: std::io::Write + Send + 'static,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let (true, Some(span)) = (self.spans, ctx.span(id)) {
+            span.extensions_mut().insert(SpanStart(std::time::Instant::now()))
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            None => return,
+            Some(span) => span,
+        };
+        let start = span.extensions().get::<SpanStart>().map(|s| s.0);
+        if let Some(start) = start {
+            let tag = format!("{}{}", self.span_prefix, span.name());
+            self.write_scalar(None, &tag, start.elapsed().as_secs_f64())
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if !self.is_selected(event.metadata().target()) {
+            return;
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        for (name, value) in fields.values {
+            self.write_scalar(fields.step, &name.replace('.', "/"), value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::SharedBuffer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn record(layer: TracingLayer<SharedBuffer>, f: impl FnOnce()) {
+        let subscriber = tracing_subscriber::Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, f)
+    }
+
+    fn tags(scalars: &[(String, i64, f32)]) -> Vec<(&str, i64)> {
+        scalars.iter().map(|(tag, step, _)| (tag.as_str(), *step)).collect()
+    }
+
+    #[test]
+    fn events() {
+        let buffer = SharedBuffer::default();
+        let layer = TracingLayer::new(EventWriter::from_writer(buffer.clone(), None).unwrap());
+        record(layer, || {
+            tracing::info!(target: "tb", step = 12, train.loss = 0.25, train.acc = 0.5);
+            tracing::info!(target: "tb", lr = 0.1);
+            tracing::info!(target: "tb::sub", lr = 0.2, n = 3u64);
+            tracing::info!(target: "tbx", lr = 0.3);
+            tracing::info!(target: "tb", "no values");
+            tracing::info_span!("forward").in_scope(|| {});
+        });
+        let scalars = buffer.values();
+        let expected = [
+            ("train/loss", 12),
+            ("train/acc", 12),
+            ("lr", 0),
+            ("lr", 1),
+            ("n", 0),
+            ("timing/forward", 0),
+        ];
+        assert_eq!(tags(&scalars), expected);
+        let values: Vec<f32> = scalars.iter().take(5).map(|s| s.2).collect();
+        assert_eq!(values, [0.25, 0.5, 0.1, 0.2, 3.]);
+        assert!(scalars[5].2 >= 0.);
+    }
+
+    #[test]
+    fn shared_step() {
+        let buffer = SharedBuffer::default();
+        let step = Arc::new(std::sync::atomic::AtomicI64::new(5));
+        let layer = TracingLayer::new(EventWriter::from_writer(buffer.clone(), None).unwrap())
+            .with_step_source(StepSource::Shared(step.clone()))
+            .with_spans(false);
+        record(layer, || {
+            tracing::info!(target: "tb", loss = 1.);
+            step.store(6, std::sync::atomic::Ordering::Relaxed);
+            tracing::info_span!("forward").in_scope(|| tracing::info!(target: "tb", loss = 0.5));
+            tracing::info!(target: "tb", step = 2, loss = 0.25);
+        });
+        let scalars = buffer.values();
+        assert_eq!(tags(&scalars), [("loss", 5), ("loss", 6), ("loss", 2)]);
+    }
+}
@@ -0,0 +1,487 @@
+// Validation of event files: every record is read and its crcs are checked, the first record
+// has to be a file version event, and the content of the events is checked for steps going
+// backward within a tag, non-finite scalars, and images that cannot be decoded: with the
+// `image` feature the images are decoded, otherwise only their structure is checked.
+//
+// Each problem is reported as an issue with a severity, the errors are problems with the
+// file itself whereas the warnings are suspicious values that tensorboard still displays.
+use crate::accumulator::{is_scalar_plugin, scalar_of_tensor};
+use crate::{tensorboard, Error, SummaryReader};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The first record is not a `brain.Event:*` file version.
+    FileVersion,
+    LenCrcMismatch,
+    CrcMismatch,
+    /// The last record is incomplete, usually the writer was interrupted.
+    Truncated,
+    Io,
+    Decode,
+    /// A step is smaller than the previous step for the same tag, without a restart.
+    NonMonotonicStep,
+    NonFiniteScalar,
+    InvalidImage,
+}
+
+impl IssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FileVersion => "file_version",
+            Self::LenCrcMismatch => "len_crc_mismatch",
+            Self::CrcMismatch => "crc_mismatch",
+            Self::Truncated => "truncated",
+            Self::Io => "io",
+            Self::Decode => "decode",
+            Self::NonMonotonicStep => "non_monotonic_step",
+            Self::NonFiniteScalar => "non_finite_scalar",
+            Self::InvalidImage => "invalid_image",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::NonMonotonicStep | Self::NonFiniteScalar => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// The position of the record, unknown for the errors opening the file.
+    pub offset: Option<u64>,
+    pub record: Option<u64>,
+    pub step: Option<i64>,
+    pub tag: Option<String>,
+    pub message: String,
+}
+
+impl Issue {
+    pub fn to_json(&self) -> serde_json::Value {
+        let severity = match self.kind.severity() {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        serde_json::json!({
+            "kind": self.kind.as_str(),
+            "severity": severity,
+            "offset": self.offset,
+            "record": self.record,
+            "step": self.step,
+            "tag": self.tag,
+            "message": self.message,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub path: Option<PathBuf>,
+    /// The number of records that have been read, including the corrupted ones.
+    pub records: u64,
+    pub issues: Vec<Issue>,
+}
+
+impl FileReport {
+    pub fn num_issues(&self, severity: Severity) -> usize {
+        self.issues.iter().filter(|i| i.kind.severity() == severity).count()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path.as_ref().map(|p| p.to_string_lossy()),
+            "records": self.records,
+            "errors": self.num_issues(Severity::Error),
+            "warnings": self.num_issues(Severity::Warning),
+            "issues": self.issues.iter().map(|i| i.to_json()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub files: Vec<FileReport>,
+}
+
+impl Report {
+    pub fn num_issues(&self, severity: Severity) -> usize {
+        self.files.iter().map(|f| f.num_issues(severity)).sum()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "files": self.files.iter().map(|f| f.to_json()).collect::<Vec<_>>(),
+            "errors": self.num_issues(Severity::Error),
+            "warnings": self.num_issues(Severity::Warning),
+        })
+    }
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]) as u32)
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Walks the png chunks checking their crcs, returns the dimensions from the header.
+fn check_png(data: &[u8]) -> std::result::Result<(u32, u32), String> {
+    const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    let mut pos = 8;
+    let mut dims = None;
+    loop {
+        let len = be_u32(data, pos).ok_or("truncated png chunk")? as usize;
+        // The chunk length comes from the data, so this could overflow on 32-bit targets.
+        let end =
+            pos.checked_add(8).and_then(|v| v.checked_add(len)).ok_or("truncated png chunk")?;
+        let chunk = data.get(pos + 4..end).ok_or("truncated png chunk")?;
+        let file_crc = be_u32(data, end).ok_or("truncated png chunk")?;
+        let kind = String::from_utf8_lossy(&chunk[..4]).to_string();
+        if CRC.checksum(chunk) != file_crc {
+            return Err(format!("crc mismatch for png chunk {kind}"));
+        }
+        match kind.as_str() {
+            "IHDR" => dims = Some((be_u32(chunk, 4).unwrap_or(0), be_u32(chunk, 8).unwrap_or(0))),
+            _ if dims.is_none() => return Err("the first png chunk is not IHDR".to_string()),
+            "IEND" => break,
+            _ => {}
+        }
+        pos = end + 4
+    }
+    dims.ok_or_else(|| "no png header".to_string())
+}
+
+// Walks the jpeg segments up to the start of scan, returns the dimensions from the start
+// of frame segment.
+fn check_jpeg(data: &[u8]) -> std::result::Result<(u32, u32), String> {
+    if !data.ends_with(b"\xff\xd9") {
+        return Err("missing jpeg end of image marker".to_string());
+    }
+    let mut pos = 2;
+    loop {
+        if data.get(pos) != Some(&0xff) {
+            return Err(format!("invalid jpeg marker at {pos}"));
+        }
+        let marker = *data.get(pos + 1).ok_or("truncated jpeg")?;
+        let len = be_u16(data, pos + 2).ok_or("truncated jpeg")? as usize;
+        match marker {
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let height = be_u16(data, pos + 5).ok_or("truncated jpeg")?;
+                let width = be_u16(data, pos + 7).ok_or("truncated jpeg")?;
+                return Ok((width, height));
+            }
+            0xda => return Err("no jpeg start of frame".to_string()),
+            _ => pos += 2 + len,
+        }
+    }
+}
+
+/// Check that an encoded image can be decoded, returns the width and height. The structure
+/// of the png, jpeg, and gif formats is checked, and with the `image` feature the image is
+/// also decoded.
+pub fn check_image(data: &[u8]) -> std::result::Result<(u32, u32), String> {
+    let dims = check_structure(data)?;
+    #[cfg(feature = "image")]
+    if let Err(err) = ::image::load_from_memory(data) {
+        return Err(format!("cannot decode image: {err}"));
+    }
+    Ok(dims)
+}
+
+fn check_structure(data: &[u8]) -> std::result::Result<(u32, u32), String> {
+    match crate::image_content_type(data) {
+        "image/png" => check_png(data),
+        "image/jpeg" => check_jpeg(data),
+        "image/gif" => {
+            if !data.ends_with(b"\x3b") {
+                return Err("missing gif trailer".to_string());
+            }
+            let dims = data.get(6..10).ok_or("truncated gif")?;
+            let width = u16::from_le_bytes([dims[0], dims[1]]);
+            let height = u16::from_le_bytes([dims[2], dims[3]]);
+            Ok((width as u32, height as u32))
+        }
+        _ => Err("unknown image format".to_string()),
+    }
+}
+
+// The per-file state of the validation.
+struct Validator {
+    report: FileReport,
+    last_steps: HashMap<String, i64>,
+}
+
+impl Validator {
+    fn issue(
+        &mut self,
+        kind: IssueKind,
+        pos: (u64, u64),
+        step: Option<i64>,
+        tag: Option<&str>,
+        message: String,
+    ) {
+        let (offset, record) = (Some(pos.0), Some(pos.1));
+        let tag = tag.map(|t| t.to_string());
+        self.report.issues.push(Issue { kind, offset, record, step, tag, message })
+    }
+
+    fn check_event(&mut self, event: &tensorboard::Event, pos: (u64, u64)) {
+        use tensorboard::event::What;
+        use tensorboard::session_log::SessionStatus;
+        use tensorboard::summary::value::Value;
+
+        let step = event.step;
+        if pos.1 == 0 {
+            match &event.what {
+                Some(What::FileVersion(version)) if version.starts_with("brain.Event:") => {}
+                Some(What::FileVersion(version)) => {
+                    let msg = format!("unexpected file version {version:?}");
+                    self.issue(IssueKind::FileVersion, pos, None, None, msg)
+                }
+                _ => {
+                    let msg = "the first record is not a file version".to_string();
+                    self.issue(IssueKind::FileVersion, pos, None, None, msg)
+                }
+            }
+        }
+        match &event.what {
+            Some(What::SessionLog(s)) if s.status() == SessionStatus::Start => {
+                self.last_steps.retain(|_, last_step| *last_step < step)
+            }
+            Some(What::Summary(summary)) => {
+                for value in summary.value.iter() {
+                    let tag = value.tag.as_str();
+                    match self.last_steps.insert(tag.to_string(), step) {
+                        Some(last_step) if last_step > step => {
+                            let msg = format!("step {step} after step {last_step}");
+                            self.issue(IssueKind::NonMonotonicStep, pos, Some(step), Some(tag), msg)
+                        }
+                        _ => {}
+                    }
+                    let scalar = match &value.value {
+                        Some(Value::SimpleValue(v)) => Some(*v as f64),
+                        Some(Value::Tensor(t)) if is_scalar_plugin(&value.metadata) => {
+                            scalar_of_tensor(t)
+                        }
+                        Some(Value::Image(image)) => {
+                            let (width, height) = (image.width as u32, image.height as u32);
+                            match check_image(&image.encoded_image_string) {
+                                Err(msg) => self.issue(
+                                    IssueKind::InvalidImage,
+                                    pos,
+                                    Some(step),
+                                    Some(tag),
+                                    msg,
+                                ),
+                                Ok(dims) if image.width > 0 && dims != (width, height) => {
+                                    let msg = format!(
+                                        "image is {}x{} but the summary says {width}x{height}",
+                                        dims.0, dims.1
+                                    );
+                                    self.issue(
+                                        IssueKind::InvalidImage,
+                                        pos,
+                                        Some(step),
+                                        Some(tag),
+                                        msg,
+                                    )
+                                }
+                                Ok(_) => {}
+                            }
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(v) = scalar.filter(|v| !v.is_finite()) {
+                        let msg = format!("non-finite value {v}");
+                        self.issue(IssueKind::NonFiniteScalar, pos, Some(step), Some(tag), msg)
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Validate the events from a reader.
+pub fn validate_reader<R: std::io::Read>(mut reader: SummaryReader<R>) -> FileReport {
+    let path = reader.path().map(|p| p.to_path_buf());
+    let report = FileReport { path, records: 0, issues: vec![] };
+    let mut v = Validator { report, last_steps: HashMap::new() };
+    loop {
+        let pos = (reader.offset(), reader.index());
+        let event = match reader.next() {
+            None => break,
+            Some(event) => event,
+        };
+        v.report.records += 1;
+        let (kind, stop) = match &event {
+            Ok(event) => {
+                v.check_event(event, pos);
+                continue;
+            }
+            Err(Error::CrcMismatch { .. }) => (IssueKind::CrcMismatch, false),
+            Err(Error::RecordDecode { .. }) => (IssueKind::Decode, false),
+            // The reader cannot find the next record after these errors.
+            Err(Error::LenCrcMismatch { .. }) => (IssueKind::LenCrcMismatch, true),
+            Err(Error::TruncatedRecord { .. }) => (IssueKind::Truncated, true),
+            Err(Error::InvalidRecord { .. }) => (IssueKind::Decode, true),
+            Err(_) => (IssueKind::Io, true),
+        };
+        v.issue(kind, pos, None, None, event.unwrap_err().to_string());
+        if stop {
+            break;
+        }
+    }
+    if v.report.records == 0 {
+        let msg = "empty file".to_string();
+        v.issue(IssueKind::FileVersion, (0, 0), None, None, msg)
+    }
+    v.report
+}
+
+/// Validate an event file, the errors opening the file are reported as issues.
+pub fn validate_file<P: AsRef<Path>>(path: P) -> FileReport {
+    let path = path.as_ref();
+    match std::fs::File::open(path) {
+        Ok(file) => {
+            validate_reader(SummaryReader::new(std::io::BufReader::new(file)).with_path(path))
+        }
+        Err(err) => {
+            let issue = Issue {
+                kind: IssueKind::Io,
+                offset: None,
+                record: None,
+                step: None,
+                tag: None,
+                message: err.to_string(),
+            };
+            FileReport { path: Some(path.to_path_buf()), records: 0, issues: vec![issue] }
+        }
+    }
+}
+
+/// Validate an event file, or all the event files from a log directory.
+pub fn validate<P: AsRef<Path>>(path: P) -> crate::Result<Report> {
+    let path = path.as_ref();
+    let files = if path.is_dir() {
+        crate::logdir::runs(path)?.into_iter().flat_map(|run| run.files).collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+    Ok(Report { files: files.iter().map(validate_file).collect() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventWriter;
+
+    fn kinds(report: &FileReport) -> Vec<(IssueKind, Option<u64>, Option<&str>)> {
+        report.issues.iter().map(|i| (i.kind, i.record, i.tag.as_deref())).collect()
+    }
+
+    #[test]
+    fn check_images() {
+        let png = crate::png::encode(3, 2, 1, &[0; 6]).unwrap();
+        assert_eq!(check_image(&png), Ok((3, 2)));
+        let mut corrupted = png.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(check_image(&corrupted), Err("crc mismatch for png chunk IHDR".to_string()));
+        assert_eq!(check_image(&png[..png.len() - 4]), Err("truncated png chunk".to_string()));
+        let gif = b"GIF89a\x05\x00\x07\x00;";
+        assert_eq!(check_structure(gif), Ok((5, 7)));
+        assert_eq!(check_structure(&gif[..10]), Err("missing gif trailer".to_string()));
+        assert_eq!(check_image(b"BM"), Err("unknown image format".to_string()));
+        // A chunk length that would go past the end of the address space.
+        let mut huge = png[..8].to_vec();
+        huge.extend_from_slice(&[0xff; 8]);
+        assert_eq!(check_image(&huge), Err("truncated png chunk".to_string()));
+    }
+
+    #[test]
+    fn check_image_data() {
+        let chunk = |kind: &[u8], data: &[u8]| {
+            const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+            let content = [kind, data].concat();
+            let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(&content);
+            chunk.extend_from_slice(&CRC.checksum(&content).to_be_bytes());
+            chunk
+        };
+        // A 2x2 grayscale png with a valid structure but an invalid zlib stream.
+        let header = [0, 0, 0, 2, 0, 0, 0, 2, 8, 0, 0, 0, 0];
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&chunk(b"IHDR", &header));
+        png.extend_from_slice(&chunk(b"IDAT", &[0x78, 0x01, 0xff, 0xff, 0xff]));
+        png.extend_from_slice(&chunk(b"IEND", &[]));
+        assert_eq!(check_structure(&png), Ok((2, 2)));
+        #[cfg(feature = "image")]
+        {
+            let err = check_image(&png).unwrap_err();
+            assert!(err.starts_with("cannot decode image: "), "{err}");
+            let png = crate::png::encode(3, 2, 3, &[7; 18]).unwrap();
+            assert_eq!(check_image(&png), Ok((3, 2)));
+        }
+        #[cfg(not(feature = "image"))]
+        assert_eq!(check_image(&png), Ok((2, 2)));
+    }
+
+    #[test]
+    fn validate_events() {
+        let mut data = vec![];
+        let mut writer = EventWriter::from_writer(&mut data, None).unwrap();
+        writer.write_scalar(1, "loss", 1.).unwrap();
+        writer.write_scalar(2, "loss", f32::NAN).unwrap();
+        writer.write_scalar(0, "loss", 2.).unwrap();
+        let png = crate::png::encode(3, 2, 1, &[0; 6]).unwrap();
+        writer.write_image(3, "img", 4, 2, 1, png).unwrap();
+        writer.write_image(3, "gif", 0, 0, 1, b"GIF89a".to_vec()).unwrap();
+        drop(writer);
+        let report = validate_reader(SummaryReader::new(data.as_slice()));
+        assert_eq!(report.records, 6);
+        assert_eq!(report.num_issues(Severity::Warning), 2);
+        let expected = [
+            (IssueKind::NonFiniteScalar, Some(2), Some("loss")),
+            (IssueKind::NonMonotonicStep, Some(3), Some("loss")),
+            (IssueKind::InvalidImage, Some(4), Some("img")),
+            (IssueKind::InvalidImage, Some(5), Some("gif")),
+        ];
+        assert_eq!(kinds(&report), expected);
+        assert_eq!(report.issues[1].message, "step 0 after step 2");
+        assert_eq!(report.issues[2].message, "image is 3x2 but the summary says 4x2");
+    }
+
+    #[test]
+    fn validate_corrupted() {
+        let data = crate::test_utils::scalar_events(&[("a", 1, 1.), ("a", 2, 2.)]);
+        let record_len = data.len() - crate::test_utils::scalar_events(&[("a", 1, 1.)]).len();
+        let mut corrupted = data.clone();
+        corrupted[data.len() - record_len - 10] ^= 1;
+        let report = validate_reader(SummaryReader::new(&corrupted[..data.len() - 2]));
+        assert_eq!(report.records, 3);
+        let expected =
+            [(IssueKind::CrcMismatch, Some(1), None), (IssueKind::Truncated, Some(2), None)];
+        assert_eq!(kinds(&report), expected);
+        let offset = (data.len() - record_len) as u64;
+        assert_eq!(report.issues[1].offset, Some(offset));
+
+        let report = validate_reader(SummaryReader::new(&data[data.len() - 2 * record_len..]));
+        assert_eq!(kinds(&report), [(IssueKind::FileVersion, Some(0), None)]);
+        let report = validate_reader(SummaryReader::new(&[][..]));
+        assert_eq!(report.issues[0].message, "empty file");
+    }
+}
@@ -0,0 +1,386 @@
+// Zero-copy access to the records of an event file held in memory or memory mapped. The
+// records crcs are validated over the borrowed slices, and decoding the payload into a
+// `tensorboard::Event` only happens on demand so that filtering on the step, wall time or
+// tags is cheap.
+use crate::reader::{check_payload, parse_header, FOOTER_LEN, HEADER_LEN};
+use crate::{tensorboard, Error, RecordPos, Result};
+use prost::Message;
+
+// Protobuf field numbers, see event.proto and summary.proto.
+const EVENT_WALL_TIME: u32 = 1;
+const EVENT_STEP: u32 = 2;
+const EVENT_SUMMARY: u32 = 5;
+const SUMMARY_VALUE: u32 = 1;
+const VALUE_TAG: u32 = 1;
+
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+// Minimal protobuf wire format parser iterating over the top-level fields of a message.
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+fn decode_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = match data.split_first() {
+            None => crate::bail!("unexpected end of buffer in varint"),
+            Some(v) => v,
+        };
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    crate::bail!("invalid varint")
+}
+
+fn split_at<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        crate::bail!("unexpected end of buffer, expected {len} bytes, got {}", data.len())
+    }
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
+
+impl<'a> Fields<'a> {
+    fn read_field(&mut self) -> Result<(u32, WireValue<'a>)> {
+        let key = decode_varint(&mut self.data)?;
+        let value = match key & 7 {
+            0 => WireValue::Varint(decode_varint(&mut self.data)?),
+            1 => {
+                let value = split_at(&mut self.data, 8)?;
+                WireValue::Fixed64(u64::from_le_bytes(value.try_into().unwrap()))
+            }
+            2 => {
+                let len = decode_varint(&mut self.data)?;
+                let len = usize::try_from(len).unwrap_or(usize::MAX);
+                WireValue::Bytes(split_at(&mut self.data, len)?)
+            }
+            5 => {
+                split_at(&mut self.data, 4)?;
+                WireValue::Fixed32
+            }
+            wire_type => crate::bail!("unsupported wire type {wire_type}"),
+        };
+        Ok(((key >> 3) as u32, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, WireValue<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let field = self.read_field();
+        if field.is_err() {
+            self.data = &[]
+        }
+        Some(field)
+    }
+}
+
+// Extracts the tags from an encoded `tensorboard::Event`.
+fn summary_tags<'a>(payload: &'a [u8], tags: &mut Vec<&'a str>) -> Result<()> {
+    for field in (Fields { data: payload }) {
+        let summary = match field? {
+            (EVENT_SUMMARY, WireValue::Bytes(summary)) => summary,
+            _ => continue,
+        };
+        for field in (Fields { data: summary }) {
+            let value = match field? {
+                (SUMMARY_VALUE, WireValue::Bytes(value)) => value,
+                _ => continue,
+            };
+            for field in (Fields { data: value }) {
+                if let (VALUE_TAG, WireValue::Bytes(tag)) = field? {
+                    tags.push(std::str::from_utf8(tag).map_err(Error::msg)?)
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A borrowed view over a record with a valid crc.
+#[derive(Debug, Clone, Copy)]
+pub struct RecordView<'a> {
+    path: Option<&'a std::path::Path>,
+    offset: u64,
+    index: u64,
+    payload: &'a [u8],
+}
+
+impl<'a> RecordView<'a> {
+    /// Byte offset of the start of the record.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Ordinal of the record in the file.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// The encoded `tensorboard::Event`.
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    fn pos(&self) -> RecordPos {
+        let path = self.path.map(|v| v.to_path_buf());
+        RecordPos { path, offset: self.offset, index: self.index }
+    }
+
+    fn wrap_err(&self, err: Error) -> Error {
+        match err {
+            Error::Msg(msg) => Error::InvalidRecord { msg, pos: self.pos() },
+            err => err,
+        }
+    }
+
+    fn fields(&self) -> Fields<'a> {
+        Fields { data: self.payload }
+    }
+
+    // When a field occurs multiple times, the last occurrence wins as per the protobuf
+    // merge semantics, so the whole payload is scanned.
+    pub fn wall_time(&self) -> Result<f64> {
+        let mut wall_time = 0.;
+        for field in self.fields() {
+            if let (EVENT_WALL_TIME, WireValue::Fixed64(v)) = field.map_err(|e| self.wrap_err(e))? {
+                wall_time = f64::from_bits(v)
+            }
+        }
+        Ok(wall_time)
+    }
+
+    pub fn step(&self) -> Result<i64> {
+        let mut step = 0;
+        for field in self.fields() {
+            if let (EVENT_STEP, WireValue::Varint(v)) = field.map_err(|e| self.wrap_err(e))? {
+                step = v as i64
+            }
+        }
+        Ok(step)
+    }
+
+    /// Returns true if the record contains a summary.
+    pub fn is_summary(&self) -> Result<bool> {
+        for field in self.fields() {
+            if let (EVENT_SUMMARY, _) = field.map_err(|e| self.wrap_err(e))? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The tags of the summary values in this record, without decoding the values.
+    pub fn tags(&self) -> Result<Vec<&'a str>> {
+        let mut tags = vec![];
+        summary_tags(self.payload, &mut tags).map_err(|e| self.wrap_err(e))?;
+        Ok(tags)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> Result<bool> {
+        Ok(self.tags()?.contains(&tag))
+    }
+
+    /// Fully decode the record.
+    pub fn decode(&self) -> Result<tensorboard::Event> {
+        tensorboard::Event::decode(self.payload)
+            .map_err(|source| Error::RecordDecode { source, pos: self.pos() })
+    }
+}
+
+/// Iterates over the records of an event file held in memory, checking the crcs but not
+/// decoding the payloads. Iteration stops after the first error.
+pub struct SliceReader<'a> {
+    data: &'a [u8],
+    path: Option<&'a std::path::Path>,
+    offset: u64,
+    index: u64,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, path: None, offset: 0, index: 0 }
+    }
+
+    /// Set the path reported in errors.
+    pub fn with_path(mut self, path: &'a std::path::Path) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    /// The byte offset of the next record to be read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Decode all the remaining records.
+    pub fn events(self) -> impl Iterator<Item = Result<tensorboard::Event>> + 'a {
+        self.map(|record| record?.decode())
+    }
+
+    fn read_record(&mut self) -> Result<RecordView<'a>> {
+        let path = self.path.map(|v| v.to_path_buf());
+        let pos = RecordPos { path, offset: self.offset, index: self.index };
+        let data = self.data.get(self.offset as usize..).unwrap_or_default();
+        if data.len() < HEADER_LEN {
+            let (expected_len, actual_len) = (None, 0);
+            return Err(Error::TruncatedRecord { expected_len, actual_len, pos });
+        }
+        let event_len = parse_header(data, &pos)?;
+        let record_len = usize::try_from(event_len)
+            .ok()
+            .and_then(|len| len.checked_add(HEADER_LEN + FOOTER_LEN))
+            .filter(|&len| len <= data.len());
+        let record_len = match record_len {
+            Some(record_len) => record_len,
+            None => {
+                let actual_len = (data.len() - HEADER_LEN) as u64;
+                let (expected_len, actual_len) = (Some(event_len), actual_len.min(event_len));
+                return Err(Error::TruncatedRecord { expected_len, actual_len, pos });
+            }
+        };
+        let payload = &data[HEADER_LEN..record_len - FOOTER_LEN];
+        let footer = &data[record_len - FOOTER_LEN..record_len];
+        check_payload(payload, footer, &pos)?;
+        let record =
+            RecordView { path: self.path, offset: self.offset, index: self.index, payload };
+        self.offset += record_len as u64;
+        self.index += 1;
+        Ok(record)
+    }
+}
+
+impl<'a> Iterator for SliceReader<'a> {
+    type Item = Result<RecordView<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset as usize >= self.data.len() {
+            return None;
+        }
+        let record = self.read_record();
+        if record.is_err() {
+            self.offset = self.data.len() as u64
+        }
+        Some(record)
+    }
+}
+
+/// An event file mapped in memory.
+#[cfg(feature = "mmap")]
+pub struct MmapReader {
+    mmap: memmap2::Mmap,
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapReader {
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)?;
+        // Safety: the file should not be modified while it is mapped. Appending to the file
+        // is fine as the mapping does not cover the new data, but truncating it results in
+        // a SIGBUS when accessing the mapped pages past the new end of file.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self { mmap, path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    pub fn records(&self) -> SliceReader<'_> {
+        SliceReader::new(&self.mmap).with_path(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scalar_events;
+
+    #[test]
+    fn lazy_fields() {
+        let data = scalar_events(&[("loss", 3, 1.5), ("acc", 7, 0.5)]);
+        let records: Vec<_> = SliceReader::new(&data).map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert!(!records[0].is_summary().unwrap());
+        assert!(records[0].tags().unwrap().is_empty());
+        assert_eq!(records[1].step().unwrap(), 3);
+        assert_eq!(records[1].tags().unwrap(), ["loss"]);
+        assert!(records[2].has_tag("acc").unwrap());
+        assert!(!records[2].has_tag("loss").unwrap());
+        let event = records[2].decode().unwrap();
+        assert_eq!(records[2].wall_time().unwrap(), event.wall_time);
+        assert_eq!(event.step, 7);
+        assert_eq!(records[2].index(), 2);
+
+        let events: Vec<_> = SliceReader::new(&data).events().map(|e| e.unwrap()).collect();
+        let expected: Vec<_> =
+            crate::SummaryReader::new(data.as_slice()).map(|e| e.unwrap()).collect();
+        assert_eq!(events, expected);
+    }
+
+    fn record(payload: &[u8]) -> Vec<u8> {
+        let mut record = (payload.len() as u64).to_le_bytes().to_vec();
+        record.extend_from_slice(&crate::masked_crc(&record).to_le_bytes());
+        record.extend_from_slice(payload);
+        record.extend_from_slice(&crate::masked_crc(payload).to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn repeated_fields() {
+        // Concatenated messages are merged, the last occurrence of a field wins.
+        let event = |wall_time, step| tensorboard::Event { wall_time, step, ..Default::default() };
+        let mut payload = event(1.5, 3).encode_to_vec();
+        payload.extend_from_slice(&event(2.5, 9).encode_to_vec());
+        let record = record(&payload);
+        let view = SliceReader::new(&record).next().unwrap().unwrap();
+        let decoded = view.decode().unwrap();
+        assert_eq!((decoded.wall_time, decoded.step), (2.5, 9));
+        assert_eq!((view.wall_time().unwrap(), view.step().unwrap()), (2.5, 9));
+    }
+
+    #[test]
+    fn truncated_and_corrupted() {
+        let data = scalar_events(&[("loss", 3, 1.5), ("acc", 7, 0.5)]);
+        let mut reader = SliceReader::new(&data[..data.len() - 6]);
+        reader.next().unwrap().unwrap();
+        let offset = reader.next().unwrap().unwrap().offset();
+        let second_len = reader.offset() - offset;
+        let err = reader.next().unwrap().unwrap_err();
+        match err {
+            Error::TruncatedRecord { expected_len: Some(len), actual_len, pos } => {
+                assert_eq!(len - actual_len, 2);
+                assert_eq!((pos.offset, pos.index), (offset + second_len, 2));
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+        assert!(reader.next().is_none());
+
+        // A payload with a valid crc that is not a valid protobuf message.
+        let record = record(&[0x0a, 0xff]);
+        let record = SliceReader::new(&record).next().unwrap().unwrap();
+        let err = record.tags().unwrap_err();
+        assert!(matches!(err, Error::InvalidRecord { ref pos, .. } if pos.index == 0), "{err:?}");
+        assert!(matches!(record.decode(), Err(Error::RecordDecode { .. })));
+    }
+}
@@ -1,9 +1,29 @@
 use crate::{masked_crc, tensorboard, Result};
-use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 use prost::Message;
 
 pub trait TensorType: Sized {
     fn into_proto(v: Vec<Self>) -> tensorboard::TensorProto;
+
+    /// Returns the values of a tensor with the matching data type, either from the typed
+    /// value field or from the little-endian `tensor_content` bytes. Returns `None` for
+    /// the other data types, or when the content is not a whole number of values. The
+    /// default implementation always returns `None`.
+    fn from_proto(_tensor: &tensorboard::TensorProto) -> Option<Vec<Self>> {
+        None
+    }
+}
+
+// Returns `None` when the content is not a whole number of values.
+pub(crate) fn from_content<T, const N: usize>(
+    content: &[u8],
+    f: impl Fn([u8; N]) -> T,
+) -> Option<Vec<T>> {
+    let chunks = content.chunks_exact(N);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    Some(chunks.map(|v| f(v.try_into().unwrap())).collect())
 }
 
 impl TensorType for f32 {
@@ -16,6 +36,16 @@ impl TensorType for f32 {
             ..Default::default()
         }
     }
+
+    fn from_proto(tensor: &tensorboard::TensorProto) -> Option<Vec<Self>> {
+        if tensor.dtype() != tensorboard::DataType::DtFloat {
+            None
+        } else if tensor.tensor_content.is_empty() {
+            Some(tensor.float_val.clone())
+        } else {
+            from_content(&tensor.tensor_content, f32::from_le_bytes)
+        }
+    }
 }
 
 impl TensorType for f64 {
@@ -28,6 +58,16 @@ impl TensorType for f64 {
             ..Default::default()
         }
     }
+
+    fn from_proto(tensor: &tensorboard::TensorProto) -> Option<Vec<Self>> {
+        if tensor.dtype() != tensorboard::DataType::DtDouble {
+            None
+        } else if tensor.tensor_content.is_empty() {
+            Some(tensor.double_val.clone())
+        } else {
+            from_content(&tensor.tensor_content, f64::from_le_bytes)
+        }
+    }
 }
 
 impl TensorType for i64 {
@@ -40,6 +80,16 @@ impl TensorType for i64 {
             ..Default::default()
         }
     }
+
+    fn from_proto(tensor: &tensorboard::TensorProto) -> Option<Vec<Self>> {
+        if tensor.dtype() != tensorboard::DataType::DtInt64 {
+            None
+        } else if tensor.tensor_content.is_empty() {
+            Some(tensor.int64_val.clone())
+        } else {
+            from_content(&tensor.tensor_content, i64::from_le_bytes)
+        }
+    }
 }
 
 impl TensorType for i32 {
@@ -52,19 +102,210 @@ impl TensorType for i32 {
             ..Default::default()
         }
     }
+
+    fn from_proto(tensor: &tensorboard::TensorProto) -> Option<Vec<Self>> {
+        if tensor.dtype() != tensorboard::DataType::DtInt32 {
+            None
+        } else if tensor.tensor_content.is_empty() {
+            Some(tensor.int_val.clone())
+        } else {
+            from_content(&tensor.tensor_content, i32::from_le_bytes)
+        }
+    }
+}
+
+/// The values that can be written as a tensor summary.
+pub trait IntoTensor {
+    fn into_tensor_proto(self) -> Result<tensorboard::TensorProto>;
+}
+
+impl<T: TensorType> IntoTensor for Vec<T> {
+    fn into_tensor_proto(self) -> Result<tensorboard::TensorProto> {
+        Ok(T::into_proto(self))
+    }
 }
 
-fn global_uid() -> u64 {
+#[cfg(any(feature = "candle", feature = "ndarray"))]
+pub(crate) fn tensor_shape(dims: &[usize]) -> tensorboard::TensorShapeProto {
+    let dim = dims
+        .iter()
+        .map(|&size| tensorboard::tensor_shape_proto::Dim {
+            size: size as i64,
+            name: String::new(),
+        })
+        .collect();
+    tensorboard::TensorShapeProto { dim, unknown_rank: false }
+}
+
+pub(crate) fn global_uid() -> u64 {
     // https://users.rust-lang.org/t/idiomatic-rust-way-to-generate-unique-id/33805
     use std::sync::atomic;
     static COUNTER: atomic::AtomicU64 = atomic::AtomicU64::new(1);
     COUNTER.fetch_add(1, atomic::Ordering::Relaxed)
 }
 
+// Returns the name of a new event file in `logdir`, creating the directory if needed.
+pub(crate) fn event_filename(logdir: &std::path::Path) -> Result<std::path::PathBuf> {
+    let now = std::time::SystemTime::now();
+    let now = now.duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    event_filename_at(logdir, now)
+}
+
+// Same as `event_filename` with the timestamp in seconds used in the name, e.g. the wall time
+// of the first event when importing some old data.
+pub(crate) fn event_filename_at(
+    logdir: &std::path::Path,
+    timestamp: u64,
+) -> Result<std::path::PathBuf> {
+    if logdir.is_file() {
+        let logdir = logdir.canonicalize();
+        crate::bail!("{logdir:?} is not a directory")
+    }
+    if !logdir.exists() {
+        std::fs::create_dir_all(logdir)?
+    }
+    // https://github.com/tensorflow/tensorboard/blob/d1ab6e7a39e4dc4d556a8a73c0ae5c1b116801ba/tensorboard/summary/writer/event_file_writer.py#L76
+    let hostname = hostname::get()?;
+    let hostname = hostname.to_string_lossy();
+    let pid = std::process::id();
+    let uid = global_uid();
+    Ok(logdir.join(format!("events.out.tfevents.{timestamp:010}.{hostname}.{pid}.{uid}")))
+}
+
+// https://github.com/LaurentMazare/ocaml-tensorboard/blob/11022591e15327f31595443d18e1f3e38cc0a433/src/tensorboard/tf_record_writer.ml#L25
+// Encodes an event as a record in `buf`: the payload length and its crc, followed by the
+// payload and its crc.
+pub(crate) fn encode_record(event: &tensorboard::Event, buf: &mut Vec<u8>) -> Result<()> {
+    let event_len = event.encoded_len();
+    buf.clear();
+    buf.write_u64::<LittleEndian>(event_len as u64)?;
+    let buf_len_crc = masked_crc(buf.as_slice());
+    buf.write_u32::<LittleEndian>(buf_len_crc)?;
+    event.encode(buf)?;
+    let event_crc = masked_crc(&buf[12..]);
+    buf.write_u32::<LittleEndian>(event_crc)?;
+    Ok(())
+}
+
+pub(crate) fn wall_time_now() -> Result<f64> {
+    let now = std::time::SystemTime::now();
+    let now = now.duration_since(std::time::UNIX_EPOCH)?;
+    Ok(now.as_secs() as f64 + now.subsec_nanos() as f64 / 1e9)
+}
+
+// Helpers building the summaries for the typed `write_*` methods, these are shared
+// between the sync and async writers.
+pub(crate) mod what {
+    use crate::tensorboard;
+    use tensorboard::event::What;
+    use tensorboard::summary::value::Value;
+
+    pub(crate) fn summary(tag: &str, value: Value) -> What {
+        let value = tensorboard::summary::Value {
+            node_name: "".to_string(),
+            tag: tag.to_string(),
+            metadata: None,
+            value: Some(value),
+        };
+        What::Summary(tensorboard::Summary { value: vec![value] })
+    }
+
+    pub(crate) fn scalar(tag: &str, value: f32) -> What {
+        summary(tag, Value::SimpleValue(value))
+    }
+
+    pub(crate) fn audio(
+        tag: &str,
+        content_type: &str,
+        encoded_audio_string: Vec<u8>,
+        length_frames: i64,
+        num_channels: i64,
+        sample_rate: f32,
+    ) -> What {
+        let audio = tensorboard::summary::Audio {
+            content_type: content_type.to_string(),
+            encoded_audio_string,
+            length_frames,
+            num_channels,
+            sample_rate,
+        };
+        summary(tag, Value::Audio(audio))
+    }
+
+    pub(crate) fn pcm_as_wav<S: crate::wave::Sample>(
+        tag: &str,
+        pcm_data: &[S],
+        sample_rate: u32,
+    ) -> crate::Result<What> {
+        let mut encoded_data = Vec::new();
+        crate::wave::write_pcm_as_wav(&mut encoded_data, pcm_data, sample_rate)?;
+        let length_frames = pcm_data.len() as i64;
+        Ok(audio(tag, "audio/wav", encoded_data, length_frames, 1, sample_rate as f32))
+    }
+
+    pub(crate) fn histo(tag: &str, histo: tensorboard::HistogramProto) -> What {
+        summary(tag, Value::Histo(histo))
+    }
+
+    pub(crate) fn image(
+        tag: &str,
+        width: i32,
+        height: i32,
+        colorspace: i32,
+        encoded_image_string: Vec<u8>,
+    ) -> What {
+        let image = tensorboard::summary::Image { width, height, colorspace, encoded_image_string };
+        summary(tag, Value::Image(image))
+    }
+
+    pub(crate) fn tensor<T: super::IntoTensor>(tag: &str, val: T) -> crate::Result<What> {
+        Ok(summary(tag, Value::Tensor(val.into_tensor_proto()?)))
+    }
+
+    // Encodes some HWC pixels as PNG.
+    pub(crate) fn image_pixels(
+        tag: &str,
+        width: usize,
+        height: usize,
+        channels: usize,
+        pixels: &[u8],
+    ) -> crate::Result<What> {
+        let data = crate::png::encode(width, height, channels, pixels)?;
+        Ok(image(tag, width as i32, height as i32, channels as i32, data))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn video<T: crate::grid::Pixel>(
+        tag: &str,
+        frames: &[T],
+        n_frames: usize,
+        height: usize,
+        width: usize,
+        channels: usize,
+        fps: f32,
+    ) -> crate::Result<What> {
+        let mut data = Vec::new();
+        crate::gif::write_frames_as_gif(&mut data, frames, n_frames, height, width, channels, fps)?;
+        Ok(image(tag, width as i32, height as i32, channels as i32, data))
+    }
+
+    pub(crate) fn session_log(
+        status: tensorboard::session_log::SessionStatus,
+        checkpoint_path: &str,
+        msg: &str,
+    ) -> What {
+        let session_log = tensorboard::SessionLog {
+            status: status.into(),
+            checkpoint_path: checkpoint_path.to_string(),
+            msg: msg.to_string(),
+        };
+        What::SessionLog(session_log)
+    }
+}
+
 /// Similar to tensorboard EventFileWriter
 pub struct EventWriter<W: std::io::Write> {
     writer: W,
-    buf_len: [u8; 8],
     buf: Vec<u8>,
     filename: Option<std::path::PathBuf>,
 }
@@ -72,22 +313,7 @@ pub struct EventWriter<W: std::io::Write> {
 impl EventWriter<std::fs::File> {
     /// Create an `EventFileWriter` like structure in the specified log directory.
     pub fn create<P: AsRef<std::path::Path>>(logdir: P) -> Result<Self> {
-        let logdir = logdir.as_ref();
-        if logdir.is_file() {
-            let logdir = logdir.canonicalize();
-            crate::bail!("{logdir:?} is not a directory")
-        }
-        if !logdir.exists() {
-            std::fs::create_dir_all(logdir)?
-        }
-        // https://github.com/tensorflow/tensorboard/blob/d1ab6e7a39e4dc4d556a8a73c0ae5c1b116801ba/tensorboard/summary/writer/event_file_writer.py#L76
-        let now = std::time::SystemTime::now();
-        let now = now.duration_since(std::time::UNIX_EPOCH)?.as_secs();
-        let hostname = hostname::get()?;
-        let hostname = hostname.to_string_lossy();
-        let pid = std::process::id();
-        let uid = global_uid();
-        let filename = logdir.join(format!("events.out.tfevents.{now:010}.{hostname}.{pid}.{uid}"));
+        let filename = event_filename(logdir.as_ref())?;
         let file = std::fs::File::create(&filename)?;
         Self::from_writer(file, Some(filename))
     }
@@ -95,32 +321,42 @@ impl EventWriter<std::fs::File> {
 
 impl<W: std::io::Write> EventWriter<W> {
     pub fn from_writer(writer: W, filename: Option<std::path::PathBuf>) -> Result<Self> {
-        let mut slf = Self { writer, buf_len: Default::default(), buf: vec![0u8, 128], filename };
-        slf.write(0, tensorboard::event::What::FileVersion("brain.Event:2".to_string()))?;
+        Self::from_writer_with_wall_time(writer, filename, wall_time_now()?)
+    }
+
+    // Uses `wall_time` for the file version event, this is used when writing events that
+    // do not happen now, e.g. when importing some old data.
+    pub(crate) fn from_writer_with_wall_time(
+        writer: W,
+        filename: Option<std::path::PathBuf>,
+        wall_time: f64,
+    ) -> Result<Self> {
+        let mut slf = Self { writer, buf: vec![0u8, 128], filename };
+        slf.write_event(tensorboard::Event {
+            wall_time,
+            step: 0,
+            source_metadata: None,
+            what: Some(tensorboard::event::What::FileVersion("brain.Event:2".to_string())),
+        })?;
         Ok(slf)
     }
 
-    // https://github.com/LaurentMazare/ocaml-tensorboard/blob/11022591e15327f31595443d18e1f3e38cc0a433/src/tensorboard/tf_record_writer.ml#L25
     pub fn write_event(&mut self, event: tensorboard::Event) -> Result<()> {
-        let event_len = event.encoded_len();
-        LittleEndian::write_u64(&mut self.buf_len, event_len as u64);
-        let buf_len_crc = masked_crc(self.buf_len.as_slice());
-        self.writer.write_all(self.buf_len.as_slice())?;
-        self.writer.write_u32::<LittleEndian>(buf_len_crc)?;
-        self.buf.truncate(0);
-        event.encode(&mut self.buf)?;
+        encode_record(&event, &mut self.buf)?;
         self.writer.write_all(self.buf.as_slice())?;
-        let event_crc = masked_crc(self.buf.as_slice());
-        self.writer.write_u32::<LittleEndian>(event_crc)?;
+        Ok(())
+    }
+
+    // Writes a record that has already been encoded and checked, e.g. a record copied from
+    // another event file, so that the fields unknown to the protos are preserved.
+    pub(crate) fn write_raw_record(&mut self, record: &[u8]) -> Result<()> {
+        self.writer.write_all(record)?;
         Ok(())
     }
 
     pub fn write(&mut self, step: i64, what: tensorboard::event::What) -> Result<()> {
-        let now = std::time::SystemTime::now();
-        let now = now.duration_since(std::time::UNIX_EPOCH)?;
-        let wall_time = now.as_secs() as f64 + now.subsec_nanos() as f64 / 1e9;
         self.write_event(tensorboard::Event {
-            wall_time,
+            wall_time: wall_time_now()?,
             step,
             source_metadata: None,
             what: Some(what),
@@ -128,14 +364,7 @@ impl<W: std::io::Write> EventWriter<W> {
     }
 
     pub fn write_scalar(&mut self, step: i64, tag: &str, value: f32) -> Result<()> {
-        let value = tensorboard::summary::Value {
-            node_name: "".to_string(),
-            tag: tag.to_string(),
-            metadata: None,
-            value: Some(tensorboard::summary::value::Value::SimpleValue(value)),
-        };
-        let what = tensorboard::event::What::Summary(tensorboard::Summary { value: vec![value] });
-        self.write(step, what)
+        self.write(step, what::scalar(tag, value))
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -149,20 +378,14 @@ impl<W: std::io::Write> EventWriter<W> {
         num_channels: i64,
         sample_rate: f32,
     ) -> Result<()> {
-        let audio = tensorboard::summary::Audio {
-            content_type: content_type.to_string(),
+        let what = what::audio(
+            tag,
+            content_type,
             encoded_audio_string,
             length_frames,
             num_channels,
             sample_rate,
-        };
-        let value = tensorboard::summary::Value {
-            node_name: "".to_string(),
-            tag: tag.to_string(),
-            metadata: None,
-            value: Some(tensorboard::summary::value::Value::Audio(audio)),
-        };
-        let what = tensorboard::event::What::Summary(tensorboard::Summary { value: vec![value] });
+        );
         self.write(step, what)
     }
 
@@ -173,17 +396,7 @@ impl<W: std::io::Write> EventWriter<W> {
         pcm_data: &[S],
         sample_rate: u32,
     ) -> Result<()> {
-        let mut encoded_data = Vec::new();
-        crate::wave::write_pcm_as_wav(&mut encoded_data, pcm_data, sample_rate)?;
-        self.write_audio(
-            step,
-            tag,
-            "audio/wav",
-            encoded_data,
-            pcm_data.len() as i64,
-            1,
-            sample_rate as f32,
-        )
+        self.write(step, what::pcm_as_wav(tag, pcm_data, sample_rate)?)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -201,14 +414,26 @@ impl<W: std::io::Write> EventWriter<W> {
     ) -> Result<()> {
         let histo =
             tensorboard::HistogramProto { bucket, bucket_limit, max, min, num, sum, sum_squares };
-        let value = tensorboard::summary::Value {
-            node_name: "".to_string(),
-            tag: tag.to_string(),
-            metadata: None,
-            value: Some(tensorboard::summary::value::Value::Histo(histo)),
-        };
-        let what = tensorboard::event::What::Summary(tensorboard::Summary { value: vec![value] });
-        self.write(step, what)
+        self.write(step, what::histo(tag, histo))
+    }
+
+    pub fn write_histogram(
+        &mut self,
+        step: i64,
+        tag: &str,
+        histogram: &crate::histogram::Histogram,
+    ) -> Result<()> {
+        self.write(step, what::histo(tag, histogram.to_proto()))
+    }
+
+    /// Write a histogram of some values using the tensorflow default buckets.
+    pub fn write_histogram_values<T: Into<f64> + Copy>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        values: &[T],
+    ) -> Result<()> {
+        self.write_histogram(step, tag, &crate::histogram::Histogram::from_values(values))
     }
 
     pub fn write_image(
@@ -220,29 +445,69 @@ impl<W: std::io::Write> EventWriter<W> {
         colorspace: i32,
         encoded_image_string: Vec<u8>,
     ) -> Result<()> {
-        let image = tensorboard::summary::Image { width, height, colorspace, encoded_image_string };
-        let value = tensorboard::summary::Value {
-            node_name: "".to_string(),
-            tag: tag.to_string(),
-            metadata: None,
-            value: Some(tensorboard::summary::value::Value::Image(image)),
-        };
-        let what = tensorboard::event::What::Summary(tensorboard::Summary { value: vec![value] });
-        self.write(step, what)
+        self.write(step, what::image(tag, width, height, colorspace, encoded_image_string))
     }
 
-    pub fn write_tensor<T: TensorType>(&mut self, step: i64, tag: &str, val: Vec<T>) -> Result<()> {
-        let tensor = T::into_proto(val);
-        let value = tensorboard::summary::Value {
-            node_name: "".to_string(),
-            tag: tag.to_string(),
-            metadata: None,
-            value: Some(tensorboard::summary::value::Value::Tensor(tensor)),
-        };
-        let what = tensorboard::event::What::Summary(tensorboard::Summary { value: vec![value] });
+    /// Write some raw pixels as a PNG image, the pixels are in HWC order and there can be 1
+    /// (grayscale), 2 (grayscale and alpha), 3 (RGB), or 4 (RGBA) channels. The PNG data is
+    /// not compressed so each image takes about `width * height * channels` bytes, e.g.
+    /// around 200KB for a 256x256 RGB image, in the event file for every step it is
+    /// written at. Use `write_image` with an image encoded by a proper codec when the file
+    /// size matters.
+    pub fn write_image_pixels(
+        &mut self,
+        step: i64,
+        tag: &str,
+        width: usize,
+        height: usize,
+        channels: usize,
+        pixels: &[u8],
+    ) -> Result<()> {
+        self.write(step, what::image_pixels(tag, width, height, channels, pixels)?)
+    }
+
+    /// Write some frames as an animated GIF, which the Images dashboard plays as a video.
+    /// `frames` contains the frames one after the other, each in HWC order with 1 or 3
+    /// channels, the float values being expected to be between 0 and 1. The colors are
+    /// quantized to a palette of 256 colors shared by all the frames.
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_video<T: crate::grid::Pixel>(
+        &mut self,
+        step: i64,
+        tag: &str,
+        frames: &[T],
+        n_frames: usize,
+        height: usize,
+        width: usize,
+        channels: usize,
+        fps: f32,
+    ) -> Result<()> {
+        let what = what::video(tag, frames, n_frames, height, width, channels, fps)?;
         self.write(step, what)
     }
 
+    pub fn write_tensor<T: IntoTensor>(&mut self, step: i64, tag: &str, val: T) -> Result<()> {
+        self.write(step, what::tensor(tag, val)?)
+    }
+
+    pub fn write_session_log(
+        &mut self,
+        step: i64,
+        status: tensorboard::session_log::SessionStatus,
+        checkpoint_path: &str,
+        msg: &str,
+    ) -> Result<()> {
+        self.write(step, what::session_log(status, checkpoint_path, msg))
+    }
+
+    /// Signal that training restarted from `step`, e.g. after resuming from a checkpoint.
+    /// Readers purge the events from previous sessions that have a step greater or equal
+    /// to `step`, this is similar to the `purge_step` argument of the python writers.
+    pub fn resume(&mut self, step: i64) -> Result<()> {
+        let status = tensorboard::session_log::SessionStatus::Start;
+        self.write_session_log(step, status, "", "")
+    }
+
     pub fn flush(&mut self) -> Result<()> {
         self.writer.flush()?;
         Ok(())